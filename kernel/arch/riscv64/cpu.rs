@@ -21,3 +21,16 @@ pub fn wfi() {
         asm!("wfi");
     }
 }
+
+/// Interrupts each core in the bitmask 'cores' (by core ID).
+pub fn send_ipi(cores: usize) {
+    super::fwi::send_ipi(cores);
+}
+
+/// Acknowledges an interrupt sent by another core with 'send_ipi'.
+pub fn clear_ipi() {
+    use super::csr::sip;
+    use crate::bit::Bit;
+
+    csr!(sip = csr!(sip).set_bit(sip::SSIP, false));
+}
@@ -52,28 +52,45 @@ pub mod sie {
     pub const SSIE: usize = 1;
 }
 
+pub mod sip {
+    pub const SSIP: usize = 1;
+}
+
 pub mod mip {
+    pub const SSIP: usize = 1;
     pub const STIP: usize = 5;
     pub const MTIP: usize = 7;
 }
 
 pub mod mie {
+    pub const MSIE: usize = 3;
     pub const STIE: usize = 5;
     pub const MTIE: usize = 7;
 }
 
 pub mod cause {
     // interrupts
+    // supervisor software interrupt
+    pub const SSI: usize = 0x8000000000000001;
+    // machine software interrupt
+    pub const MSI: usize = 0x8000000000000003;
     // software timer interrupt
     pub const STI: usize = 0x8000000000000005;
+    // supervisor external interrupt
+    pub const SEI: usize = 0x8000000000000009;
     // machine timer interrupt
     pub const MTI: usize = 0x8000000000000007;
 
     // exceptions
+    pub const IMISALIGN: usize = 0;
     pub const ILLEGAL: usize = 2;
     pub const BREAKPOINT: usize = 3;
+    pub const LMISALIGN: usize = 4;
+    pub const WMISALIGN: usize = 6;
     pub const ECALL_U: usize = 8;
     pub const ECALL_S: usize = 9;
     pub const ECALL_M: usize = 11;
+    pub const IPGFLT: usize = 12;
+    pub const LPGFLT: usize = 13;
     pub const WPGFLT: usize = 15;
 }
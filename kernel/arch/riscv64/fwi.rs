@@ -4,6 +4,7 @@ pub enum Func {
     WakeCores,
     SetTimer,
     SetWatchpoint,
+    SendIpi,
 }
 
 pub fn wake_cores() {
@@ -19,3 +20,8 @@ pub fn set_watchpoint(addr: usize) {
         asm!("ecall", in("a7") Func::SetWatchpoint as u64, in ("a0") addr);
     }
 }
+
+/// Raises a supervisor software interrupt on each core in the bitmask 'cores'.
+pub fn send_ipi(cores: usize) {
+    unsafe { asm!("ecall", in("a7") Func::SendIpi as u64, in("a0") cores) };
+}
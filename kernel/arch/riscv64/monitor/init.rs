@@ -1,4 +1,4 @@
-use crate::arch::riscv64::csr::{sie, sstatus, Priv};
+use crate::arch::riscv64::csr::{mie, sie, sstatus, Priv};
 use crate::arch::riscv64::regs::{rd_gp, rd_tp};
 use crate::arch::riscv64::vm::Pagetable;
 use crate::bit::Bit;
@@ -48,9 +48,14 @@ pub fn enter_kernel() {
     csr!(mstatus = csr!(mstatus).set_bits(12, 11, Priv::S as usize));
     // Disable paging.
     csr!(satp = 0);
-    // Delegate certain interrupts and exceptions to S-mode.
-    csr!(medeleg = 0x00f0b501);
+    // Delegate certain interrupts and exceptions to S-mode (including page faults, illegal
+    // instructions and misaligned accesses, so that the kernel can handle them for user
+    // processes).
+    csr!(medeleg = 0x00f0b555);
     csr!(mideleg = 0x00001666);
+    // IPIs between kernel cores arrive as machine software interrupts, which are passed on to
+    // S-mode.
+    csr!(mie = csr!(mie).set_bit(mie::MSIE, true));
 
     // Configure the PMP to allow all accesses for S-mode. Uses a TOR region to allow R/W/X
     // starting at 0x0 and ending at 0xffff_ffff_ffff.
@@ -89,8 +94,9 @@ pub fn init_kernel(primary: bool) {
     vm_fence();
 
     // Prepare to enable interrupts (will only be enabled when sstatus is written as well).
-    csr!(sie = (1 << sie::STIE) | (1 << sie::SSIE));
+    csr!(sie = (1 << sie::STIE) | (1 << sie::SSIE) | (1 << sie::SEIE));
 
-    // Enable SUM bit so supervisor can access user-mode pages.
-    csr!(sstatus = csr!(sstatus) | (1 << sstatus::SUM))
+    // Leave the SUM bit clear so that the supervisor faults on direct accesses to user-mode pages.
+    // User memory must be accessed through the pagetable with vm::copyin/copyout.
+    csr!(sstatus = csr!(sstatus) & !(1 << sstatus::SUM))
 }
@@ -2,6 +2,7 @@ use crate::arch::riscv64::csr::{cause, mie, mip};
 use crate::arch::riscv64::fwi::Func;
 use crate::arch::riscv64::regs::Regs;
 use crate::bit::Bit;
+use crate::cpu::cpu;
 
 #[no_mangle]
 pub extern "C" fn monitortrap(regs: &mut Regs) {
@@ -16,6 +17,11 @@ pub extern "C" fn monitortrap(regs: &mut Regs) {
             csr!(mie = csr!(mie).set_bit(mie::MTIE, false));
             csr!(mip = csr!(mip).set_bit(mip::STIP, true));
         }
+        cause::MSI => {
+            // Another core sent an IPI, which is passed on to S-mode.
+            crate::board::CLINT.wr_msip(cpu().coreid, false);
+            csr!(mip = csr!(mip).set_bit(mip::SSIP, true));
+        }
         cause::BREAKPOINT => {
             panic!(
                 "[monitortrap breakpoint]: core: {}, epc: {:#x}, mtval {:#x}",
@@ -44,6 +50,9 @@ fn fwi_handler(regs: &mut Regs) {
         x if x == Func::SetTimer as usize => {
             set_timer(regs.a0 as u64);
         }
+        x if x == Func::SendIpi as usize => {
+            send_ipi(regs.a0);
+        }
         x if x == Func::SetWatchpoint as usize => {
             use super::{
                 debug,
@@ -69,6 +78,14 @@ fn wake_cores() {
     }
 }
 
+fn send_ipi(cores: usize) {
+    use crate::board::{machine, CLINT};
+
+    for coreid in (0..machine::NCORES).filter(|c| cores & (1 << c) != 0) {
+        CLINT.wr_msip(coreid, true);
+    }
+}
+
 fn set_timer(stime_value: u64) {
     use crate::board::CLINT;
 
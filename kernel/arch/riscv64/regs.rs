@@ -47,6 +47,14 @@ impl Regs {
     pub fn set_ret(&mut self, val: usize) {
         self.a0 = val;
     }
+    /// Sets register x'reg'. Writes to x0 are ignored.
+    pub fn set(&mut self, reg: usize, val: usize) {
+        assert!(reg < 32);
+        if reg != 0 {
+            // The fields are x1 to x31 in order.
+            unsafe { (self as *mut Regs as *mut usize).add(reg - 1).write(val) };
+        }
+    }
 }
 
 use core::arch::asm;
@@ -13,9 +13,12 @@ pub fn time() -> u64 {
     board::CLINT.rd_mtime()
 }
 
-pub const TIME_SLICE_US: u64 = 100_000;
+/// Allows user mode on this core to read the 'time' CSR.
+pub fn init_hart() {
+    csr!(scounteren = 0b010);
+}
 
-pub fn intr(us: u64) {
-    let next = time() + freq() / 1_000_000 * us;
+/// Requests a timer interrupt on this core once 'time' reaches 'next'.
+pub fn intr_at(next: u64) {
     fwi::set_timer(next);
 }
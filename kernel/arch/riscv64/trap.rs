@@ -35,6 +35,13 @@ pub extern "C" fn kerneltrap() {
     if scause == cause::STI {
         // Timer interrupt.
         trap::irq_handler_kern(trap::Irq::Timer);
+    } else if scause == cause::SEI {
+        // External interrupt.
+        trap::irq_handler_kern(trap::Irq::External);
+    } else if scause == cause::SSI {
+        // Interrupt from another core.
+        super::cpu::clear_ipi();
+        trap::irq_handler_kern(trap::Irq::Ipi);
     } else {
         panic!(
             "[unhandled kernel trap] core: {}, epc: {:#x}, cause: {:#x}, stval: {:#x}",
@@ -59,7 +66,9 @@ pub struct Trapframe {
 }
 
 use crate::proc::Proc;
+use crate::signal;
 use crate::syscall::syscall;
+use crate::vm::{copyin, cow_fault};
 
 extern "C" {
     // Assembly routine for returning to user-mode.
@@ -103,6 +112,30 @@ pub extern "C" fn usertrap(p: *mut Proc) {
             // Timer interrupt.
             trap::irq_handler_user(&mut p, trap::Irq::Timer);
         }
+        cause::SEI => {
+            // External interrupt.
+            trap::irq_handler_user(&mut p, trap::Irq::External);
+        }
+        cause::SSI => {
+            // Interrupt from another core.
+            super::cpu::clear_ipi();
+            trap::irq_handler_user(&mut p, trap::Irq::Ipi);
+        }
+        cause::WPGFLT if cow_fault(&mut p.data.pt, csr!(stval)) => {
+            // Store to a copy-on-write page, which now has a private writable copy.
+        }
+        cause::IPGFLT | cause::LPGFLT | cause::WPGFLT => {
+            trap::fault_handler_user(&mut p, trap::Fault::Page, csr!(sepc), csr!(stval));
+        }
+        cause::ILLEGAL if emulate_rdtime(&mut p) => {
+            // Read of the 'time' CSR, which the core does not implement.
+        }
+        cause::ILLEGAL => {
+            trap::fault_handler_user(&mut p, trap::Fault::Illegal, csr!(sepc), csr!(stval));
+        }
+        cause::IMISALIGN | cause::LMISALIGN | cause::WMISALIGN => {
+            trap::fault_handler_user(&mut p, trap::Fault::Misaligned, csr!(sepc), csr!(stval));
+        }
         _ => {
             panic!(
                 "[unhandled] usertrap: core: {}: cause: {:#x}, epc: {:#x}, tval: {:#x}",
@@ -117,12 +150,36 @@ pub extern "C" fn usertrap(p: *mut Proc) {
     unsafe { usertrapret(Box::<Proc>::into_raw(p)) };
 }
 
+// Encoding of 'rdtime' (csrrs rd, time, x0), without the destination register.
+const RDTIME: u32 = 0xc010_2073;
+const RD_MASK: u32 = 0x1f << 7;
+
+// Emulates the 'rdtime' instruction that caused an illegal instruction exception, since some cores
+// (such as the U74) have no 'time' CSR. Returns false if the instruction is something else.
+fn emulate_rdtime(p: &mut Proc) -> bool {
+    let epc = csr!(sepc);
+    let mut insn = [0u8; 4];
+    if copyin(&mut p.data.pt, &mut insn, epc).is_err() {
+        return false;
+    }
+    let insn = u32::from_le_bytes(insn);
+    if insn & !RD_MASK != RDTIME {
+        return false;
+    }
+    let rd = ((insn & RD_MASK) >> 7) as usize;
+    p.trapframe.regs.set(rd, super::timer::time() as usize);
+    p.trapframe.epc = epc + 4;
+    true
+}
+
 use super::csr::sstatus;
 use super::vm::vm_fence;
 use crate::bit::Bit;
 
-/// Return to user-mode and execute process 'p'.
+/// Return to user-mode and execute process 'p', after handling its pending signals.
 pub unsafe fn usertrapret(p: *mut Proc) -> ! {
+    signal::deliver(&mut *p);
+
     // Disable interrupts to set up user-mode.
     irq::off();
 
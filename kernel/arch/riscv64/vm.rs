@@ -207,6 +207,19 @@ impl Pagetable {
         true
     }
 
+    /// Removes the mapping for 'va' of size 'level'. Returns the physical address that was mapped,
+    /// or None if there was no such mapping. The caller is responsible for flushing the TLB.
+    pub fn unmap(&mut self, va: usize, level: PtLevel) -> Option<usize> {
+        match self.walk::<false>(va, level) {
+            Some((pte, lvl)) if lvl == level && pte.is_valid() => {
+                let pa = pte.pa();
+                pte.data = 0;
+                Some(pa)
+            }
+            _ => None,
+        }
+    }
+
     /// Maps a Giga page. Cannot fail because mapping such a page does not require sub-levels.
     pub fn map_giga(&mut self, va: usize, pa: usize, perm: u8) {
         assert!(perm != 0);
@@ -236,6 +249,12 @@ pub fn vm_fence() {
     }
 }
 
+/// Switches the current core to pagetable 'pt'.
+pub fn vm_install(pt: &Pagetable) {
+    csr!(satp = pt.satp());
+    vm_fence();
+}
+
 use crate::board::machine;
 
 /// Maps the kernel into the pagetable.
@@ -1,4 +1,7 @@
+use crate::dev::irq::plic::Plic;
 use crate::dev::irq::sfclint::SifiveClint;
+use crate::dev::rtc::goldfish::GoldfishRtc;
+use crate::dev::rtc::Rtc;
 use crate::dev::uart::virt::VirtUart;
 use crate::dev::uart::UartWrapper;
 use crate::vm::pa2ka;
@@ -9,6 +12,28 @@ pub static UART: SpinLock<UartWrapper<VirtUart>> = SpinLock::new(UartWrapper::<V
     pa2ka(0x10000000) as *mut VirtUart,
 ));
 pub static CLINT: &SifiveClint = unsafe { &*(pa2ka(0x200_0000) as *const SifiveClint) };
+pub static PLIC: &Plic = unsafe { &*(pa2ka(0xc00_0000) as *const Plic) };
+pub static RTC: Option<&dyn Rtc> = Some(unsafe { &*(pa2ka(0x10_1000) as *const GoldfishRtc) });
+
+/// PLIC interrupt source of the UART.
+pub const UART_IRQ: u32 = 10;
+
+/// Virtio-mmio transport slots (physical base address and PLIC interrupt source).
+pub const VIRTIO_MMIO: [(usize, u32); 8] = {
+    let mut slots = [(0, 0); 8];
+    let mut i = 0;
+    while i < slots.len() {
+        slots[i] = (0x1000_1000 + 0x1000 * i, 1 + i as u32);
+        i += 1;
+    }
+    slots
+};
+
+/// Returns the PLIC context for supervisor-mode interrupts on 'coreid'.
+pub const fn plic_context(coreid: usize) -> usize {
+    // Each hart has an M-mode context followed by an S-mode context.
+    2 * coreid + 1
+}
 
 pub mod machine {
     use crate::sys;
@@ -16,6 +41,9 @@ pub mod machine {
     pub const NCORES: usize = 4;
     pub const MTIME_FREQ: u64 = 3_580_000 * 2;
 
+    /// Number of PLIC interrupt sources.
+    pub const PLIC_NIRQ: usize = 127;
+
     pub struct MemRange {
         pub start: usize,
         pub size: usize,
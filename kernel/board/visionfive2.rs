@@ -1,4 +1,6 @@
+use crate::dev::irq::plic::Plic;
 use crate::dev::irq::sfclint::SifiveClint;
+use crate::dev::rtc::Rtc;
 use crate::dev::uart::dwapb::DwApbUart;
 use crate::dev::uart::UartWrapper;
 use crate::vm::pa2ka;
@@ -9,6 +11,22 @@ pub static UART: SpinLock<UartWrapper<DwApbUart>> = SpinLock::new(UartWrapper::<
     pa2ka(0x10000000) as *mut DwApbUart,
 ));
 pub static CLINT: &SifiveClint = unsafe { &*(pa2ka(0x200_0000) as *const SifiveClint) };
+pub static PLIC: &Plic = unsafe { &*(pa2ka(0xc00_0000) as *const Plic) };
+// The real-time clock on the I2C bus is not supported.
+pub static RTC: Option<&dyn Rtc> = None;
+
+/// PLIC interrupt source of the UART.
+pub const UART_IRQ: u32 = 32;
+
+/// Virtio-mmio transport slots (physical base address and PLIC interrupt source).
+pub const VIRTIO_MMIO: [(usize, u32); 0] = [];
+
+/// Returns the PLIC context for supervisor-mode interrupts on 'coreid'.
+pub const fn plic_context(coreid: usize) -> usize {
+    // Hart 0 (the S7 monitor core) only has an M-mode context; every other hart has an M-mode
+    // context followed by an S-mode context.
+    2 * coreid
+}
 
 pub mod machine {
     use crate::sys;
@@ -17,6 +35,9 @@ pub mod machine {
     pub const NCORES: usize = 5;
     pub const MTIME_FREQ: u64 = 4_000_000;
 
+    /// Number of PLIC interrupt sources.
+    pub const PLIC_NIRQ: usize = 136;
+
     pub struct MemRange {
         pub start: usize,
         pub size: usize,
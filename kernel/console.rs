@@ -0,0 +1,134 @@
+use crate::board::{self, UART};
+use crate::dev::irq;
+use crate::dev::uart::Uart;
+use crate::file::File;
+use crate::proc::{self, Proc};
+use crate::schedule::{Queue, QueueType};
+use crate::sync::spinlock::SpinLock;
+use crate::timer;
+
+const INPUT_BUF: usize = 128;
+
+const BACKSPACE: u8 = 0x08;
+const DELETE: u8 = 0x7f;
+const CTRL_C: u8 = 0x03;
+const CTRL_D: u8 = 0x04;
+const CTRL_P: u8 = 0x10;
+
+// Console input, edited a line at a time. Characters in [r, w) are complete lines that are ready
+// to be read, and characters in [w, e) belong to the line that is still being edited. Indices
+// increase monotonically and wrap around the buffer.
+struct Input {
+    buf: [u8; INPUT_BUF],
+    r: usize,
+    w: usize,
+    e: usize,
+}
+
+static INPUT: SpinLock<Input> = SpinLock::new(Input {
+    buf: [0; INPUT_BUF],
+    r: 0,
+    w: 0,
+    e: 0,
+});
+
+// Processes waiting for a line of input. Lock order: INPUT, then INPUT_QUEUE.
+static INPUT_QUEUE: SpinLock<Queue> = SpinLock::new(Queue::new(QueueType::Console));
+
+/// Enables UART receive interrupts. Must be called by the primary core during boot.
+pub fn init() {
+    irq::register(board::UART_IRQ, |_| intr());
+    UART.lock().set_rx_intr(true);
+}
+
+fn echo(b: &[u8]) {
+    UART.lock().write_bytes(b);
+}
+
+/// Console interrupt handler, called when the UART has received data. Performs line editing and
+/// wakes up readers once a line is complete.
+pub fn intr() {
+    let mut input = INPUT.lock();
+    loop {
+        let c = {
+            let mut uart = UART.lock();
+            if uart.rx_empty() {
+                break;
+            }
+            uart.rx()
+        };
+        match c {
+            BACKSPACE | DELETE => {
+                if input.e != input.w {
+                    input.e -= 1;
+                    echo(b"\x08 \x08");
+                }
+            }
+            CTRL_P => {
+                // Print a listing of all processes, and how busy the cores are.
+                proc::dump();
+                timer::dump();
+            }
+            CTRL_C => {
+                // Discard the line being edited.
+                input.e = input.w;
+                echo(b"^C\n");
+            }
+            _ if input.e - input.r < INPUT_BUF => {
+                let c = if c == b'\r' { b'\n' } else { c };
+                if c != CTRL_D {
+                    echo(&[c]);
+                }
+                let e = input.e;
+                input.buf[e % INPUT_BUF] = c;
+                input.e += 1;
+                if c == b'\n' || c == CTRL_D || input.e - input.r == INPUT_BUF {
+                    // A line is complete (or the buffer is full), so make it available to
+                    // readers.
+                    input.w = input.e;
+                    INPUT_QUEUE.lock().wake_all();
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The console file, which reads lines from the console input and writes to the board UART.
+pub struct Console;
+
+impl File for Console {
+    /// Reads at most one line, blocking until a line is available. A ^D at the start of a line
+    /// signals end of file. Fails with INTR if a signal arrives while waiting.
+    fn read(&self, p: &mut Proc, buf: &mut [u8]) -> Result<usize, isize> {
+        let mut input = INPUT.lock();
+        while input.r == input.w {
+            p.block_intr(&INPUT_QUEUE, input)?;
+            input = INPUT.lock();
+        }
+
+        let mut n = 0;
+        while n < buf.len() && input.r != input.w {
+            let c = input.buf[input.r % INPUT_BUF];
+            input.r += 1;
+            if c == CTRL_D {
+                if n > 0 {
+                    // Leave the ^D so that the next read returns 0.
+                    input.r -= 1;
+                }
+                break;
+            }
+            buf[n] = c;
+            n += 1;
+            if c == b'\n' {
+                break;
+            }
+        }
+        Ok(n)
+    }
+
+    fn write(&self, _p: &mut Proc, buf: &[u8]) -> Result<usize, isize> {
+        UART.lock().write_bytes(buf);
+        Ok(buf.len())
+    }
+}
@@ -1,5 +1,10 @@
 use crate::arch::cpu::rd_cpu;
+use crate::arch::regs::Context;
 use crate::board;
+use crate::proc::Proc;
+use crate::timer::ClockEvent;
+
+use core::ptr::null_mut;
 
 // A global that becomes true when the secondary cores boot up.
 static mut BOOTED_ALL: bool = false;
@@ -19,17 +24,28 @@ pub fn booted_all() -> bool {
     unsafe { BOOTED_ALL }
 }
 
-#[derive(Copy, Clone)]
 pub struct Cpu {
     pub coreid: usize,
     pub primary: bool,
     pub stack: usize,
+    /// Context of this core's scheduler (registers and pagetable), which processes switch back
+    /// to when they yield.
+    pub context: Context,
+    /// Process currently running on this core, or null while the scheduler is running.
+    pub proc: *mut Proc,
+    /// This core's timer.
+    pub clock: ClockEvent,
 }
 
-static mut CPUS: [Cpu; board::machine::NCORES] = [Cpu {
-    coreid: 0,
-    primary: false,
-    stack: 0,
+static mut CPUS: [Cpu; board::machine::NCORES] = [const {
+    Cpu {
+        coreid: 0,
+        primary: false,
+        stack: 0,
+        context: Context::zero(),
+        proc: null_mut(),
+        clock: ClockEvent::new(),
+    }
 }; board::machine::NCORES];
 
 // Initializes the core-local CPU state for coreid (the current core).
@@ -0,0 +1,36 @@
+use crate::sync::spinlock::SpinLock;
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Size of a disk sector in bytes. Block devices are addressed in sectors.
+pub const SECTOR_SIZE: usize = 512;
+
+/// A block storage device. Transfers block the calling process until they complete (or spin if
+/// there is no calling process, such as during boot).
+pub trait BlockDevice: Send + Sync {
+    /// Returns the size of the device in sectors.
+    fn nsectors(&self) -> u64;
+
+    /// Reads 'buf.len() / SECTOR_SIZE' sectors starting at 'sector' into 'buf'. The length of
+    /// 'buf' must be a multiple of SECTOR_SIZE.
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), isize>;
+
+    /// Writes 'buf' to the device starting at 'sector'. The length of 'buf' must be a multiple of
+    /// SECTOR_SIZE.
+    fn write(&self, sector: u64, buf: &[u8]) -> Result<(), isize>;
+}
+
+static DEVICES: SpinLock<Vec<Arc<dyn BlockDevice>>> = SpinLock::new(Vec::new());
+
+/// Registers a block device and returns its device number.
+pub fn register(dev: Arc<dyn BlockDevice>) -> usize {
+    let mut devs = DEVICES.lock();
+    devs.push(dev);
+    devs.len() - 1
+}
+
+/// Returns the block device with device number 'n'.
+pub fn get(n: usize) -> Option<Arc<dyn BlockDevice>> {
+    DEVICES.lock().get(n).cloned()
+}
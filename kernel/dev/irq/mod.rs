@@ -1 +1,75 @@
+pub mod plic;
 pub mod sfclint;
+
+use crate::board::{self, machine};
+use crate::cpu::cpu;
+use crate::sync::spinlock::SpinLock;
+
+/// Handler for an external interrupt, called with the interrupt source number.
+pub type Handler = fn(irq: u32);
+
+struct Handlers {
+    // Indexed by interrupt source (source 0 does not exist).
+    handlers: [Option<Handler>; machine::PLIC_NIRQ + 1],
+    // Cores that have enabled external interrupts.
+    online: [bool; machine::NCORES],
+}
+
+static HANDLERS: SpinLock<Handlers> = SpinLock::new(Handlers {
+    handlers: [None; machine::PLIC_NIRQ + 1],
+    online: [false; machine::NCORES],
+});
+
+/// Enables external interrupts on the current core. Every interrupt source with a registered
+/// handler is routed to this core (whichever core claims an interrupt first handles it).
+pub fn init_hart() {
+    let mut h = HANDLERS.lock();
+    let coreid = cpu().coreid;
+    let ctx = board::plic_context(coreid);
+    for irq in 1..h.handlers.len() {
+        board::PLIC.enable(ctx, irq as u32, h.handlers[irq].is_some());
+    }
+    board::PLIC.set_threshold(ctx, 0);
+    h.online[coreid] = true;
+}
+
+/// Registers 'handler' for the interrupt source 'irq' and enables the source on all cores.
+pub fn register(irq: u32, handler: Handler) {
+    assert!(irq != 0 && irq as usize <= machine::PLIC_NIRQ);
+    let mut h = HANDLERS.lock();
+    assert!(
+        h.handlers[irq as usize].is_none(),
+        "irq {} already registered",
+        irq
+    );
+    h.handlers[irq as usize] = Some(handler);
+    board::PLIC.set_priority(irq, 1);
+    for coreid in 0..machine::NCORES {
+        if h.online[coreid] {
+            board::PLIC.enable(board::plic_context(coreid), irq, true);
+        }
+    }
+}
+
+/// Claims and handles all pending external interrupts for the current core.
+pub fn dispatch() {
+    let ctx = board::plic_context(cpu().coreid);
+    loop {
+        let irq = board::PLIC.claim(ctx);
+        if irq == 0 {
+            break;
+        }
+        // Don't hold the lock while the handler runs.
+        let handler = HANDLERS
+            .lock()
+            .handlers
+            .get(irq as usize)
+            .copied()
+            .flatten();
+        match handler {
+            Some(handler) => handler(irq),
+            None => println!("unexpected external interrupt {}", irq),
+        }
+        board::PLIC.complete(ctx, irq);
+    }
+}
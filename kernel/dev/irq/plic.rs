@@ -0,0 +1,42 @@
+/// SiFive platform-level interrupt controller (PLIC). Interrupt sources are routed to contexts
+/// (a privilege mode on a hart) that have them enabled and whose priority threshold they exceed.
+pub struct Plic {}
+
+impl Plic {
+    fn reg(&self, off: usize) -> *mut u32 {
+        let base = self as *const _ as *mut u8;
+        unsafe { base.add(off) as *mut u32 }
+    }
+
+    /// Sets the priority of interrupt source 'irq'. A priority of 0 disables the source.
+    pub fn set_priority(&self, irq: u32, prio: u32) {
+        unsafe { self.reg(4 * irq as usize).write_volatile(prio) };
+    }
+
+    /// Enables or disables delivery of interrupt source 'irq' to context 'ctx'.
+    pub fn enable(&self, ctx: usize, irq: u32, en: bool) {
+        let reg = self.reg(0x2000 + 0x80 * ctx + 4 * (irq as usize / 32));
+        let bit = 1 << (irq % 32);
+        unsafe {
+            let val = reg.read_volatile();
+            reg.write_volatile(if en { val | bit } else { val & !bit });
+        }
+    }
+
+    /// Sets the priority threshold of context 'ctx'. Only sources with a higher priority are
+    /// delivered.
+    pub fn set_threshold(&self, ctx: usize, threshold: u32) {
+        unsafe { self.reg(0x20_0000 + 0x1000 * ctx).write_volatile(threshold) };
+    }
+
+    /// Claims the highest-priority pending interrupt for context 'ctx'. Returns 0 if no interrupt
+    /// is pending.
+    pub fn claim(&self, ctx: usize) -> u32 {
+        unsafe { self.reg(0x20_0004 + 0x1000 * ctx).read_volatile() }
+    }
+
+    /// Signals that the interrupt 'irq' claimed by context 'ctx' has been handled.
+    pub fn complete(&self, ctx: usize, irq: u32) {
+        unsafe { self.reg(0x20_0004 + 0x1000 * ctx).write_volatile(irq) };
+    }
+}
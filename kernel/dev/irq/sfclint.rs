@@ -11,6 +11,15 @@ impl SifiveClint {
         }
     }
 
+    /// Sets or clears the pending machine software interrupt of 'coreid'.
+    pub fn wr_msip(&self, coreid: usize, pending: bool) {
+        let base = self as *const _ as *const u8;
+        unsafe {
+            let msip = base.add(4 * coreid) as *mut u32;
+            msip.write_volatile(pending as u32);
+        }
+    }
+
     pub fn wr_mtimecmp(&self, val: u64) {
         let base = self as *const _ as *const u8;
         unsafe {
@@ -1,2 +1,5 @@
+pub mod block;
 pub mod irq;
+pub mod rtc;
 pub mod uart;
+pub mod virtio;
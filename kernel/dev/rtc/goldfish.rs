@@ -0,0 +1,24 @@
+use crate::dev::rtc::Rtc;
+
+// Register offsets. Reading the low half of the time latches the high half, so the low half must
+// be read first.
+const TIME_LOW: usize = 0x00;
+const TIME_HIGH: usize = 0x04;
+
+/// Goldfish real-time clock, as emulated by QEMU's virt machine.
+pub struct GoldfishRtc {}
+
+impl GoldfishRtc {
+    fn rd32(&self, off: usize) -> u32 {
+        let base = self as *const _ as *const u8;
+        unsafe { (base.add(off) as *const u32).read_volatile() }
+    }
+}
+
+impl Rtc for GoldfishRtc {
+    fn read(&self) -> u64 {
+        let low = self.rd32(TIME_LOW) as u64;
+        let high = self.rd32(TIME_HIGH) as u64;
+        (high << 32) | low
+    }
+}
@@ -0,0 +1,7 @@
+pub mod goldfish;
+
+/// A real-time clock, which keeps the date and time while the machine is off.
+pub trait Rtc: Sync {
+    /// Returns the current time in nanoseconds since the Unix epoch.
+    fn read(&self) -> u64;
+}
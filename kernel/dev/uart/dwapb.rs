@@ -4,7 +4,8 @@ use crate::dev::uart::Uart;
 #[repr(C)]
 pub struct DwApbUart {
     thr_rbr: u32,
-    _pad: [u32; 4],
+    ier: u32,
+    _pad: [u32; 3],
     lsr: u32,
 }
 
@@ -13,6 +14,10 @@ mod lsr {
     pub const THR_EMPTY: usize = 5;
 }
 
+mod ier {
+    pub const RX_AVAIL: usize = 0;
+}
+
 impl DwApbUart {
     fn thr_empty(&mut self) -> bool {
         let lsr = unsafe { (&mut self.lsr as *mut u32).read_volatile() };
@@ -47,4 +52,11 @@ impl Uart for DwApbUart {
         let lsr = unsafe { (&mut self.lsr as *mut u32).read_volatile() };
         !lsr.bit(lsr::DATA_READY)
     }
+
+    fn set_rx_intr(&mut self, enable: bool) {
+        let ier = 0u32.set_bit(ier::RX_AVAIL, enable);
+        unsafe {
+            (&mut self.ier as *mut u32).write_volatile(ier);
+        }
+    }
 }
@@ -9,6 +9,8 @@ pub trait Uart {
     fn tx_flush(&mut self);
     fn rx(&mut self) -> u8;
     fn rx_empty(&mut self) -> bool;
+    /// Enables or disables the interrupt raised when received data is available.
+    fn set_rx_intr(&mut self, enable: bool);
 }
 
 pub struct UartWrapper<T: Uart> {
@@ -1,8 +1,23 @@
+use crate::bit::Bit;
 use crate::dev::uart::Uart;
 
+// NS16550A-compatible UART with byte-wide registers.
 #[repr(C)]
 pub struct VirtUart {
-    thr: u32,
+    thr_rbr: u8,
+    ier: u8,
+    iir_fcr: u8,
+    lcr: u8,
+    mcr: u8,
+    lsr: u8,
+}
+
+mod lsr {
+    pub const DATA_READY: usize = 0;
+}
+
+mod ier {
+    pub const RX_AVAIL: usize = 0;
 }
 
 impl VirtUart {}
@@ -12,17 +27,27 @@ impl Uart for VirtUart {
 
     fn tx(&mut self, b: u8) {
         unsafe {
-            (&mut self.thr as *mut u32).write_volatile(b as u32);
+            (&mut self.thr_rbr as *mut u8).write_volatile(b);
         }
     }
 
     fn tx_flush(&mut self) {}
 
     fn rx(&mut self) -> u8 {
-        0
+        // Wait until there is data available.
+        while self.rx_empty() {}
+        unsafe { (&mut self.thr_rbr as *mut u8).read_volatile() }
     }
 
     fn rx_empty(&mut self) -> bool {
-        true
+        let lsr = unsafe { (&mut self.lsr as *mut u8).read_volatile() };
+        !lsr.bit(lsr::DATA_READY)
+    }
+
+    fn set_rx_intr(&mut self, enable: bool) {
+        let ier = 0u8.set_bit(ier::RX_AVAIL, enable);
+        unsafe {
+            (&mut self.ier as *mut u8).write_volatile(ier);
+        }
     }
 }
@@ -0,0 +1,201 @@
+use super::queue::{Buf, VirtQueue, NUM};
+use super::Mmio;
+use crate::board;
+use crate::dev::block::{self, BlockDevice, SECTOR_SIZE};
+use crate::dev::irq;
+use crate::err;
+use crate::proc::Proc;
+use crate::schedule::{Queue, QueueType};
+use crate::sync::spinlock::{Guard, SpinLock};
+
+use alloc::sync::Arc;
+use core::mem::size_of;
+
+mod req {
+    pub const IN: u32 = 0;
+    pub const OUT: u32 = 1;
+}
+
+// Status written by the device when a request completes.
+const S_OK: u8 = 0;
+
+// Request header, which is read by the device.
+#[repr(C)]
+struct ReqHeader {
+    type_: u32,
+    reserved: u32,
+    sector: u64,
+}
+
+struct State {
+    vq: VirtQueue,
+    // Completion flags of in-flight requests, indexed by head descriptor.
+    done: [bool; NUM],
+    // Processes waiting for a request to complete or for free descriptors.
+    waiting: Queue,
+}
+
+/// A virtio block device.
+pub struct VirtioBlk {
+    mmio: Mmio,
+    capacity: u64,
+    state: SpinLock<State>,
+}
+
+// Devices indexed by virtio-mmio slot, used to find the device that raised an interrupt.
+static DEVICES: SpinLock<[Option<Arc<VirtioBlk>>; board::VIRTIO_MMIO.len()]> =
+    SpinLock::new([const { None }; board::VIRTIO_MMIO.len()]);
+
+/// Initializes the block device in virtio-mmio 'slot' and registers it as a block device.
+pub fn attach(slot: usize, mmio: Mmio, irq: u32) {
+    // No optional features are needed.
+    if !mmio.init(0) {
+        println!("virtio-blk: slot {}: feature negotiation failed", slot);
+        return;
+    }
+    let vq = match VirtQueue::new() {
+        None => {
+            println!("virtio-blk: slot {}: out of memory", slot);
+            return;
+        }
+        Some(vq) => vq,
+    };
+    if !mmio.setup_queue(0, &vq) {
+        println!("virtio-blk: slot {}: queue setup failed", slot);
+        return;
+    }
+    mmio.driver_ok();
+
+    let capacity = mmio.config(0) as u64 | (mmio.config(4) as u64) << 32;
+    let dev = Arc::new(VirtioBlk {
+        mmio,
+        capacity,
+        state: SpinLock::new(State {
+            vq,
+            done: [false; NUM],
+            waiting: Queue::new(QueueType::Disk),
+        }),
+    });
+    DEVICES.lock()[slot] = Some(dev.clone());
+    irq::register(irq, intr);
+    let n = block::register(dev);
+    println!("virtio-blk: disk {}: {} sectors", n, capacity);
+}
+
+fn intr(irq: u32) {
+    let slot = board::VIRTIO_MMIO
+        .iter()
+        .position(|&(_, i)| i == irq)
+        .expect("virtio-blk: unknown irq");
+    let dev = DEVICES.lock()[slot].clone();
+    if let Some(dev) = dev {
+        dev.mmio.ack_intr();
+        dev.complete(&mut dev.state.lock());
+    }
+}
+
+impl VirtioBlk {
+    // Marks all requests that the device has finished as done, and wakes up waiting processes.
+    fn complete(&self, st: &mut State) {
+        let mut any = false;
+        while let Some(head) = st.vq.pop_used() {
+            st.done[head as usize] = true;
+            any = true;
+        }
+        if any {
+            st.waiting.wake_all();
+        }
+    }
+
+    // Blocks the current process on the wait queue, or polls the device if there is no process.
+    // Returns the relocked state.
+    fn wait<'a>(&'a self, mut st: Guard<'a, State>) -> Guard<'a, State> {
+        match unsafe { Proc::current() } {
+            Some(p) => {
+                p.block(&mut st.waiting);
+                drop(st);
+                p.yield_();
+                self.state.lock()
+            }
+            None => {
+                core::hint::spin_loop();
+                self.complete(&mut st);
+                st
+            }
+        }
+    }
+
+    fn rw(&self, sector: u64, addr: usize, len: usize, write: bool) -> Result<(), isize> {
+        assert!(len.is_multiple_of(SECTOR_SIZE));
+        if sector
+            .checked_add((len / SECTOR_SIZE) as u64)
+            .is_none_or(|end| end > self.capacity)
+        {
+            return Err(err::IO);
+        }
+
+        // The header and status stay on this kernel stack, which remains valid until the request
+        // completes since we wait for it below.
+        let header = ReqHeader {
+            type_: if write { req::OUT } else { req::IN },
+            reserved: 0,
+            sector,
+        };
+        let mut status: u8 = 0xff;
+        let bufs = [
+            Buf {
+                addr: &header as *const _ as usize,
+                len: size_of::<ReqHeader>(),
+                write: false,
+            },
+            Buf {
+                addr,
+                len,
+                write: !write,
+            },
+            Buf {
+                addr: &raw mut status as usize,
+                len: 1,
+                write: true,
+            },
+        ];
+
+        let mut st = self.state.lock();
+        let head = loop {
+            if let Some(head) = st.vq.submit(&bufs) {
+                break head;
+            }
+            st = self.wait(st);
+        };
+        self.mmio.notify(0);
+
+        while !st.done[head as usize] {
+            st = self.wait(st);
+        }
+        st.done[head as usize] = false;
+        st.vq.free(head);
+        // Others may be waiting for free descriptors.
+        st.waiting.wake_all();
+        drop(st);
+
+        let status = unsafe { (&raw const status).read_volatile() };
+        if status != S_OK {
+            return Err(err::IO);
+        }
+        Ok(())
+    }
+}
+
+impl BlockDevice for VirtioBlk {
+    fn nsectors(&self) -> u64 {
+        self.capacity
+    }
+
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), isize> {
+        self.rw(sector, buf.as_mut_ptr() as usize, buf.len(), false)
+    }
+
+    fn write(&self, sector: u64, buf: &[u8]) -> Result<(), isize> {
+        self.rw(sector, buf.as_ptr() as usize, buf.len(), true)
+    }
+}
@@ -0,0 +1,158 @@
+pub mod blk;
+pub mod queue;
+
+use crate::board;
+use crate::vm::pa2ka;
+
+// Virtio over MMIO (virtio specification version 1.1, section 4.2). Only the modern (version 2)
+// register layout is supported, so QEMU must be run with virtio-mmio.force-legacy=false.
+
+const MAGIC: u32 = 0x7472_6976; // "virt" in little endian
+const VERSION: u32 = 2;
+
+mod reg {
+    pub const MAGIC: usize = 0x000;
+    pub const VERSION: usize = 0x004;
+    pub const DEVICE_ID: usize = 0x008;
+    pub const DEVICE_FEATURES: usize = 0x010;
+    pub const DEVICE_FEATURES_SEL: usize = 0x014;
+    pub const DRIVER_FEATURES: usize = 0x020;
+    pub const DRIVER_FEATURES_SEL: usize = 0x024;
+    pub const QUEUE_SEL: usize = 0x030;
+    pub const QUEUE_NUM_MAX: usize = 0x034;
+    pub const QUEUE_NUM: usize = 0x038;
+    pub const QUEUE_READY: usize = 0x044;
+    pub const QUEUE_NOTIFY: usize = 0x050;
+    pub const INTERRUPT_STATUS: usize = 0x060;
+    pub const INTERRUPT_ACK: usize = 0x064;
+    pub const STATUS: usize = 0x070;
+    pub const QUEUE_DESC_LOW: usize = 0x080;
+    pub const QUEUE_DESC_HIGH: usize = 0x084;
+    pub const QUEUE_DRIVER_LOW: usize = 0x090;
+    pub const QUEUE_DRIVER_HIGH: usize = 0x094;
+    pub const QUEUE_DEVICE_LOW: usize = 0x0a0;
+    pub const QUEUE_DEVICE_HIGH: usize = 0x0a4;
+    pub const CONFIG: usize = 0x100;
+}
+
+mod status {
+    pub const ACKNOWLEDGE: u32 = 1;
+    pub const DRIVER: u32 = 2;
+    pub const DRIVER_OK: u32 = 4;
+    pub const FEATURES_OK: u32 = 8;
+    pub const FAILED: u32 = 128;
+}
+
+// Feature bit that must be negotiated by drivers of modern devices.
+const F_VERSION_1: u64 = 1 << 32;
+
+mod id {
+    pub const BLOCK: u32 = 2;
+}
+
+/// Register window of a virtio-mmio device.
+#[derive(Copy, Clone)]
+pub struct Mmio {
+    base: usize,
+}
+
+impl Mmio {
+    fn read(&self, off: usize) -> u32 {
+        unsafe { ((self.base + off) as *const u32).read_volatile() }
+    }
+
+    fn write(&self, off: usize, val: u32) {
+        unsafe { ((self.base + off) as *mut u32).write_volatile(val) }
+    }
+
+    /// Reads a 32-bit word from the device-specific configuration space.
+    pub fn config(&self, off: usize) -> u32 {
+        self.read(reg::CONFIG + off)
+    }
+
+    /// Initializes the device, accepting the features in 'wanted' that the device offers (as well
+    /// as VERSION_1). Returns false if the device rejects the features.
+    pub fn init(&self, wanted: u64) -> bool {
+        self.write(reg::STATUS, 0);
+        let mut st = status::ACKNOWLEDGE | status::DRIVER;
+        self.write(reg::STATUS, st);
+
+        self.write(reg::DEVICE_FEATURES_SEL, 0);
+        let mut features = self.read(reg::DEVICE_FEATURES) as u64;
+        self.write(reg::DEVICE_FEATURES_SEL, 1);
+        features |= (self.read(reg::DEVICE_FEATURES) as u64) << 32;
+        features &= wanted | F_VERSION_1;
+
+        self.write(reg::DRIVER_FEATURES_SEL, 0);
+        self.write(reg::DRIVER_FEATURES, features as u32);
+        self.write(reg::DRIVER_FEATURES_SEL, 1);
+        self.write(reg::DRIVER_FEATURES, (features >> 32) as u32);
+
+        st |= status::FEATURES_OK;
+        self.write(reg::STATUS, st);
+        if self.read(reg::STATUS) & status::FEATURES_OK == 0 {
+            self.write(reg::STATUS, st | status::FAILED);
+            return false;
+        }
+        true
+    }
+
+    /// Configures virtqueue 'idx' to use 'q'. Returns false if the device does not support a
+    /// queue of that size.
+    pub fn setup_queue(&self, idx: u32, q: &queue::VirtQueue) -> bool {
+        self.write(reg::QUEUE_SEL, idx);
+        if self.read(reg::QUEUE_READY) != 0 {
+            return false;
+        }
+        let max = self.read(reg::QUEUE_NUM_MAX);
+        if max < queue::NUM as u32 {
+            return false;
+        }
+        self.write(reg::QUEUE_NUM, queue::NUM as u32);
+        let (desc, driver, device) = q.addrs();
+        self.write(reg::QUEUE_DESC_LOW, desc as u32);
+        self.write(reg::QUEUE_DESC_HIGH, (desc >> 32) as u32);
+        self.write(reg::QUEUE_DRIVER_LOW, driver as u32);
+        self.write(reg::QUEUE_DRIVER_HIGH, (driver >> 32) as u32);
+        self.write(reg::QUEUE_DEVICE_LOW, device as u32);
+        self.write(reg::QUEUE_DEVICE_HIGH, (device >> 32) as u32);
+        self.write(reg::QUEUE_READY, 1);
+        true
+    }
+
+    /// Tells the device that the driver is ready.
+    pub fn driver_ok(&self) {
+        let st = self.read(reg::STATUS);
+        self.write(reg::STATUS, st | status::DRIVER_OK);
+    }
+
+    /// Notifies the device that new buffers are available in virtqueue 'idx'.
+    pub fn notify(&self, idx: u32) {
+        self.write(reg::QUEUE_NOTIFY, idx);
+    }
+
+    /// Acknowledges all pending interrupts.
+    pub fn ack_intr(&self) {
+        let st = self.read(reg::INTERRUPT_STATUS);
+        self.write(reg::INTERRUPT_ACK, st & 0x3);
+    }
+}
+
+/// Scans the board's virtio-mmio slots and attaches drivers for the devices that are found. Must
+/// be called by the primary core during boot.
+pub fn probe() {
+    // Slots are scanned from last to first, so that devices are numbered in the same order as
+    // QEMU's virtio-mmio buses (bus 0 is the last slot).
+    for (slot, &(base, irq)) in board::VIRTIO_MMIO.iter().enumerate().rev() {
+        let mmio = Mmio { base: pa2ka(base) };
+        if mmio.read(reg::MAGIC) != MAGIC || mmio.read(reg::VERSION) != VERSION {
+            continue;
+        }
+        match mmio.read(reg::DEVICE_ID) {
+            id::BLOCK => blk::attach(slot, mmio, irq),
+            // Empty slot.
+            0 => {}
+            dev => println!("virtio: ignoring device {} at {:#x}", dev, base),
+        }
+    }
+}
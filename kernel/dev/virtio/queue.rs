@@ -0,0 +1,159 @@
+use crate::kalloc::{zalloc, Zero};
+use crate::sync::fence::io_fence;
+use crate::vm::ka2pa;
+
+use alloc::boxed::Box;
+
+/// Number of descriptors in a virtqueue.
+pub const NUM: usize = 8;
+
+mod flags {
+    pub const NEXT: u16 = 1;
+    pub const WRITE: u16 = 2;
+}
+
+#[repr(C, align(16))]
+struct Desc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16,
+}
+
+#[repr(C, align(2))]
+struct Avail {
+    flags: u16,
+    idx: u16,
+    ring: [u16; NUM],
+    used_event: u16,
+}
+
+#[repr(C)]
+struct UsedElem {
+    id: u32,
+    len: u32,
+}
+
+#[repr(C, align(4))]
+struct Used {
+    flags: u16,
+    idx: u16,
+    ring: [UsedElem; NUM],
+    avail_event: u16,
+}
+
+struct DescTable([Desc; NUM]);
+
+impl Zero for DescTable {}
+impl Zero for Avail {}
+impl Zero for Used {}
+
+/// A buffer that is part of a request, identified by its kernel address.
+pub struct Buf {
+    pub addr: usize,
+    pub len: usize,
+    /// True if the device writes to this buffer (otherwise it reads from it).
+    pub write: bool,
+}
+
+/// A split virtqueue, made up of the descriptor table, the available ring (driver area) and the
+/// used ring (device area).
+pub struct VirtQueue {
+    desc: Box<DescTable>,
+    avail: Box<Avail>,
+    used: Box<Used>,
+    free: [bool; NUM],
+    // Next entry of the used ring that the driver has not processed yet.
+    used_idx: u16,
+}
+
+impl VirtQueue {
+    pub fn new() -> Option<Self> {
+        Some(Self {
+            desc: zalloc().ok()?,
+            avail: zalloc().ok()?,
+            used: zalloc().ok()?,
+            free: [true; NUM],
+            used_idx: 0,
+        })
+    }
+
+    /// Returns the physical addresses of the descriptor table, available ring and used ring.
+    pub fn addrs(&self) -> (usize, usize, usize) {
+        (
+            ka2pa(&*self.desc as *const _ as usize),
+            ka2pa(&*self.avail as *const _ as usize),
+            ka2pa(&*self.used as *const _ as usize),
+        )
+    }
+
+    /// Places a chain of descriptors for 'bufs' on the available ring. Returns the index of the
+    /// head descriptor, which identifies the request when it completes, or None if there are not
+    /// enough free descriptors. The caller must then notify the device.
+    pub fn submit(&mut self, bufs: &[Buf]) -> Option<u16> {
+        let mut idx = [0u16; NUM];
+        let mut n = 0;
+        for (i, free) in self.free.iter().enumerate() {
+            if n == bufs.len() {
+                break;
+            }
+            if *free {
+                idx[n] = i as u16;
+                n += 1;
+            }
+        }
+        if n < bufs.len() {
+            return None;
+        }
+
+        for (i, buf) in bufs.iter().enumerate() {
+            let d = idx[i];
+            self.free[d as usize] = false;
+            let desc = &mut self.desc.0[d as usize];
+            desc.addr = ka2pa(buf.addr) as u64;
+            desc.len = buf.len as u32;
+            desc.flags = if buf.write { flags::WRITE } else { 0 };
+            if i + 1 < bufs.len() {
+                desc.flags |= flags::NEXT;
+                desc.next = idx[i + 1];
+            } else {
+                desc.next = 0;
+            }
+        }
+
+        let head = idx[0];
+        let avail = &mut *self.avail;
+        avail.ring[avail.idx as usize % NUM] = head;
+        // The descriptors and ring entry must be visible before the device sees the new index.
+        io_fence();
+        unsafe { (&mut avail.idx as *mut u16).write_volatile(avail.idx.wrapping_add(1)) };
+        io_fence();
+        Some(head)
+    }
+
+    /// Returns the head descriptor of the next request that the device has completed.
+    pub fn pop_used(&mut self) -> Option<u16> {
+        io_fence();
+        let idx = unsafe { (&self.used.idx as *const u16).read_volatile() };
+        if self.used_idx == idx {
+            return None;
+        }
+        let id = self.used.ring[self.used_idx as usize % NUM].id;
+        self.used_idx = self.used_idx.wrapping_add(1);
+        Some(id as u16)
+    }
+
+    /// Frees the chain of descriptors starting at 'head'.
+    pub fn free(&mut self, head: u16) {
+        let mut d = head as usize;
+        loop {
+            assert!(!self.free[d]);
+            self.free[d] = true;
+            let desc = &self.desc.0[d];
+            if desc.flags & flags::NEXT == 0 {
+                break;
+            }
+            d = desc.next as usize;
+        }
+    }
+}
@@ -1,9 +1,13 @@
 use crate::arch::vm::Pagetable;
 use crate::kalloc::kallocpage;
+use crate::proc::Proc;
 use crate::sys;
 use crate::vm::{perm, PageMap};
 
+use core::mem::size_of;
+
 const MAGIC: u32 = 0x464C457F; // "\x7ELF" in little endian
+const WIDTH_64: u8 = 2;
 const PROG_LOAD: u32 = 1;
 
 #[repr(C)]
@@ -39,20 +43,31 @@ struct ProgHeader64 {
 }
 
 /// Loads the given ELF binary into the given pagetable. If successful, returns the ELF entrypoint
-/// and brkpoint (max data address). The ELF binary must be aligned to an 8-byte boundary.
+/// and brkpoint (max data address). The ELF binary must be aligned to an 8-byte boundary, otherwise
+/// None is returned.
 pub fn load64(pt: &mut Pagetable, elfdat: &[u8]) -> Option<(u64, u64)> {
-    // The elf data must be properly aligned.
-    assert!(&elfdat[0] as *const _ as usize % 8 == 0);
+    if elfdat.len() < size_of::<FileHeader64>() {
+        return None;
+    }
+    // The headers are read in place, so the elf data must be properly aligned.
+    if elfdat.as_ptr() as usize % 8 != 0 {
+        return None;
+    }
     let elf: &FileHeader64 = unsafe { &*(elfdat.as_ptr() as *const FileHeader64) };
 
-    if elf.magic != MAGIC {
+    if elf.magic != MAGIC || elf.width != WIDTH_64 {
         return None;
     }
 
     let mut brk = 0;
 
     for i in 0..elf.phnum {
-        let off = elf.phoff as usize + i as usize * core::mem::size_of::<ProgHeader64>();
+        // The binary may come from user space, so every header and segment must be checked to be
+        // in bounds.
+        let off = (elf.phoff as usize).checked_add(i as usize * size_of::<ProgHeader64>())?;
+        if off.checked_add(size_of::<ProgHeader64>())? > elfdat.len() || off % 8 != 0 {
+            return None;
+        }
         let ph: &ProgHeader64 = unsafe { &*(elfdat.as_ptr().add(off) as *const ProgHeader64) };
         if ph.type_ != PROG_LOAD || ph.memsz == 0 {
             continue;
@@ -61,7 +76,11 @@ pub fn load64(pt: &mut Pagetable, elfdat: &[u8]) -> Option<(u64, u64)> {
         if ph.memsz < ph.filesz {
             return None;
         }
-        if ph.vaddr + ph.memsz < ph.vaddr {
+        if ph.offset.checked_add(ph.filesz)? > elfdat.len() as u64 {
+            return None;
+        }
+        // Segments must not overlap the user stack or extend into kernel addresses.
+        if ph.vaddr.checked_add(ph.memsz)? > Proc::STACK_VA as u64 {
             return None;
         }
 
@@ -0,0 +1,27 @@
+// Error codes returned by system calls (negated Linux errno values).
+
+pub const NOENT: isize = -2;
+pub const SRCH: isize = -3;
+pub const INTR: isize = -4;
+pub const IO: isize = -5;
+pub const TOOBIG: isize = -7;
+pub const NOEXEC: isize = -8;
+pub const BADF: isize = -9;
+pub const CHILD: isize = -10;
+pub const AGAIN: isize = -11;
+pub const NOMEM: isize = -12;
+pub const FAULT: isize = -14;
+pub const BUSY: isize = -16;
+pub const EXIST: isize = -17;
+pub const XDEV: isize = -18;
+pub const NOTDIR: isize = -20;
+pub const ISDIR: isize = -21;
+pub const INVAL: isize = -22;
+pub const MFILE: isize = -24;
+pub const FBIG: isize = -27;
+pub const NOSPC: isize = -28;
+pub const ROFS: isize = -30;
+pub const PIPE: isize = -32;
+pub const NAMETOOLONG: isize = -36;
+pub const NOSYS: isize = -38;
+pub const NOTEMPTY: isize = -39;
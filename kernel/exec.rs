@@ -0,0 +1,118 @@
+use crate::arch::vm::{kernel_procmap, Pagetable};
+use crate::elf;
+use crate::err;
+use crate::kalloc::{zalloc, zallocpage};
+use crate::proc::Proc;
+use crate::sys;
+use crate::timer;
+use crate::vm::{free_upages, perm, PageMap};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+// Auxiliary vector entry types.
+const AT_NULL: usize = 0;
+const AT_PAGESZ: usize = 6;
+const AT_ENTRY: usize = 9;
+
+/// A freshly loaded user address space that has not yet been installed into a process.
+pub struct Image {
+    pub pt: Box<Pagetable>,
+    pub entry: usize,
+    pub brk: usize,
+    /// Initial stack pointer, which points to 'argc'.
+    pub sp: usize,
+    pub argc: usize,
+    pub argv: usize,
+    pub envp: usize,
+}
+
+/// Loads the ELF binary 'bin' into a new pagetable and builds the initial user stack. The stack
+/// uses the System V layout: 'sp' points to argc, followed by the argv pointers, the (empty) envp
+/// pointers and the auxiliary vector, with the argument strings stored at the top of the stack.
+pub fn load(bin: &[u8], argv: &[&[u8]]) -> Result<Image, isize> {
+    // The ELF loader needs the binary to be 8-byte aligned, so copy it if it is not.
+    let copy;
+    let bin = if (bin.as_ptr() as usize).is_multiple_of(8) {
+        bin
+    } else {
+        copy = aligned(bin)?;
+        unsafe { core::slice::from_raw_parts(copy.as_ptr() as *const u8, bin.len()) }
+    };
+
+    let mut pt = zalloc::<Pagetable>().map_err(|_| err::NOMEM)?;
+    kernel_procmap(&mut pt);
+
+    match build(&mut pt, bin, argv) {
+        Ok((entry, brk, sp)) => Ok(Image {
+            pt,
+            entry,
+            brk,
+            sp,
+            argc: argv.len(),
+            argv: sp + size_of::<usize>(),
+            envp: sp + (argv.len() + 2) * size_of::<usize>(),
+        }),
+        Err(e) => {
+            free_upages(&mut pt);
+            Err(e)
+        }
+    }
+}
+
+// Returns a copy of 'bin' in a buffer of u64, which is 8-byte aligned.
+fn aligned(bin: &[u8]) -> Result<Vec<u64>, isize> {
+    let mut buf = Vec::new();
+    let len = bin.len().div_ceil(size_of::<u64>());
+    buf.try_reserve_exact(len).map_err(|_| err::NOMEM)?;
+    buf.resize(len, 0u64);
+    unsafe { core::ptr::copy_nonoverlapping(bin.as_ptr(), buf.as_mut_ptr() as *mut u8, bin.len()) };
+    Ok(buf)
+}
+
+// Loads the ELF segments and the stack into 'pt'. Returns the entrypoint, program break and the
+// initial stack pointer.
+fn build(pt: &mut Pagetable, bin: &[u8], argv: &[&[u8]]) -> Result<(usize, usize, usize), isize> {
+    let (entry, brk) = elf::load64(pt, bin).ok_or(err::NOEXEC)?;
+    let (entry, brk) = (entry as usize, brk as usize);
+
+    let mut stack = zallocpage().map_err(|_| err::NOMEM)?;
+
+    // Offsets are relative to the bottom of the stack page.
+    let mut off = Proc::STACK_SIZE;
+    let mut ptrs = Vec::new();
+    ptrs.try_reserve_exact(argv.len()).map_err(|_| err::NOMEM)?;
+    for arg in argv {
+        off = off.checked_sub(arg.len() + 1).ok_or(err::TOOBIG)?;
+        stack[off..off + arg.len()].copy_from_slice(arg);
+        stack[off + arg.len()] = 0;
+        ptrs.push(Proc::STACK_VA + off);
+    }
+
+    let mut words = Vec::new();
+    words
+        .try_reserve_exact(argv.len() + 9)
+        .map_err(|_| err::NOMEM)?;
+    words.push(argv.len());
+    words.extend_from_slice(&ptrs);
+    words.push(0); // end of argv
+    words.push(0); // end of envp
+    words.extend_from_slice(&[AT_PAGESZ, sys::PAGESIZE, AT_ENTRY, entry, AT_NULL, 0]);
+
+    // The stack pointer must be 16-byte aligned.
+    off = off
+        .checked_sub(words.len() * size_of::<usize>())
+        .ok_or(err::TOOBIG)?
+        & !15;
+    for (i, w) in words.iter().enumerate() {
+        let o = off + i * size_of::<usize>();
+        stack[o..o + size_of::<usize>()].copy_from_slice(&w.to_le_bytes());
+    }
+
+    pt.mappg(Proc::STACK_VA, stack, perm::URW)
+        .ok_or(err::NOMEM)?;
+    timer::map_page(pt)?;
+
+    Ok((entry, brk, Proc::STACK_VA + off))
+}
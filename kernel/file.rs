@@ -0,0 +1,114 @@
+use crate::err;
+use crate::proc::Proc;
+
+use alloc::sync::Arc;
+
+/// Maximum number of open files per process.
+pub const NOFILE: usize = 16;
+
+/// Flags for the open system call (these match newlib's values).
+pub mod flags {
+    pub const O_RDONLY: usize = 0;
+    pub const O_WRONLY: usize = 1;
+    pub const O_RDWR: usize = 2;
+    pub const O_ACCMODE: usize = 3;
+    pub const O_APPEND: usize = 0x0008;
+    pub const O_CREAT: usize = 0x0200;
+    pub const O_TRUNC: usize = 0x0400;
+    pub const O_EXCL: usize = 0x0800;
+}
+
+/// An open file. Open files are reference counted, and may be shared by several file descriptors
+/// (after dup) and processes (after fork). Reads and writes operate on kernel buffers; the system
+/// call layer copies data to and from user memory. Either operation may block the calling process
+/// 'p'.
+pub trait File: Send + Sync {
+    /// Reads up to 'buf.len()' bytes into 'buf'. Returns the number of bytes read, which is 0 at
+    /// end of file.
+    fn read(&self, p: &mut Proc, buf: &mut [u8]) -> Result<usize, isize> {
+        let _ = (p, buf);
+        Err(err::BADF)
+    }
+
+    /// Writes up to 'buf.len()' bytes from 'buf'. Returns the number of bytes written.
+    fn write(&self, p: &mut Proc, buf: &[u8]) -> Result<usize, isize> {
+        let _ = (p, buf);
+        Err(err::BADF)
+    }
+
+    /// Reads directory entries into 'buf', in the format of Linux's getdents64. Returns the
+    /// number of bytes used, which is 0 at the end of the directory.
+    fn getdents(&self, p: &mut Proc, buf: &mut [u8]) -> Result<usize, isize> {
+        let _ = (p, buf);
+        Err(err::NOTDIR)
+    }
+}
+
+/// Per-process file descriptor table.
+#[derive(Clone)]
+pub struct FdTable {
+    files: [Option<Arc<dyn File>>; NOFILE],
+}
+
+impl FdTable {
+    pub const fn new() -> Self {
+        Self {
+            files: [const { None }; NOFILE],
+        }
+    }
+
+    /// Returns the file open at 'fd'.
+    pub fn get(&self, fd: usize) -> Result<Arc<dyn File>, isize> {
+        match self.files.get(fd) {
+            Some(Some(f)) => Ok(f.clone()),
+            _ => Err(err::BADF),
+        }
+    }
+
+    /// Installs 'f' at the lowest free file descriptor and returns it.
+    pub fn alloc(&mut self, f: Arc<dyn File>) -> Result<usize, isize> {
+        let fd = self
+            .files
+            .iter()
+            .position(|f| f.is_none())
+            .ok_or(err::MFILE)?;
+        self.files[fd] = Some(f);
+        Ok(fd)
+    }
+
+    /// Closes 'fd'. The file itself is released once its last descriptor is closed.
+    pub fn close(&mut self, fd: usize) -> Result<(), isize> {
+        match self.files.get_mut(fd) {
+            Some(f @ Some(_)) => {
+                *f = None;
+                Ok(())
+            }
+            _ => Err(err::BADF),
+        }
+    }
+
+    /// Duplicates 'fd' onto the lowest free file descriptor.
+    pub fn dup(&mut self, fd: usize) -> Result<usize, isize> {
+        let f = self.get(fd)?;
+        self.alloc(f)
+    }
+
+    /// Duplicates 'fd' onto 'newfd', closing the file previously open at 'newfd'.
+    pub fn dup2(&mut self, fd: usize, newfd: usize) -> Result<usize, isize> {
+        let f = self.get(fd)?;
+        let slot = self.files.get_mut(newfd).ok_or(err::BADF)?;
+        *slot = Some(f);
+        Ok(newfd)
+    }
+
+    /// Closes all file descriptors.
+    pub fn clear(&mut self) {
+        self.files = [const { None }; NOFILE];
+    }
+}
+
+impl Default for FdTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
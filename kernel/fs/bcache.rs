@@ -0,0 +1,131 @@
+use crate::dev::block::{BlockDevice, SECTOR_SIZE};
+use crate::err;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+struct Buf {
+    blockno: u32,
+    valid: bool,
+    // Pinned buffers hold changes that are not on disk yet, and are never evicted.
+    pinned: bool,
+    data: Box<[u8]>,
+}
+
+/// A cache of recently used disk blocks. The cache is not synchronized; the filesystem that owns
+/// it must serialize accesses.
+pub struct Bcache {
+    dev: Arc<dyn BlockDevice>,
+    bsize: usize,
+    // Buffers in least-recently-used order, with the most recently used buffer first.
+    bufs: Vec<Buf>,
+}
+
+impl Bcache {
+    /// Creates a cache of 'nbuf' blocks of 'bsize' bytes for 'dev'. The block size must be a
+    /// multiple of the sector size.
+    pub fn new(dev: Arc<dyn BlockDevice>, bsize: usize, nbuf: usize) -> Result<Self, isize> {
+        assert!(bsize.is_multiple_of(SECTOR_SIZE));
+        let mut bufs = Vec::new();
+        bufs.try_reserve_exact(nbuf).map_err(|_| err::NOMEM)?;
+        for _ in 0..nbuf {
+            bufs.push(Buf {
+                blockno: 0,
+                valid: false,
+                pinned: false,
+                data: Box::<[u8]>::try_new_zeroed_slice(bsize)
+                    .map(|b| unsafe { b.assume_init() })
+                    .map_err(|_| err::NOMEM)?,
+            });
+        }
+        Ok(Self { dev, bsize, bufs })
+    }
+
+    /// Returns the block size.
+    pub fn bsize(&self) -> usize {
+        self.bsize
+    }
+
+    /// Returns the number of blocks on the device.
+    pub fn nblocks(&self) -> u64 {
+        self.dev.nsectors() / (self.bsize / SECTOR_SIZE) as u64
+    }
+
+    fn sector(&self, blockno: u32) -> u64 {
+        blockno as u64 * (self.bsize / SECTOR_SIZE) as u64
+    }
+
+    // Returns the index of the buffer for 'blockno', which has been moved to the front. The
+    // contents are only loaded from disk if 'load' is true.
+    fn lookup(&mut self, blockno: u32, load: bool) -> Result<usize, isize> {
+        let i = match self
+            .bufs
+            .iter()
+            .position(|b| b.valid && b.blockno == blockno)
+        {
+            Some(i) => i,
+            None => {
+                // Recycle the least recently used buffer that is not pinned.
+                let i = self
+                    .bufs
+                    .iter()
+                    .rposition(|b| !b.pinned)
+                    .ok_or(err::NOMEM)?;
+                let sector = self.sector(blockno);
+                let b = &mut self.bufs[i];
+                b.valid = false;
+                if load {
+                    self.dev.read(sector, &mut b.data)?;
+                }
+                b.blockno = blockno;
+                b.valid = true;
+                i
+            }
+        };
+        self.bufs[..=i].rotate_right(1);
+        Ok(0)
+    }
+
+    /// Returns the contents of block 'blockno', reading it from disk if it is not cached.
+    /// Modifications are only written back by 'flush'.
+    pub fn get(&mut self, blockno: u32) -> Result<&mut [u8], isize> {
+        let i = self.lookup(blockno, true)?;
+        Ok(&mut self.bufs[i].data)
+    }
+
+    /// Returns a zeroed buffer for block 'blockno' without reading it from disk, for blocks that
+    /// are about to be overwritten entirely.
+    pub fn zero(&mut self, blockno: u32) -> Result<&mut [u8], isize> {
+        let i = self.lookup(blockno, false)?;
+        self.bufs[i].data.fill(0);
+        Ok(&mut self.bufs[i].data)
+    }
+
+    /// Writes the cached contents of block 'blockno' to disk.
+    pub fn flush(&mut self, blockno: u32) -> Result<(), isize> {
+        let sector = self.sector(blockno);
+        let i = self.lookup(blockno, true)?;
+        self.dev.write(sector, &self.bufs[i].data)
+    }
+
+    /// Discards the cached contents of block 'blockno' (and unpins it), so that it is read from
+    /// disk again on the next access.
+    pub fn invalidate(&mut self, blockno: u32) {
+        for b in self
+            .bufs
+            .iter_mut()
+            .filter(|b| b.valid && b.blockno == blockno)
+        {
+            b.valid = false;
+            b.pinned = false;
+        }
+    }
+
+    /// Pins or unpins block 'blockno' in the cache. Pinned blocks are never evicted.
+    pub fn pin(&mut self, blockno: u32, pinned: bool) -> Result<(), isize> {
+        let i = self.lookup(blockno, true)?;
+        self.bufs[i].pinned = pinned;
+        Ok(())
+    }
+}
@@ -0,0 +1,1409 @@
+use crate::dev::block::{BlockDevice, SECTOR_SIZE};
+use crate::err;
+use crate::fs::bcache::Bcache;
+use crate::fs::vfs::{DirEntry, FileSystem, FileType, Stat};
+use crate::fs::{rd16, rd32, wr16, wr32};
+use crate::sync::mutex::Mutex;
+use crate::timer;
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::min;
+
+// A FAT32 filesystem. Volume layout, in sectors:
+//
+// [ reserved (boot sector, fsinfo) | FATs | data clusters ]
+//
+// Files are stored in clusters of consecutive sectors. The file allocation table (FAT) maps each
+// cluster to the next cluster of the file it belongs to, forming a chain that starts at the
+// first cluster recorded in the file's directory entry. Directories, including the root, are
+// files holding 32-byte entries. Long file names are stored in extra entries that precede the
+// 8.3 entry they belong to.
+//
+// There is no journal. Data is written through to the disk immediately, and the FAT and
+// directory entries modified by an operation are written when it completes, so that a crash
+// leaves at worst lost clusters behind.
+//
+// Since the filesystem has no inodes, files are identified by the position (in bytes from the
+// start of the volume) of their 8.3 directory entry.
+
+const NBUF: usize = 64;
+const DIRENT_SIZE: usize = 32;
+const FAT_MASK: u32 = 0x0fff_ffff;
+// Values stored in the FAT for the last cluster of a chain.
+const EOC: u32 = 0x0fff_ffff;
+const EOC_MIN: u32 = 0x0fff_fff8;
+const MAXCLUSTERS: u32 = 0x0fff_fff5;
+// Number of free clusters in the FSInfo sector when it is not known.
+const FREE_UNKNOWN: u32 = 0xffff_ffff;
+// Maximum length of a long name, in UTF-16 code units.
+const MAXNAME: usize = 255;
+// Number of name characters held by each long name entry.
+const LFN_CHARS: usize = 13;
+const LFN_MAXENTRIES: usize = MAXNAME.div_ceil(LFN_CHARS);
+// Flag of the long name entry holding the end of the name (which is stored first).
+const LFN_LAST: u8 = 0x40;
+// Directories may not have more entries than this.
+const MAXDIRENTS: usize = 65536;
+// Maximum number of modified metadata sectors held in the cache before they are written out.
+const MAXDIRTY: usize = NBUF / 2;
+// Inode number of the root directory, which has no directory entry.
+const ROOT_INO: u64 = 1;
+// First byte of the name of a deleted entry, and of the entry following the last entry in use.
+const DELETED: u8 = 0xe5;
+const END: u8 = 0x00;
+// Date of the FAT epoch, 1980-01-01, which is used for times that cannot be represented.
+const EPOCH_DATE: u16 = (1 << 5) | 1;
+
+// Directory entry attributes.
+mod attr {
+    pub const READ_ONLY: u8 = 0x01;
+    pub const HIDDEN: u8 = 0x02;
+    pub const SYSTEM: u8 = 0x04;
+    pub const VOLUME_ID: u8 = 0x08;
+    pub const DIRECTORY: u8 = 0x10;
+    pub const ARCHIVE: u8 = 0x20;
+    pub const LONG_NAME: u8 = READ_ONLY | HIDDEN | SYSTEM | VOLUME_ID;
+}
+
+// Flags in the reserved byte of an 8.3 entry that indicate a lowercase base name or extension.
+const LCASE_BASE: u8 = 0x08;
+const LCASE_EXT: u8 = 0x10;
+
+// Offsets of fields in a directory entry.
+mod ent {
+    pub const ATTR: usize = 11;
+    pub const NTRES: usize = 12;
+    pub const CRT_TENTH: usize = 13;
+    pub const CRT_TIME: usize = 14;
+    pub const CRT_DATE: usize = 16;
+    pub const ACC_DATE: usize = 18;
+    pub const CLUS_HI: usize = 20;
+    pub const WRT_TIME: usize = 22;
+    pub const WRT_DATE: usize = 24;
+    pub const CLUS_LO: usize = 26;
+    pub const SIZE: usize = 28;
+    // Long name entries.
+    pub const LFN_CHKSUM: usize = 13;
+    pub const LFN_NAME: [usize; 13] = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+}
+
+// Parameters of the volume, from the BIOS parameter block in the boot sector.
+struct Bpb {
+    // Bytes per sector.
+    bps: u32,
+    // Sectors per cluster.
+    spc: u32,
+    fat_start: u32,
+    fat_size: u32,
+    nfats: u32,
+    // If the FATs are not mirrored, only FAT 'active' is used.
+    mirrored: bool,
+    active: u32,
+    data_start: u32,
+    // Clusters are numbered from 2 to nclusters + 1.
+    nclusters: u32,
+    root: u32,
+    // Sector of the FSInfo structure, or 0 if there is none.
+    fsinfo: u32,
+}
+
+// A file or directory.
+#[derive(Copy, Clone)]
+struct Node {
+    ino: u64,
+    // First cluster, or 0 for an empty file.
+    first: u32,
+    size: u32,
+    dir: bool,
+}
+
+// A directory entry found by a lookup: the positions of its slots, which are its long name
+// entries followed by its 8.3 entry.
+struct Found {
+    slots: Vec<u64>,
+}
+
+impl Found {
+    fn pos(&self) -> u64 {
+        *self.slots.last().unwrap()
+    }
+}
+
+fn first_cluster(e: &[u8]) -> u32 {
+    ((rd16(e, ent::CLUS_HI) as u32) << 16) | rd16(e, ent::CLUS_LO) as u32
+}
+
+fn set_first_cluster(e: &mut [u8], c: u32) {
+    wr16(e, ent::CLUS_HI, (c >> 16) as u16);
+    wr16(e, ent::CLUS_LO, c as u16);
+}
+
+// Returns the current real time as a FAT date and time (with a resolution of 2 seconds), and the
+// remaining seconds in hundredths. Times before the FAT epoch are mapped to the epoch.
+fn timestamp() -> (u16, u16, u8) {
+    let secs = timer::realtime() / 1_000_000_000;
+    // Convert the days since 1970-01-01 into a civil date, with years starting in March so that
+    // leap days come last (see http://howardhinnant.github.io/date_algorithms.html).
+    let z = secs / 86400 + 719468;
+    let (era, doe) = (z / 146097, z % 146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = era * 400 + yoe + (month <= 2) as u64;
+    if !(1980..2108).contains(&year) {
+        return (EPOCH_DATE, 0, 0);
+    }
+    let s = secs % 86400;
+    let date = ((year - 1980) << 9) | (month << 5) | day;
+    let time = ((s / 3600) << 11) | ((s / 60 % 60) << 5) | (s % 60 / 2);
+    (date as u16, time as u16, (s % 2 * 100) as u8)
+}
+
+// Sets the creation, access and modification times of a new entry to the current time.
+fn set_created(e: &mut [u8]) {
+    let (date, time, tenth) = timestamp();
+    e[ent::CRT_TENTH] = tenth;
+    wr16(e, ent::CRT_TIME, time);
+    wr16(e, ent::CRT_DATE, date);
+    wr16(e, ent::ACC_DATE, date);
+    wr16(e, ent::WRT_TIME, time);
+    wr16(e, ent::WRT_DATE, date);
+}
+
+// Sets the access and modification times of an entry to the current time.
+fn set_modified(e: &mut [u8]) {
+    let (date, time, _) = timestamp();
+    wr16(e, ent::ACC_DATE, date);
+    wr16(e, ent::WRT_TIME, time);
+    wr16(e, ent::WRT_DATE, date);
+}
+
+fn is_lfn(e: &[u8]) -> bool {
+    e[ent::ATTR] & 0x3f == attr::LONG_NAME
+}
+
+// Returns true for the "." and ".." entries of a directory.
+fn is_dots(e: &[u8]) -> bool {
+    &e[..11] == b".          " || &e[..11] == b"..         "
+}
+
+// Checksum of an 8.3 name, stored in each of its long name entries.
+fn checksum(name: &[u8]) -> u8 {
+    name[..11]
+        .iter()
+        .fold(0u8, |sum, &c| sum.rotate_right(1).wrapping_add(c))
+}
+
+fn upper16(c: u16) -> u16 {
+    if (b'a' as u16..=b'z' as u16).contains(&c) {
+        c - 32
+    } else {
+        c
+    }
+}
+
+// Compares names, ignoring the case of ASCII letters.
+fn name_eq(a: &[u16], b: &[u16]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(&x, &y)| upper16(x) == upper16(y))
+}
+
+// Returns the display name of an 8.3 entry.
+fn short_name(e: &[u8]) -> Vec<u16> {
+    let mut name = Vec::new();
+    let lower = |c: u8, flag: u8| {
+        if e[ent::NTRES] & flag != 0 {
+            c.to_ascii_lowercase()
+        } else {
+            c
+        }
+    };
+    let base = &e[..8];
+    let blen = base.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+    for (i, &c) in base[..blen].iter().enumerate() {
+        // 0x05 stands for an initial 0xe5, which marks deleted entries.
+        let c = if i == 0 && c == 0x05 { DELETED } else { c };
+        name.push(lower(c, LCASE_BASE) as u16);
+    }
+    let ext = &e[8..11];
+    let elen = ext.iter().rposition(|&c| c != b' ').map_or(0, |i| i + 1);
+    if elen > 0 {
+        name.push(b'.' as u16);
+        name.extend(ext[..elen].iter().map(|&c| lower(c, LCASE_EXT) as u16));
+    }
+    name
+}
+
+// Characters that may appear in 8.3 names, besides letters and digits.
+const SHORT_SPECIAL: &[u8] = b"$%'-_@~`!(){}^#&";
+
+fn short_char(c: u16) -> bool {
+    c < 0x80 && ((c as u8).is_ascii_alphanumeric() || SHORT_SPECIAL.contains(&(c as u8)))
+}
+
+// Returns the 8.3 name and case flags for 'name' if it can be stored in an 8.3 entry alone.
+fn exact_short_name(name: &[u16]) -> Option<([u8; 11], u8)> {
+    let (base, ext) = match name.iter().position(|&c| c == b'.' as u16) {
+        Some(i) => (&name[..i], &name[i + 1..]),
+        None => (name, &[][..]),
+    };
+    if base.is_empty() || base.len() > 8 || ext.len() > 3 || (ext.is_empty() && base != name) {
+        return None;
+    }
+    let mut short = [b' '; 11];
+    let mut ntres = 0;
+    for (part, dst, flag) in [(base, 0, LCASE_BASE), (ext, 8, LCASE_EXT)] {
+        if !part.iter().all(|&c| short_char(c)) {
+            return None;
+        }
+        let has_lower = part.iter().any(|&c| (c as u8).is_ascii_lowercase());
+        let has_upper = part.iter().any(|&c| (c as u8).is_ascii_uppercase());
+        if has_lower && has_upper {
+            return None;
+        }
+        if has_lower {
+            ntres |= flag;
+        }
+        for (i, &c) in part.iter().enumerate() {
+            short[dst + i] = (c as u8).to_ascii_uppercase();
+        }
+    }
+    Some((short, ntres))
+}
+
+// Returns an 8.3 alias for a name that needs a long name entry, of the form "BASE~N.EXT", which
+// is not already used by one of 'existing'.
+fn alias(name: &[u16], existing: &[[u8; 11]]) -> Result<[u8; 11], isize> {
+    let conv = |c: u16| {
+        if short_char(c) {
+            Some((c as u8).to_ascii_uppercase())
+        } else if c == b' ' as u16 || c == b'.' as u16 {
+            None
+        } else {
+            Some(b'_')
+        }
+    };
+    let (base, ext) = match name.iter().rposition(|&c| c == b'.' as u16) {
+        Some(i) if i > 0 => (&name[..i], &name[i + 1..]),
+        _ => (name, &[][..]),
+    };
+    let mut short = [b' '; 11];
+    let mut blen = 0;
+    for c in base.iter().filter_map(|&c| conv(c)).take(6) {
+        short[blen] = c;
+        blen += 1;
+    }
+    for (i, c) in ext.iter().filter_map(|&c| conv(c)).take(3).enumerate() {
+        short[8 + i] = c;
+    }
+    if blen == 0 {
+        short[0] = b'_';
+        blen = 1;
+    }
+    for n in 1..1_000_000u32 {
+        let mut digits = [0u8; 7];
+        let mut len = 0;
+        let mut v = n;
+        while v > 0 {
+            digits[len] = b'0' + (v % 10) as u8;
+            v /= 10;
+            len += 1;
+        }
+        let keep = min(blen, 8 - 1 - len);
+        let mut cand = short;
+        cand[keep..8].fill(b' ');
+        cand[keep] = b'~';
+        for i in 0..len {
+            cand[keep + 1 + i] = digits[len - 1 - i];
+        }
+        if !existing.contains(&cand) {
+            return Ok(cand);
+        }
+    }
+    Err(err::NOSPC)
+}
+
+// Accumulates the long name entries that precede an 8.3 entry.
+struct Lfn {
+    name: [u16; LFN_MAXENTRIES * LFN_CHARS],
+    slots: Vec<u64>,
+    // Number of entries of the name, and ordinal of the next expected entry (0 once all entries
+    // were seen).
+    count: usize,
+    next: usize,
+    chksum: u8,
+}
+
+impl Lfn {
+    fn new() -> Self {
+        Self {
+            name: [0; LFN_MAXENTRIES * LFN_CHARS],
+            slots: Vec::new(),
+            count: 0,
+            next: 0,
+            chksum: 0,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.count = 0;
+        self.slots.clear();
+    }
+
+    fn add(&mut self, pos: u64, e: &[u8]) {
+        let ord = (e[0] & !LFN_LAST) as usize;
+        if e[0] & LFN_LAST != 0 {
+            self.reset();
+            if ord == 0 || ord > LFN_MAXENTRIES {
+                return;
+            }
+            self.count = ord;
+            self.chksum = e[ent::LFN_CHKSUM];
+        } else if self.count == 0
+            || ord == 0
+            || ord != self.next
+            || e[ent::LFN_CHKSUM] != self.chksum
+        {
+            self.reset();
+            return;
+        }
+        for (i, &off) in ent::LFN_NAME.iter().enumerate() {
+            self.name[(ord - 1) * LFN_CHARS + i] = rd16(e, off);
+        }
+        self.slots.push(pos);
+        self.next = ord - 1;
+    }
+
+    // Returns the long name belonging to the 8.3 entry 'e', if there is a complete one.
+    fn name(&self, e: &[u8]) -> Option<&[u16]> {
+        if self.count == 0 || self.next != 0 || checksum(e) != self.chksum {
+            return None;
+        }
+        let name = &self.name[..self.count * LFN_CHARS];
+        let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+        Some(&name[..len])
+    }
+}
+
+// Returns the name as UTF-16, checking that it is a valid FAT name.
+fn encode_name(name: &str) -> Result<Vec<u16>, isize> {
+    if name.is_empty() || name == "." || name == ".." || name.ends_with(['.', ' ']) {
+        return Err(err::INVAL);
+    }
+    if name
+        .chars()
+        .any(|c| c < ' ' || "\"*/:<>?\\|\x7f".contains(c))
+    {
+        return Err(err::INVAL);
+    }
+    let mut name16 = Vec::new();
+    name16.try_reserve(name.len()).map_err(|_| err::NOMEM)?;
+    name16.extend(name.encode_utf16());
+    if name16.len() > MAXNAME {
+        return Err(err::NAMETOOLONG);
+    }
+    Ok(name16)
+}
+
+struct Inner {
+    cache: Bcache,
+    bpb: Bpb,
+    // Cluster at which to start looking for free clusters.
+    next_free: u32,
+    free_count: u32,
+    // Metadata sectors modified by the current operation, which are pinned in the cache.
+    dirty: Vec<u32>,
+    // Number of open files for each inode that is open. Open files may not be removed.
+    open: BTreeMap<u64, usize>,
+}
+
+/// A mounted FAT32 filesystem. Operations are serialized by a single sleeping lock.
+pub struct Fat32Fs {
+    inner: Mutex<Inner>,
+}
+
+impl Inner {
+    fn bps(&self) -> usize {
+        self.bpb.bps as usize
+    }
+
+    fn csize(&self) -> usize {
+        (self.bpb.bps * self.bpb.spc) as usize
+    }
+
+    fn valid_cluster(&self, c: u32) -> bool {
+        c >= 2 && c - 2 < self.bpb.nclusters
+    }
+
+    fn cluster_sector(&self, c: u32) -> u32 {
+        self.bpb.data_start + (c - 2) * self.bpb.spc
+    }
+
+    // Records that cached sector 'sec' was modified and must be written at the end of the
+    // operation.
+    fn mark_dirty(&mut self, sec: u32) -> Result<(), isize> {
+        if self.dirty.contains(&sec) {
+            return Ok(());
+        }
+        if self.dirty.len() >= MAXDIRTY {
+            self.sync()?;
+        }
+        self.dirty.try_reserve(1).map_err(|_| err::NOMEM)?;
+        self.cache.pin(sec, true)?;
+        self.dirty.push(sec);
+        Ok(())
+    }
+
+    // Writes the modified metadata sectors to disk.
+    fn sync(&mut self) -> Result<(), isize> {
+        while let Some(&sec) = self.dirty.first() {
+            self.cache.flush(sec)?;
+            self.cache.pin(sec, false)?;
+            self.dirty.remove(0);
+        }
+        Ok(())
+    }
+
+    fn fat_get(&mut self, c: u32) -> Result<u32, isize> {
+        if !self.valid_cluster(c) {
+            return Err(err::IO);
+        }
+        let off = c as usize * 4;
+        let bps = self.bps();
+        let sec = self.bpb.fat_start + self.bpb.active * self.bpb.fat_size + (off / bps) as u32;
+        Ok(rd32(self.cache.get(sec)?, off % bps) & FAT_MASK)
+    }
+
+    fn fat_set(&mut self, c: u32, val: u32) -> Result<(), isize> {
+        let off = c as usize * 4;
+        let bps = self.bps();
+        for i in 0..self.bpb.nfats {
+            if !self.bpb.mirrored && i != self.bpb.active {
+                continue;
+            }
+            let sec = self.bpb.fat_start + i * self.bpb.fat_size + (off / bps) as u32;
+            let b = self.cache.get(sec)?;
+            // The top 4 bits are reserved and must be preserved.
+            let old = rd32(b, off % bps);
+            wr32(b, off % bps, (old & !FAT_MASK) | val);
+            self.mark_dirty(sec)?;
+        }
+        Ok(())
+    }
+
+    // Returns the cluster following 'c' in its chain, or None if it is the last one.
+    fn next(&mut self, c: u32) -> Result<Option<u32>, isize> {
+        match self.fat_get(c)? {
+            n if n >= EOC_MIN => Ok(None),
+            n if self.valid_cluster(n) => Ok(Some(n)),
+            // Free or bad cluster in a chain.
+            _ => Err(err::IO),
+        }
+    }
+
+    fn update_fsinfo(&mut self) -> Result<(), isize> {
+        let sec = self.bpb.fsinfo;
+        if sec == 0 {
+            return Ok(());
+        }
+        let (free, next) = (self.free_count, self.next_free);
+        let b = self.cache.get(sec)?;
+        wr32(b, 488, free);
+        wr32(b, 492, next);
+        self.mark_dirty(sec)
+    }
+
+    // Allocates a cluster and appends it to the chain ending at 'prev', if any. The contents of
+    // the cluster are zeroed if 'zero' is true.
+    fn alloc_cluster(&mut self, prev: Option<u32>, zero: bool) -> Result<u32, isize> {
+        let n = self.bpb.nclusters;
+        let start = if self.valid_cluster(self.next_free) {
+            self.next_free - 2
+        } else {
+            0
+        };
+        for i in 0..n {
+            let c = 2 + (start + i) % n;
+            if self.fat_get(c)? != 0 {
+                continue;
+            }
+            if zero {
+                let sec = self.cluster_sector(c);
+                for s in sec..sec + self.bpb.spc {
+                    self.cache.zero(s)?;
+                    self.cache.flush(s)?;
+                }
+            }
+            self.fat_set(c, EOC)?;
+            if let Some(p) = prev {
+                self.fat_set(p, c)?;
+            }
+            self.next_free = c + 1;
+            if self.free_count != FREE_UNKNOWN {
+                self.free_count = self.free_count.saturating_sub(1);
+            }
+            self.update_fsinfo()?;
+            return Ok(c);
+        }
+        Err(err::NOSPC)
+    }
+
+    // Frees the chain starting at cluster 'c'.
+    fn free_chain(&mut self, mut c: u32) -> Result<(), isize> {
+        // Bound the walk in case the chain is corrupted into a loop.
+        for _ in 0..self.bpb.nclusters {
+            let next = self.next(c);
+            self.fat_set(c, 0)?;
+            if self.free_count != FREE_UNKNOWN {
+                self.free_count += 1;
+            }
+            match next? {
+                None => break,
+                Some(n) => c = n,
+            }
+        }
+        self.update_fsinfo()
+    }
+
+    // Returns the cluster following 'c', appending a new cluster to the chain if 'c' is the last
+    // one and 'alloc' is true.
+    fn step(&mut self, c: u32, alloc: bool, zero: bool) -> Result<Option<u32>, isize> {
+        match self.next(c)? {
+            None if alloc => Ok(Some(self.alloc_cluster(Some(c), zero)?)),
+            next => Ok(next),
+        }
+    }
+
+    // Returns cluster 'idx' of the chain starting at '*first'. If 'alloc' is true, the chain is
+    // extended (or created, updating '*first') as necessary.
+    fn cluster_at(
+        &mut self,
+        first: &mut u32,
+        idx: usize,
+        alloc: bool,
+    ) -> Result<Option<u32>, isize> {
+        if *first == 0 {
+            if !alloc {
+                return Ok(None);
+            }
+            *first = self.alloc_cluster(None, false)?;
+        }
+        let mut c = *first;
+        for _ in 0..idx {
+            match self.step(c, alloc, false)? {
+                None => return Ok(None),
+                Some(n) => c = n,
+            }
+        }
+        Ok(Some(c))
+    }
+
+    fn entry(&mut self, pos: u64) -> Result<[u8; DIRENT_SIZE], isize> {
+        let bps = self.bps();
+        let b = self.cache.get((pos / bps as u64) as u32)?;
+        let off = (pos % bps as u64) as usize;
+        let mut e = [0; DIRENT_SIZE];
+        e.copy_from_slice(&b[off..off + DIRENT_SIZE]);
+        Ok(e)
+    }
+
+    fn set_entry(&mut self, pos: u64, e: &[u8]) -> Result<(), isize> {
+        let bps = self.bps() as u64;
+        let sec = (pos / bps) as u32;
+        let off = (pos % bps) as usize;
+        self.cache.get(sec)?[off..off + DIRENT_SIZE].copy_from_slice(e);
+        self.mark_dirty(sec)
+    }
+
+    fn node(&mut self, ino: u64) -> Result<Node, isize> {
+        if ino == ROOT_INO {
+            return Ok(Node {
+                ino,
+                first: self.bpb.root,
+                size: 0,
+                dir: true,
+            });
+        }
+        let bps = self.bps() as u64;
+        if !ino.is_multiple_of(DIRENT_SIZE as u64) || ino / bps < self.bpb.data_start as u64 {
+            return Err(err::INVAL);
+        }
+        let e = self.entry(ino)?;
+        if e[0] == DELETED || e[0] == END || is_lfn(&e) {
+            return Err(err::NOENT);
+        }
+        Ok(Node {
+            ino,
+            first: first_cluster(&e),
+            size: rd32(&e, ent::SIZE),
+            dir: e[ent::ATTR] & attr::DIRECTORY != 0,
+        })
+    }
+
+    // Calls 'f' with the position and contents of each slot of the directory starting at
+    // cluster 'dir', until 'f' returns a value.
+    fn walk<T>(
+        &mut self,
+        dir: u32,
+        mut f: impl FnMut(u64, &[u8]) -> Option<T>,
+    ) -> Result<Option<T>, isize> {
+        let bps = self.bps();
+        let mut c = Some(dir);
+        let mut nslots = 0;
+        while let Some(cur) = c {
+            let sec = self.cluster_sector(cur);
+            for s in sec..sec + self.bpb.spc {
+                let b = self.cache.get(s)?;
+                for off in (0..bps).step_by(DIRENT_SIZE) {
+                    let pos = s as u64 * bps as u64 + off as u64;
+                    if let Some(v) = f(pos, &b[off..off + DIRENT_SIZE]) {
+                        return Ok(Some(v));
+                    }
+                }
+            }
+            nslots += self.csize() / DIRENT_SIZE;
+            if nslots >= MAXDIRENTS {
+                break;
+            }
+            c = self.next(cur)?;
+        }
+        Ok(None)
+    }
+
+    // Looks up 'name' in the directory starting at cluster 'dir'.
+    fn dirlookup(&mut self, dir: u32, name: &[u16]) -> Result<Option<Found>, isize> {
+        let mut lfn = Lfn::new();
+        let mut nomem = false;
+        let found = self.walk(dir, |pos, e| {
+            if e[0] == END {
+                return Some(None);
+            }
+            if e[0] == DELETED {
+                lfn.reset();
+                return None;
+            }
+            if is_lfn(e) {
+                lfn.add(pos, e);
+                return None;
+            }
+            let matches = e[ent::ATTR] & attr::VOLUME_ID == 0
+                && (lfn.name(e).is_some_and(|long| name_eq(long, name))
+                    || name_eq(&short_name(e), name));
+            if !matches {
+                lfn.reset();
+                return None;
+            }
+            let mut slots = Vec::new();
+            if slots.try_reserve(lfn.slots.len() + 1).is_err() {
+                nomem = true;
+                return Some(None);
+            }
+            if lfn.name(e).is_some() {
+                slots.extend_from_slice(&lfn.slots);
+            }
+            slots.push(pos);
+            Some(Some(Found { slots }))
+        })?;
+        if nomem {
+            return Err(err::NOMEM);
+        }
+        Ok(found.flatten())
+    }
+
+    // Returns the cluster of the directory 'node', checking that it is a directory.
+    fn dir_cluster(&self, node: &Node) -> Result<u32, isize> {
+        if !node.dir {
+            return Err(err::NOTDIR);
+        }
+        // The ".." entries of directories in the root directory hold cluster 0.
+        Ok(if node.first == 0 {
+            self.bpb.root
+        } else {
+            node.first
+        })
+    }
+
+    // Returns the cluster of directory 'ino'.
+    fn dir(&mut self, ino: u64) -> Result<u32, isize> {
+        let node = self.node(ino)?;
+        self.dir_cluster(&node)
+    }
+
+    // Looks up 'name' in the directory starting at cluster 'dir', failing if it does not exist.
+    fn find(&mut self, dir: u32, name: &str) -> Result<Found, isize> {
+        // Names that cannot be stored cannot exist either.
+        let name = encode_name(name).map_err(|e| if e == err::INVAL { err::NOENT } else { e })?;
+        self.dirlookup(dir, &name)?.ok_or(err::NOENT)
+    }
+
+    fn lookup(&mut self, dir: u64, name: &str) -> Result<u64, isize> {
+        let dir = self.dir(dir)?;
+        Ok(self.find(dir, name)?.pos())
+    }
+
+    // Returns the first cluster of the directory in the ".." entry of the directory starting at
+    // cluster 'dir', or 0 for the root.
+    fn up_cluster(&mut self, dir: u32) -> Result<u32, isize> {
+        let up = self.cluster_sector(dir) as u64 * self.bps() as u64 + DIRENT_SIZE as u64;
+        Ok(first_cluster(&self.entry(up)?))
+    }
+
+    // Returns the inode of the directory containing directory 'ino'. Its cluster is found in the
+    // ".." entry, and its inode (the position of its entry) by searching the directory that
+    // contains it in turn.
+    fn parent(&mut self, ino: u64) -> Result<u64, isize> {
+        if ino == ROOT_INO {
+            return Ok(ROOT_INO);
+        }
+        let up = self.dir(ino)?;
+        let up = self.up_cluster(up)?;
+        if up == 0 || up == self.bpb.root {
+            return Ok(ROOT_INO);
+        }
+        let grand = match self.up_cluster(up)? {
+            0 => self.bpb.root,
+            c => c,
+        };
+        let found = self.walk(grand, |pos, e| {
+            if e[0] == END {
+                return Some(None);
+            }
+            let dir = e[0] != DELETED && !is_lfn(e) && e[ent::ATTR] & attr::DIRECTORY != 0;
+            (dir && e[0] != b'.' && first_cluster(e) == up).then_some(Some(pos))
+        })?;
+        found.flatten().ok_or(err::NOENT)
+    }
+
+    // Returns the 8.3 names used in the directory starting at cluster 'dir'.
+    fn short_names(&mut self, dir: u32) -> Result<Vec<[u8; 11]>, isize> {
+        let mut names = Vec::new();
+        let mut nomem = false;
+        self.walk(dir, |_, e| {
+            if e[0] == END {
+                return Some(());
+            }
+            if e[0] != DELETED && !is_lfn(e) {
+                if names.try_reserve(1).is_err() {
+                    nomem = true;
+                    return Some(());
+                }
+                let mut name = [0; 11];
+                name.copy_from_slice(&e[..11]);
+                names.push(name);
+            }
+            None
+        })?;
+        if nomem {
+            return Err(err::NOMEM);
+        }
+        Ok(names)
+    }
+
+    // Returns the positions of 'n' consecutive free slots in the directory starting at cluster
+    // 'dir', growing the directory if necessary.
+    fn free_slots(&mut self, dir: u32, n: usize) -> Result<Vec<u64>, isize> {
+        let mut run = Vec::new();
+        run.try_reserve(n).map_err(|_| err::NOMEM)?;
+        let mut nslots = 0;
+        let mut last = dir;
+        let found = self.walk(dir, |pos, e| {
+            nslots += 1;
+            if e[0] == END || e[0] == DELETED {
+                run.push(pos);
+            } else {
+                run.clear();
+            }
+            (run.len() == n).then_some(())
+        })?;
+        if found.is_some() {
+            return Ok(run);
+        }
+        // Extend the directory with zeroed (free) clusters.
+        while let Some(c) = self.next(last)? {
+            last = c;
+        }
+        while run.len() < n {
+            if nslots + self.csize() / DIRENT_SIZE > MAXDIRENTS {
+                return Err(err::NOSPC);
+            }
+            last = self.alloc_cluster(Some(last), true)?;
+            let bps = self.bps() as u64;
+            let start = self.cluster_sector(last) as u64 * bps;
+            for off in (0..self.csize() as u64).step_by(DIRENT_SIZE) {
+                if run.len() < n {
+                    run.push(start + off);
+                }
+            }
+            nslots += self.csize() / DIRENT_SIZE;
+        }
+        Ok(run)
+    }
+
+    // Adds an entry named 'name' to the directory starting at cluster 'dir'. Returns the position
+    // of the new 8.3 entry.
+    fn dirlink(&mut self, dir: u32, name: &[u16], first: u32, attrs: u8) -> Result<u64, isize> {
+        if self.dirlookup(dir, name)?.is_some() {
+            return Err(err::EXIST);
+        }
+        let (short, ntres, nlfn) = match exact_short_name(name) {
+            Some((short, ntres)) => (short, ntres, 0),
+            None => {
+                let existing = self.short_names(dir)?;
+                (alias(name, &existing)?, 0, name.len().div_ceil(LFN_CHARS))
+            }
+        };
+        let slots = self.free_slots(dir, nlfn + 1)?;
+        let chksum = checksum(&short);
+        for (i, &pos) in slots[..nlfn].iter().enumerate() {
+            // Long name entries are stored in reverse order.
+            let ord = nlfn - i;
+            let mut e = [0u8; DIRENT_SIZE];
+            e[0] = ord as u8 | if i == 0 { LFN_LAST } else { 0 };
+            e[ent::ATTR] = attr::LONG_NAME;
+            e[ent::LFN_CHKSUM] = chksum;
+            for (j, &off) in ent::LFN_NAME.iter().enumerate() {
+                let k = (ord - 1) * LFN_CHARS + j;
+                // The name is terminated by a NUL (unless it fills the entry) and padded with
+                // 0xffff.
+                let c = match k.cmp(&name.len()) {
+                    core::cmp::Ordering::Less => name[k],
+                    core::cmp::Ordering::Equal => 0,
+                    core::cmp::Ordering::Greater => 0xffff,
+                };
+                wr16(&mut e, off, c);
+            }
+            self.set_entry(pos, &e)?;
+        }
+        let mut e = [0u8; DIRENT_SIZE];
+        e[..11].copy_from_slice(&short);
+        e[ent::ATTR] = attrs;
+        e[ent::NTRES] = ntres;
+        set_created(&mut e);
+        set_first_cluster(&mut e, first);
+        let pos = slots[nlfn];
+        self.set_entry(pos, &e)?;
+        Ok(pos)
+    }
+
+    fn create(&mut self, dir: u64, name: &str, ftype: FileType) -> Result<u64, isize> {
+        let parent = dir;
+        let dir = self.dir(parent)?;
+        let name = encode_name(name)?;
+        match ftype {
+            FileType::File => self.dirlink(dir, &name, 0, attr::ARCHIVE),
+            FileType::Dir => {
+                if self.dirlookup(dir, &name)?.is_some() {
+                    return Err(err::EXIST);
+                }
+                let c = self.alloc_cluster(None, true)?;
+                // The ".." entry holds cluster 0 if the parent is the root.
+                let up = if parent == ROOT_INO { 0 } else { dir };
+                let start = self.cluster_sector(c) as u64 * self.bps() as u64;
+                for (i, (dots, first)) in [(&b"."[..], c), (&b".."[..], up)].into_iter().enumerate()
+                {
+                    let mut e = [b' '; DIRENT_SIZE];
+                    e[11..].fill(0);
+                    e[..dots.len()].copy_from_slice(dots);
+                    e[ent::ATTR] = attr::DIRECTORY;
+                    set_created(&mut e);
+                    set_first_cluster(&mut e, first);
+                    self.set_entry(start + (i * DIRENT_SIZE) as u64, &e)?;
+                }
+                match self.dirlink(dir, &name, c, attr::DIRECTORY) {
+                    Err(e) => {
+                        self.free_chain(c)?;
+                        Err(e)
+                    }
+                    res => res,
+                }
+            }
+            FileType::Device => Err(err::INVAL),
+        }
+    }
+
+    fn is_empty_dir(&mut self, dir: u32) -> Result<bool, isize> {
+        let found = self.walk(dir, |_, e| {
+            if e[0] == END {
+                return Some(false);
+            }
+            (e[0] != DELETED && !is_lfn(e) && !is_dots(e)).then_some(true)
+        })?;
+        Ok(found != Some(true))
+    }
+
+    // Marks the slots of an entry as deleted.
+    fn delete(&mut self, slots: &[u64]) -> Result<(), isize> {
+        for &pos in slots {
+            let mut e = self.entry(pos)?;
+            e[0] = DELETED;
+            self.set_entry(pos, &e)?;
+        }
+        Ok(())
+    }
+
+    // Returns the contents of the slots of an entry, so that they can be restored.
+    fn save(&mut self, slots: &[u64]) -> Result<Vec<[u8; DIRENT_SIZE]>, isize> {
+        let mut saved = Vec::new();
+        saved.try_reserve(slots.len()).map_err(|_| err::NOMEM)?;
+        for &pos in slots {
+            saved.push(self.entry(pos)?);
+        }
+        Ok(saved)
+    }
+
+    fn restore(&mut self, slots: &[u64], saved: &[[u8; DIRENT_SIZE]]) -> Result<(), isize> {
+        for (&pos, e) in slots.iter().zip(saved.iter()) {
+            self.set_entry(pos, e)?;
+        }
+        Ok(())
+    }
+
+    // Checks that the entry 'found' may be removed, and returns its node.
+    fn removable(&mut self, found: &Found) -> Result<Node, isize> {
+        if self.open.contains_key(&found.pos()) {
+            return Err(err::BUSY);
+        }
+        let node = self.node(found.pos())?;
+        if node.dir && !self.is_empty_dir(self.dir_cluster(&node)?)? {
+            return Err(err::NOTEMPTY);
+        }
+        Ok(node)
+    }
+
+    fn unlink(&mut self, dir: u64, name: &str) -> Result<(), isize> {
+        let dir = self.dir(dir)?;
+        let found = self.find(dir, name)?;
+        let node = self.removable(&found)?;
+        // Remove the entry before freeing its clusters, so that a crash cannot leave the entry
+        // referring to free clusters.
+        self.delete(&found.slots)?;
+        self.sync()?;
+        if node.first != 0 {
+            self.free_chain(node.first)?;
+        }
+        Ok(())
+    }
+
+    fn rename(
+        &mut self,
+        olddir: u64,
+        oldname: &str,
+        newdir: u64,
+        newname: &str,
+    ) -> Result<(), isize> {
+        let (oldc, newc) = (self.dir(olddir)?, self.dir(newdir)?);
+        let old = self.find(oldc, oldname)?;
+        // Files are identified by the position of their entry, which changes.
+        if self.open.contains_key(&old.pos()) {
+            return Err(err::BUSY);
+        }
+        let node = self.node(old.pos())?;
+        let name = encode_name(newname)?;
+        let mut replaced = None;
+        match self.dirlookup(newc, &name)? {
+            // Only the case of the name changes.
+            Some(found) if found.pos() == old.pos() => {}
+            Some(found) => {
+                let target = self.node(found.pos())?;
+                match (node.dir, target.dir) {
+                    (false, true) => return Err(err::ISDIR),
+                    (true, false) => return Err(err::NOTDIR),
+                    _ => {}
+                }
+                self.removable(&found)?;
+                let saved = self.save(&found.slots)?;
+                replaced = Some((target, found, saved));
+            }
+            None => {}
+        }
+
+        // Remove the replaced and the old entry first, since the new entry has the name of the
+        // former and may only differ in case from the latter. Both are put back if the new entry
+        // cannot be added.
+        let saved = self.save(&old.slots)?;
+        let e = saved.last().unwrap();
+        let linked = match &replaced {
+            Some((_, found, _)) => self.delete(&found.slots),
+            None => Ok(()),
+        }
+        .and_then(|_| self.delete(&old.slots))
+        .and_then(|_| self.dirlink(newc, &name, node.first, e[ent::ATTR]));
+        let pos = match linked {
+            Ok(pos) => pos,
+            Err(code) => {
+                self.restore(&old.slots, &saved)?;
+                if let Some((_, found, saved)) = &replaced {
+                    self.restore(&found.slots, saved)?;
+                }
+                return Err(code);
+            }
+        };
+        // Keep the timestamps and size of the file.
+        let mut ne = self.entry(pos)?;
+        ne[ent::CRT_TENTH..].copy_from_slice(&e[ent::CRT_TENTH..]);
+        self.set_entry(pos, &ne)?;
+
+        // Point the ".." entry of a moved directory to its new parent.
+        if node.dir && olddir != newdir && node.first != 0 {
+            let up =
+                self.cluster_sector(node.first) as u64 * self.bps() as u64 + DIRENT_SIZE as u64;
+            let mut e = self.entry(up)?;
+            if &e[..11] == b"..         " {
+                set_first_cluster(&mut e, if newdir == ROOT_INO { 0 } else { newc });
+                self.set_entry(up, &e)?;
+            }
+        }
+
+        self.sync()?;
+        if let Some((target, _, _)) = replaced {
+            if target.first != 0 {
+                self.free_chain(target.first)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the entry at or after slot 'pos' of directory 'dir', and the slot following it.
+    fn readdir(&mut self, dir: u64, pos: usize) -> Result<Option<(DirEntry, usize)>, isize> {
+        let dir = self.dir(dir)?;
+        let mut lfn = Lfn::new();
+        let mut slot = 0;
+        let found = self.walk(dir, |ino, e| {
+            slot += 1;
+            if e[0] == END {
+                return Some(None);
+            }
+            if slot <= pos {
+                return None;
+            }
+            if e[0] == DELETED {
+                lfn.reset();
+                return None;
+            }
+            if is_lfn(e) {
+                lfn.add(ino, e);
+                return None;
+            }
+            if e[ent::ATTR] & attr::VOLUME_ID != 0 || is_dots(e) {
+                lfn.reset();
+                return None;
+            }
+            let name = lfn.name(e).map_or_else(|| short_name(e), Vec::from);
+            lfn.reset();
+            let ftype = if e[ent::ATTR] & attr::DIRECTORY != 0 {
+                FileType::Dir
+            } else {
+                FileType::File
+            };
+            Some(Some((ino, ftype, name, slot)))
+        })?;
+        let Some((ino, ftype, name, next)) = found.flatten() else {
+            return Ok(None);
+        };
+        let name = char::decode_utf16(name)
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect();
+        Ok(Some((DirEntry { ino, ftype, name }, next)))
+    }
+
+    // Updates the first cluster and size in the entry of 'node'.
+    fn update(&mut self, node: &Node) -> Result<(), isize> {
+        let mut e = self.entry(node.ino)?;
+        set_first_cluster(&mut e, node.first);
+        wr32(&mut e, ent::SIZE, node.size);
+        e[ent::ATTR] |= attr::ARCHIVE;
+        set_modified(&mut e);
+        self.set_entry(node.ino, &e)
+    }
+
+    fn read(&mut self, ino: u64, off: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let mut node = self.node(ino)?;
+        if node.dir {
+            return Err(err::ISDIR);
+        }
+        let size = node.size as usize;
+        if off >= size {
+            return Ok(0);
+        }
+        let (bps, csize) = (self.bps(), self.csize());
+        let n = min(buf.len(), size - off);
+        let mut cur = self.cluster_at(&mut node.first, off / csize, false)?;
+        let mut done = 0;
+        while done < n {
+            let pos = off + done;
+            if done > 0 && pos.is_multiple_of(csize) {
+                cur = self.step(cur.unwrap(), false, false)?;
+            }
+            // The chain is shorter than the file size.
+            let c = cur.ok_or(err::IO)?;
+            let sec = self.cluster_sector(c) + ((pos % csize) / bps) as u32;
+            let m = min(n - done, bps - pos % bps);
+            buf[done..done + m].copy_from_slice(&self.cache.get(sec)?[pos % bps..pos % bps + m]);
+            done += m;
+        }
+        Ok(n)
+    }
+
+    fn write(&mut self, ino: u64, off: usize, buf: &[u8]) -> Result<usize, isize> {
+        let mut node = self.node(ino)?;
+        if node.dir {
+            return Err(err::ISDIR);
+        }
+        if off > node.size as usize {
+            return Err(err::INVAL);
+        }
+        // Files are limited to 4 GiB - 1.
+        let max = u32::MAX as usize;
+        if off >= max && !buf.is_empty() {
+            return Err(err::FBIG);
+        }
+        let n = min(buf.len(), max - off);
+        let (bps, csize) = (self.bps(), self.csize());
+        let mut cur = None;
+        let mut done = 0;
+        let mut res = Ok(());
+        while done < n {
+            let pos = off + done;
+            let next = match cur {
+                Some(c) if pos.is_multiple_of(csize) => {
+                    self.step(c, true, false).map(|c| c.unwrap())
+                }
+                Some(c) => Ok(c),
+                None => self
+                    .cluster_at(&mut node.first, pos / csize, true)
+                    .map(|c| c.unwrap()),
+            };
+            let c = match next {
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+                Ok(c) => c,
+            };
+            cur = Some(c);
+            let sec = self.cluster_sector(c) + ((pos % csize) / bps) as u32;
+            let m = min(n - done, bps - pos % bps);
+            let b = if m == bps {
+                self.cache.zero(sec)?
+            } else {
+                self.cache.get(sec)?
+            };
+            b[pos % bps..pos % bps + m].copy_from_slice(&buf[done..done + m]);
+            self.cache.flush(sec)?;
+            done += m;
+        }
+        if off + done > node.size as usize {
+            node.size = (off + done) as u32;
+        }
+        // Update the entry even if nothing was written, since clusters may have been allocated.
+        self.update(&node)?;
+        match res {
+            Err(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    fn truncate(&mut self, ino: u64) -> Result<(), isize> {
+        let mut node = self.node(ino)?;
+        if node.dir {
+            return Err(err::ISDIR);
+        }
+        let first = node.first;
+        node.first = 0;
+        node.size = 0;
+        self.update(&node)?;
+        self.sync()?;
+        if first != 0 {
+            self.free_chain(first)?;
+        }
+        Ok(())
+    }
+}
+
+impl Fat32Fs {
+    /// Mounts the FAT32 filesystem on 'dev'.
+    pub fn mount(dev: Arc<dyn BlockDevice>) -> Result<Arc<Self>, isize> {
+        let mut boot = [0u8; SECTOR_SIZE];
+        dev.read(0, &mut boot)?;
+        let bpb = Self::parse_bpb(&boot, dev.nsectors())?;
+        let mut cache = Bcache::new(dev, bpb.bps as usize, NBUF)?;
+
+        let (mut free_count, mut next_free) = (FREE_UNKNOWN, 2);
+        if bpb.fsinfo != 0 {
+            let b = cache.get(bpb.fsinfo)?;
+            if rd32(b, 0) == 0x4161_5252 && rd32(b, 484) == 0x6141_7272 {
+                (free_count, next_free) = (rd32(b, 488), rd32(b, 492));
+            }
+        }
+        if free_count != FREE_UNKNOWN && free_count > bpb.nclusters {
+            free_count = FREE_UNKNOWN;
+        }
+
+        let inner = Inner {
+            cache,
+            bpb,
+            next_free,
+            free_count,
+            dirty: Vec::new(),
+            open: BTreeMap::new(),
+        };
+        Arc::try_new(Self {
+            inner: Mutex::new(inner),
+        })
+        .map_err(|_| err::NOMEM)
+    }
+
+    fn parse_bpb(b: &[u8], nsectors: u64) -> Result<Bpb, isize> {
+        let bps = rd16(b, 11) as u32;
+        let spc = b[13] as u32;
+        let reserved = rd16(b, 14) as u32;
+        let nfats = b[16] as u32;
+        let root_entries = rd16(b, 17);
+        let total16 = rd16(b, 19) as u32;
+        let fat_size16 = rd16(b, 22);
+        let total32 = rd32(b, 32);
+        let fat_size = rd32(b, 36);
+        let ext_flags = rd16(b, 40);
+        let root = rd32(b, 44);
+        let fsinfo = rd16(b, 48) as u32;
+
+        // FAT12 and FAT16 volumes have a FAT size in the old field and a fixed-size root
+        // directory.
+        let valid = (b[0] == 0xeb || b[0] == 0xe9)
+            && b[510] == 0x55
+            && b[511] == 0xaa
+            && bps.is_power_of_two()
+            && (SECTOR_SIZE as u32..=4096).contains(&bps)
+            && spc.is_power_of_two()
+            && reserved > 0
+            && nfats > 0
+            && root_entries == 0
+            && fat_size16 == 0
+            && fat_size > 0;
+        if !valid {
+            return Err(err::INVAL);
+        }
+        let total = if total16 != 0 { total16 } else { total32 } as u64;
+        let data_start = reserved as u64 + nfats as u64 * fat_size as u64;
+        if total <= data_start || total * bps as u64 > nsectors * SECTOR_SIZE as u64 {
+            return Err(err::INVAL);
+        }
+        // The FAT must have an entry for each cluster.
+        let nclusters = ((total - data_start) / spc as u64)
+            .min((fat_size as u64 * bps as u64 / 4).saturating_sub(2))
+            .min(MAXCLUSTERS as u64) as u32;
+        let mirrored = ext_flags & 0x80 == 0;
+        let active = if mirrored {
+            0
+        } else {
+            (ext_flags & 0xf) as u32
+        };
+        let bpb = Bpb {
+            bps,
+            spc,
+            fat_start: reserved,
+            fat_size,
+            nfats,
+            mirrored,
+            active,
+            data_start: data_start as u32,
+            nclusters,
+            root,
+            fsinfo: if fsinfo > 0 && fsinfo < reserved {
+                fsinfo
+            } else {
+                0
+            },
+        };
+        if active >= nfats || root < 2 || root - 2 >= nclusters {
+            return Err(err::INVAL);
+        }
+        Ok(bpb)
+    }
+
+    // Runs 'op' and writes the metadata it modified, even if it fails.
+    fn op<T>(&self, op: impl FnOnce(&mut Inner) -> Result<T, isize>) -> Result<T, isize> {
+        let mut inner = self.inner.lock();
+        let res = op(&mut inner);
+        inner.sync()?;
+        res
+    }
+}
+
+impl FileSystem for Fat32Fs {
+    fn root(&self) -> u64 {
+        ROOT_INO
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, isize> {
+        self.inner.lock().lookup(dir, name)
+    }
+
+    fn parent(&self, dir: u64) -> Result<u64, isize> {
+        self.inner.lock().parent(dir)
+    }
+
+    fn stat(&self, ino: u64) -> Result<Stat, isize> {
+        let node = self.inner.lock().node(ino)?;
+        Ok(Stat {
+            ino,
+            ftype: if node.dir {
+                FileType::Dir
+            } else {
+                FileType::File
+            },
+            nlink: 1,
+            size: node.size as u64,
+        })
+    }
+
+    fn create(&self, dir: u64, name: &str, ftype: FileType) -> Result<u64, isize> {
+        self.op(|inner| inner.create(dir, name, ftype))
+    }
+
+    // Files that are open may not be removed.
+    fn unlink(&self, dir: u64, name: &str) -> Result<(), isize> {
+        self.op(|inner| inner.unlink(dir, name))
+    }
+
+    // Files that are open may not be moved or replaced.
+    fn rename(&self, olddir: u64, oldname: &str, newdir: u64, newname: &str) -> Result<(), isize> {
+        self.op(|inner| inner.rename(olddir, oldname, newdir, newname))
+    }
+
+    fn readdir(&self, dir: u64, pos: usize) -> Result<Option<(DirEntry, usize)>, isize> {
+        self.inner.lock().readdir(dir, pos)
+    }
+
+    fn read(&self, ino: u64, off: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        self.inner.lock().read(ino, off, buf)
+    }
+
+    fn write(&self, ino: u64, off: usize, buf: &[u8]) -> Result<usize, isize> {
+        self.op(|inner| inner.write(ino, off, buf))
+    }
+
+    fn truncate(&self, ino: u64) -> Result<(), isize> {
+        self.op(|inner| inner.truncate(ino))
+    }
+
+    fn open(&self, ino: u64) {
+        *self.inner.lock().open.entry(ino).or_insert(0) += 1;
+    }
+
+    fn close(&self, ino: u64) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        let cnt = inner
+            .open
+            .get_mut(&ino)
+            .expect("closing inode that is not open");
+        *cnt -= 1;
+        if *cnt == 0 {
+            inner.open.remove(&ino);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,172 @@
+use crate::primary::PrimaryCell;
+
+use core::str;
+
+// The initial RAM filesystem is a ustar archive that is linked into the kernel image. Its
+// contents are unpacked into the root ramfs at boot, which refers to the file data in the archive
+// without copying it.
+static ARCHIVE: PrimaryCell<&'static [u8]> = PrimaryCell::new(&[]);
+
+const BLOCKSIZE: usize = 512;
+
+#[repr(C)]
+struct Header {
+    name: [u8; 100],
+    mode: [u8; 8],
+    uid: [u8; 8],
+    gid: [u8; 8],
+    size: [u8; 12],
+    mtime: [u8; 12],
+    chksum: [u8; 8],
+    typeflag: u8,
+    linkname: [u8; 100],
+    magic: [u8; 6],
+    version: [u8; 2],
+    uname: [u8; 32],
+    gname: [u8; 32],
+    devmajor: [u8; 8],
+    devminor: [u8; 8],
+    prefix: [u8; 155],
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Kind {
+    File,
+    Dir,
+    Other,
+}
+
+/// A file or directory in the initial RAM filesystem.
+#[derive(Copy, Clone)]
+pub struct Entry {
+    prefix: &'static str,
+    name: &'static str,
+    pub kind: Kind,
+    pub mode: u32,
+    pub mtime: u64,
+    pub data: &'static [u8],
+}
+
+impl Entry {
+    /// Returns the components of the path of this entry, relative to the root of the archive.
+    pub fn components(&self) -> impl Iterator<Item = &'static str> {
+        self.prefix
+            .split('/')
+            .chain(self.name.split('/'))
+            .filter(|c| !c.is_empty() && *c != ".")
+    }
+}
+
+// Strips "./" and "/" components from the start of 'path' and trailing slashes from the end. The
+// root directory is represented by the empty string.
+fn normalize(mut path: &str) -> &str {
+    loop {
+        if let Some(rest) = path.strip_prefix("./") {
+            path = rest;
+        } else if let Some(rest) = path.strip_prefix('/') {
+            path = rest;
+        } else {
+            break;
+        }
+    }
+    match path.trim_end_matches('/') {
+        "." => "",
+        path => path,
+    }
+}
+
+// Parses a NUL or space terminated octal field.
+fn octal(field: &[u8]) -> Option<u64> {
+    let mut val: u64 = 0;
+    for &c in field.iter().skip_while(|&&c| c == b' ') {
+        match c {
+            b'0'..=b'7' => val = val.checked_mul(8)? + (c - b'0') as u64,
+            b'\0' | b' ' => break,
+            _ => return None,
+        }
+    }
+    Some(val)
+}
+
+// Returns the string stored in a NUL-padded field.
+fn string(field: &'static [u8]) -> Option<&'static str> {
+    let len = field.iter().position(|&c| c == 0).unwrap_or(field.len());
+    str::from_utf8(&field[..len]).ok()
+}
+
+impl Header {
+    // Verifies the header checksum, which is computed with the checksum field set to spaces.
+    fn valid(&self) -> bool {
+        let bytes =
+            unsafe { core::slice::from_raw_parts(self as *const Self as *const u8, BLOCKSIZE) };
+        let sum: u64 = bytes
+            .iter()
+            .enumerate()
+            .map(|(i, &b)| if (148..156).contains(&i) { b' ' } else { b } as u64)
+            .sum();
+        &self.magic[..5] == b"ustar" && octal(&self.chksum) == Some(sum)
+    }
+}
+
+/// Iterator over all entries in the archive.
+pub struct Iter {
+    archive: &'static [u8],
+    off: usize,
+}
+
+impl Iterator for Iter {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        if self.off + BLOCKSIZE > self.archive.len() {
+            return None;
+        }
+        let block: &'static [u8] = &self.archive[self.off..self.off + BLOCKSIZE];
+        let hdr: &'static Header = unsafe { &*(block.as_ptr() as *const Header) };
+        // The archive ends with zero blocks; stop at the first invalid header.
+        if hdr.name[0] == 0 || !hdr.valid() {
+            return None;
+        }
+
+        let size = octal(&hdr.size)? as usize;
+        let start = self.off + BLOCKSIZE;
+        let end = start.checked_add(size)?;
+        if end > self.archive.len() {
+            return None;
+        }
+        self.off = start + size.div_ceil(BLOCKSIZE) * BLOCKSIZE;
+
+        let prefix = normalize(string(&hdr.prefix)?);
+        let name = string(&hdr.name)?;
+        Some(Entry {
+            prefix,
+            name: if prefix.is_empty() {
+                normalize(name)
+            } else {
+                name.trim_end_matches('/')
+            },
+            kind: match hdr.typeflag {
+                b'0' | b'\0' => Kind::File,
+                b'5' => Kind::Dir,
+                _ => Kind::Other,
+            },
+            mode: octal(&hdr.mode)? as u32,
+            mtime: octal(&hdr.mtime)?,
+            data: &self.archive[start..end],
+        })
+    }
+}
+
+/// Installs the archive that backs the initial RAM filesystem. Must be called by the primary core
+/// before the secondary cores are booted.
+pub unsafe fn init(archive: &'static [u8]) {
+    *ARCHIVE.get_mut() = archive;
+}
+
+/// Returns an iterator over all entries in the initial RAM filesystem.
+pub fn entries() -> Iter {
+    Iter {
+        archive: *ARCHIVE,
+        off: 0,
+    }
+}
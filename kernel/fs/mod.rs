@@ -0,0 +1,130 @@
+pub mod bcache;
+pub mod fat32;
+pub mod initrd;
+pub mod part;
+pub mod ramfs;
+pub mod vfs;
+pub mod xv6;
+
+use crate::dev::block::{self, BlockDevice};
+use crate::err;
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+use ramfs::RamFs;
+use vfs::{FileSystem, FileType};
+
+/// Directory on which the first disk filesystem is mounted. Further filesystems are mounted at
+/// DISK_PREFIX followed by a number ("/disk1", "/disk2", ...).
+const DISK_PREFIX: &str = "/disk";
+
+pub(crate) fn rd16(b: &[u8], off: usize) -> u16 {
+    u16::from_le_bytes([b[off], b[off + 1]])
+}
+
+pub(crate) fn wr16(b: &mut [u8], off: usize, val: u16) {
+    b[off..off + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+pub(crate) fn rd32(b: &[u8], off: usize) -> u32 {
+    u32::from_le_bytes([b[off], b[off + 1], b[off + 2], b[off + 3]])
+}
+
+pub(crate) fn wr32(b: &mut [u8], off: usize, val: u32) {
+    b[off..off + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+pub(crate) fn rd64(b: &[u8], off: usize) -> u64 {
+    u64::from_le_bytes(b[off..off + 8].try_into().unwrap())
+}
+
+// Returns the filesystem on 'dev', trying each supported filesystem type.
+fn probe(dev: &Arc<dyn BlockDevice>) -> Option<Arc<dyn FileSystem>> {
+    if let Ok(fs) = xv6::Xv6Fs::mount(dev.clone()) {
+        return Some(fs);
+    }
+    if let Ok(fs) = fat32::Fat32Fs::mount(dev.clone()) {
+        return Some(fs);
+    }
+    None
+}
+
+// Adds the initrd entry 'entry' to the ramfs 'fs', creating missing parent directories.
+fn unpack(fs: &RamFs, entry: &initrd::Entry) -> Result<(), isize> {
+    let mut dir = fs.root();
+    let mut comps = entry.components().peekable();
+    while let Some(name) = comps.next() {
+        if name == ".." {
+            return Err(err::INVAL);
+        }
+        let last = comps.peek().is_none();
+        let ftype = if last && entry.kind == initrd::Kind::File {
+            FileType::File
+        } else {
+            FileType::Dir
+        };
+        let ino = match fs.lookup(dir, name) {
+            Err(err::NOENT) => fs.create(dir, name, ftype)?,
+            res => res?,
+        };
+        if last && ftype == FileType::File {
+            fs.set_static(ino, entry.data)?;
+        }
+        dir = ino;
+    }
+    Ok(())
+}
+
+/// Mounts a ramfs as the root of the tree and fills it with the contents of the initrd.
+pub fn init() {
+    let root = RamFs::new().expect("fs: could not create the root filesystem");
+    vfs::mount_root(root.clone()).expect("fs: could not mount the root filesystem");
+    for entry in initrd::entries() {
+        if entry.kind == initrd::Kind::Other {
+            continue;
+        }
+        if let Err(e) = unpack(&root, &entry) {
+            println!("fs: could not unpack an initrd entry: {}", e);
+        }
+    }
+}
+
+/// Mounts the filesystems found on the block devices, either on the whole device or on its
+/// partitions. The root filesystem must have been mounted.
+pub fn mount_disks() {
+    let (mut n, mut nmounted) = (0, 0);
+    while let Some(dev) = block::get(n) {
+        let vols = match probe(&dev) {
+            Some(fs) => Vec::from([fs]),
+            None => match part::partitions(&dev) {
+                Ok(parts) => parts.iter().filter_map(probe).collect(),
+                Err(e) => {
+                    println!("fs: could not read partitions of disk {}: {}", n, e);
+                    Vec::new()
+                }
+            },
+        };
+        if vols.is_empty() {
+            println!("fs: no filesystem found on disk {}", n);
+        }
+        for fs in vols {
+            let path = match nmounted {
+                0 => String::from(DISK_PREFIX),
+                i => format!("{}{}", DISK_PREFIX, i),
+            };
+            let res = match vfs::mkdir(None, &path) {
+                Ok(()) | Err(err::EXIST) => vfs::mount(&path, fs),
+                Err(e) => Err(e),
+            };
+            match res {
+                Ok(()) => println!("fs: mounted a filesystem from disk {} at {}", n, path),
+                Err(e) => println!("fs: could not mount disk {} at {}: {}", n, path, e),
+            }
+            nmounted += 1;
+        }
+        n += 1;
+    }
+}
@@ -0,0 +1,118 @@
+use crate::dev::block::{BlockDevice, SECTOR_SIZE};
+use crate::err;
+use crate::fs::{rd32, rd64};
+
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// A range of sectors of another block device.
+pub struct Partition {
+    dev: Arc<dyn BlockDevice>,
+    start: u64,
+    nsectors: u64,
+}
+
+impl Partition {
+    // Checks that the transfer of 'len' bytes at 'sector' is within the partition and returns the
+    // sector on the underlying device.
+    fn sector(&self, sector: u64, len: usize) -> Result<u64, isize> {
+        let n = (len / SECTOR_SIZE) as u64;
+        if sector > self.nsectors || n > self.nsectors - sector {
+            return Err(err::IO);
+        }
+        Ok(self.start + sector)
+    }
+}
+
+impl BlockDevice for Partition {
+    fn nsectors(&self) -> u64 {
+        self.nsectors
+    }
+
+    fn read(&self, sector: u64, buf: &mut [u8]) -> Result<(), isize> {
+        self.dev.read(self.sector(sector, buf.len())?, buf)
+    }
+
+    fn write(&self, sector: u64, buf: &[u8]) -> Result<(), isize> {
+        self.dev.write(self.sector(sector, buf.len())?, buf)
+    }
+}
+
+// Partition type of the protective MBR entry that covers a GPT disk.
+const MBR_GPT: u8 = 0xee;
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+// Upper bound on the number of GPT entries that are scanned.
+const GPT_MAXENTRIES: u32 = 128;
+
+// Appends the partition covering sectors 'start' to 'end' (exclusive) of 'dev', ignoring
+// partitions that do not fit on the device.
+fn add_partition(
+    parts: &mut Vec<Arc<dyn BlockDevice>>,
+    dev: &Arc<dyn BlockDevice>,
+    start: u64,
+    end: u64,
+) -> Result<(), isize> {
+    if start == 0 || start >= end || end > dev.nsectors() {
+        return Ok(());
+    }
+    parts.try_reserve(1).map_err(|_| err::NOMEM)?;
+    let part = Arc::try_new(Partition {
+        dev: dev.clone(),
+        start,
+        nsectors: end - start,
+    })
+    .map_err(|_| err::NOMEM)?;
+    parts.push(part);
+    Ok(())
+}
+
+fn gpt_partitions(
+    dev: &Arc<dyn BlockDevice>,
+    parts: &mut Vec<Arc<dyn BlockDevice>>,
+) -> Result<(), isize> {
+    let mut sect = [0u8; SECTOR_SIZE];
+    dev.read(1, &mut sect)?;
+    if &sect[..8] != GPT_SIGNATURE {
+        return Ok(());
+    }
+    let lba = rd64(&sect, 72);
+    let nentries = core::cmp::min(rd32(&sect, 80), GPT_MAXENTRIES);
+    let esize = rd32(&sect, 84) as usize;
+    if !(128..=SECTOR_SIZE).contains(&esize) || !SECTOR_SIZE.is_multiple_of(esize) {
+        return Err(err::INVAL);
+    }
+    let per_sector = (SECTOR_SIZE / esize) as u32;
+    for i in 0..nentries {
+        if i % per_sector == 0 {
+            dev.read(lba + (i / per_sector) as u64, &mut sect)?;
+        }
+        let e = &sect[(i % per_sector) as usize * esize..];
+        // Unused entries have a zero type GUID.
+        if e[..16].iter().all(|&b| b == 0) {
+            continue;
+        }
+        add_partition(parts, dev, rd64(e, 32), rd64(e, 40).saturating_add(1))?;
+    }
+    Ok(())
+}
+
+/// Returns the partitions described by the MBR or GPT partition table of 'dev'. Returns no
+/// partitions if the device is not partitioned.
+pub fn partitions(dev: &Arc<dyn BlockDevice>) -> Result<Vec<Arc<dyn BlockDevice>>, isize> {
+    let mut parts = Vec::new();
+    let mut mbr = [0u8; SECTOR_SIZE];
+    dev.read(0, &mut mbr)?;
+    if mbr[510] != 0x55 || mbr[511] != 0xaa {
+        return Ok(parts);
+    }
+    for i in 0..4 {
+        let e = &mbr[446 + 16 * i..446 + 16 * (i + 1)];
+        let (ptype, start, len) = (e[4], rd32(e, 8) as u64, rd32(e, 12) as u64);
+        match ptype {
+            0 => {}
+            MBR_GPT => return gpt_partitions(dev, &mut parts).map(|_| parts),
+            _ => add_partition(&mut parts, dev, start, start + len)?,
+        }
+    }
+    Ok(parts)
+}
@@ -0,0 +1,362 @@
+use crate::err;
+use crate::fs::vfs::{DirEntry, FileSystem, FileType, Stat};
+use crate::sync::spinlock::SpinLock;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::min;
+
+// A filesystem that keeps its files in memory. The root of the tree is a ramfs, which is filled
+// with the contents of the initrd when the kernel boots. Those files refer to the archive linked
+// into the kernel image, and are only copied once they are modified.
+
+// Maximum length of a name.
+const MAXNAME: usize = 255;
+const ROOT_INO: u64 = 1;
+
+enum Data {
+    Static(&'static [u8]),
+    Owned(Vec<u8>),
+}
+
+impl Data {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Data::Static(b) => b,
+            Data::Owned(v) => v,
+        }
+    }
+}
+
+enum Kind {
+    File(Data),
+    // Directory entries. Removed entries leave an empty slot behind, so that the positions of the
+    // other entries do not change while the directory is being read.
+    Dir(Vec<Option<(String, u64)>>),
+}
+
+struct Node {
+    // Number of directory entries referring to the node.
+    nlink: u32,
+    kind: Kind,
+}
+
+struct Inner {
+    nodes: BTreeMap<u64, Node>,
+    next_ino: u64,
+    // Number of open files for each inode that is open. Removed inodes are freed once they are
+    // no longer open.
+    open: BTreeMap<u64, usize>,
+}
+
+/// A filesystem held in memory. Operations are serialized by a spinlock.
+pub struct RamFs {
+    inner: SpinLock<Inner>,
+}
+
+impl Inner {
+    fn node(&mut self, ino: u64) -> Result<&mut Node, isize> {
+        self.nodes.get_mut(&ino).ok_or(err::NOENT)
+    }
+
+    fn entries(&mut self, dir: u64) -> Result<&mut Vec<Option<(String, u64)>>, isize> {
+        match &mut self.node(dir)?.kind {
+            Kind::Dir(entries) => Ok(entries),
+            Kind::File(_) => Err(err::NOTDIR),
+        }
+    }
+
+    // Returns the slot holding 'name' in directory 'dir'.
+    fn find(&mut self, dir: u64, name: &str) -> Result<usize, isize> {
+        self.entries(dir)?
+            .iter()
+            .position(|e| e.as_ref().is_some_and(|(n, _)| n == name))
+            .ok_or(err::NOENT)
+    }
+
+    fn lookup(&mut self, dir: u64, name: &str) -> Result<u64, isize> {
+        let slot = self.find(dir, name)?;
+        Ok(self.entries(dir)?[slot].as_ref().unwrap().1)
+    }
+
+    // Returns the directory with an entry for directory 'dir'. Directories have no ".." entries,
+    // but only a single entry refers to each of them.
+    fn parent(&mut self, dir: u64) -> Result<u64, isize> {
+        if dir == ROOT_INO {
+            return Ok(ROOT_INO);
+        }
+        self.nodes
+            .iter()
+            .find_map(|(&ino, node)| match &node.kind {
+                Kind::Dir(entries) if entries.iter().flatten().any(|(_, i)| *i == dir) => Some(ino),
+                _ => None,
+            })
+            .ok_or(err::NOENT)
+    }
+
+    // Adds an entry for 'ino' named 'name' to directory 'dir'.
+    fn link(&mut self, dir: u64, name: &str, ino: u64) -> Result<(), isize> {
+        if name.len() > MAXNAME {
+            return Err(err::NAMETOOLONG);
+        }
+        let mut s = String::new();
+        s.try_reserve_exact(name.len()).map_err(|_| err::NOMEM)?;
+        s.push_str(name);
+        let entries = self.entries(dir)?;
+        match entries.iter().position(|e| e.is_none()) {
+            Some(slot) => entries[slot] = Some((s, ino)),
+            None => {
+                entries.try_reserve(1).map_err(|_| err::NOMEM)?;
+                entries.push(Some((s, ino)));
+            }
+        }
+        self.node(ino)?.nlink += 1;
+        Ok(())
+    }
+
+    // Removes the entry in 'slot' of directory 'dir', freeing its node if it is no longer used.
+    fn remove(&mut self, dir: u64, slot: usize) -> Result<(), isize> {
+        let entries = self.entries(dir)?;
+        let (_, ino) = entries[slot].take().unwrap();
+        while entries.last().is_some_and(|e| e.is_none()) {
+            entries.pop();
+        }
+        self.unref(ino)
+    }
+
+    // Drops a link to 'ino', freeing its node if it is no longer used.
+    fn unref(&mut self, ino: u64) -> Result<(), isize> {
+        let node = self.node(ino)?;
+        node.nlink -= 1;
+        if node.nlink == 0 && !self.open.contains_key(&ino) {
+            self.nodes.remove(&ino);
+        }
+        Ok(())
+    }
+
+    fn is_empty_dir(&mut self, ino: u64) -> Result<bool, isize> {
+        Ok(self.entries(ino)?.iter().all(|e| e.is_none()))
+    }
+
+    fn create(&mut self, dir: u64, name: &str, ftype: FileType) -> Result<u64, isize> {
+        match self.find(dir, name) {
+            Ok(_) => return Err(err::EXIST),
+            Err(err::NOENT) => {}
+            Err(e) => return Err(e),
+        }
+        let kind = match ftype {
+            FileType::File => Kind::File(Data::Owned(Vec::new())),
+            FileType::Dir => Kind::Dir(Vec::new()),
+            FileType::Device => return Err(err::INVAL),
+        };
+        let ino = self.next_ino;
+        self.next_ino += 1;
+        self.nodes.insert(ino, Node { nlink: 0, kind });
+        if let Err(e) = self.link(dir, name, ino) {
+            self.nodes.remove(&ino);
+            return Err(e);
+        }
+        Ok(ino)
+    }
+
+    fn unlink(&mut self, dir: u64, name: &str) -> Result<(), isize> {
+        let slot = self.find(dir, name)?;
+        let ino = self.entries(dir)?[slot].as_ref().unwrap().1;
+        if matches!(self.node(ino)?.kind, Kind::Dir(_)) && !self.is_empty_dir(ino)? {
+            return Err(err::NOTEMPTY);
+        }
+        self.remove(dir, slot)
+    }
+
+    fn rename(
+        &mut self,
+        olddir: u64,
+        oldname: &str,
+        newdir: u64,
+        newname: &str,
+    ) -> Result<(), isize> {
+        let ino = self.lookup(olddir, oldname)?;
+        let dir = matches!(self.node(ino)?.kind, Kind::Dir(_));
+        match self.lookup(newdir, newname) {
+            Ok(target) if target == ino => return Ok(()),
+            Ok(target) => {
+                match (dir, matches!(self.node(target)?.kind, Kind::Dir(_))) {
+                    (false, true) => return Err(err::ISDIR),
+                    (true, false) => return Err(err::NOTDIR),
+                    (true, true) if !self.is_empty_dir(target)? => return Err(err::NOTEMPTY),
+                    _ => {}
+                }
+                // Point the existing entry at the file, which cannot fail, before dropping the
+                // link to the target.
+                let slot = self.find(newdir, newname)?;
+                self.entries(newdir)?[slot].as_mut().unwrap().1 = ino;
+                self.node(ino)?.nlink += 1;
+                self.unref(target)?;
+            }
+            Err(err::NOENT) => self.link(newdir, newname, ino)?,
+            Err(e) => return Err(e),
+        }
+        let slot = self.find(olddir, oldname)?;
+        self.remove(olddir, slot)
+    }
+
+    fn readdir(&mut self, dir: u64, pos: usize) -> Result<Option<(DirEntry, usize)>, isize> {
+        let Some((slot, name, ino)) = self
+            .entries(dir)?
+            .iter()
+            .enumerate()
+            .skip(pos)
+            .find_map(|(i, e)| e.as_ref().map(|(name, ino)| (i, name.clone(), *ino)))
+        else {
+            return Ok(None);
+        };
+        let ftype = match self.node(ino)?.kind {
+            Kind::File(_) => FileType::File,
+            Kind::Dir(_) => FileType::Dir,
+        };
+        Ok(Some((DirEntry { ino, ftype, name }, slot + 1)))
+    }
+
+    fn data(&mut self, ino: u64) -> Result<&mut Data, isize> {
+        match &mut self.node(ino)?.kind {
+            Kind::File(data) => Ok(data),
+            Kind::Dir(_) => Err(err::ISDIR),
+        }
+    }
+
+    fn write(&mut self, ino: u64, off: usize, buf: &[u8]) -> Result<usize, isize> {
+        let data = self.data(ino)?;
+        if off > data.bytes().len() {
+            return Err(err::INVAL);
+        }
+        if let Data::Static(b) = *data {
+            let mut v = Vec::new();
+            v.try_reserve_exact(b.len()).map_err(|_| err::NOMEM)?;
+            v.extend_from_slice(b);
+            *data = Data::Owned(v);
+        }
+        let Data::Owned(v) = data else { unreachable!() };
+        let end = off + buf.len();
+        if end > v.len() {
+            v.try_reserve(end - v.len()).map_err(|_| err::NOMEM)?;
+            v.resize(end, 0);
+        }
+        v[off..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+impl RamFs {
+    /// Creates an empty filesystem.
+    pub fn new() -> Result<Arc<Self>, isize> {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(
+            ROOT_INO,
+            Node {
+                nlink: 1,
+                kind: Kind::Dir(Vec::new()),
+            },
+        );
+        let inner = Inner {
+            nodes,
+            next_ino: ROOT_INO + 1,
+            open: BTreeMap::new(),
+        };
+        Arc::try_new(Self {
+            inner: SpinLock::new(inner),
+        })
+        .map_err(|_| err::NOMEM)
+    }
+
+    /// Sets the contents of file 'ino' to 'data', which is not copied unless it is modified.
+    pub fn set_static(&self, ino: u64, data: &'static [u8]) -> Result<(), isize> {
+        *self.inner.lock().data(ino)? = Data::Static(data);
+        Ok(())
+    }
+}
+
+impl FileSystem for RamFs {
+    fn root(&self) -> u64 {
+        ROOT_INO
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, isize> {
+        self.inner.lock().lookup(dir, name)
+    }
+
+    fn parent(&self, dir: u64) -> Result<u64, isize> {
+        self.inner.lock().parent(dir)
+    }
+
+    fn stat(&self, ino: u64) -> Result<Stat, isize> {
+        let mut inner = self.inner.lock();
+        let node = inner.node(ino)?;
+        let (ftype, size) = match &node.kind {
+            Kind::File(data) => (FileType::File, data.bytes().len()),
+            Kind::Dir(entries) => (FileType::Dir, entries.len()),
+        };
+        Ok(Stat {
+            ino,
+            ftype,
+            nlink: node.nlink,
+            size: size as u64,
+        })
+    }
+
+    fn create(&self, dir: u64, name: &str, ftype: FileType) -> Result<u64, isize> {
+        self.inner.lock().create(dir, name, ftype)
+    }
+
+    // Removed files that are still open remain readable until they are closed.
+    fn unlink(&self, dir: u64, name: &str) -> Result<(), isize> {
+        self.inner.lock().unlink(dir, name)
+    }
+
+    fn rename(&self, olddir: u64, oldname: &str, newdir: u64, newname: &str) -> Result<(), isize> {
+        self.inner.lock().rename(olddir, oldname, newdir, newname)
+    }
+
+    fn readdir(&self, dir: u64, pos: usize) -> Result<Option<(DirEntry, usize)>, isize> {
+        self.inner.lock().readdir(dir, pos)
+    }
+
+    fn read(&self, ino: u64, off: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let mut inner = self.inner.lock();
+        let data = inner.data(ino)?.bytes();
+        let data = &data[min(off, data.len())..];
+        let n = min(buf.len(), data.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn write(&self, ino: u64, off: usize, buf: &[u8]) -> Result<usize, isize> {
+        self.inner.lock().write(ino, off, buf)
+    }
+
+    fn truncate(&self, ino: u64) -> Result<(), isize> {
+        *self.inner.lock().data(ino)? = Data::Owned(Vec::new());
+        Ok(())
+    }
+
+    fn open(&self, ino: u64) {
+        *self.inner.lock().open.entry(ino).or_insert(0) += 1;
+    }
+
+    fn close(&self, ino: u64) -> Result<(), isize> {
+        let mut inner = self.inner.lock();
+        let cnt = inner
+            .open
+            .get_mut(&ino)
+            .expect("closing inode that is not open");
+        *cnt -= 1;
+        if *cnt == 0 {
+            inner.open.remove(&ino);
+            if inner.nodes.get(&ino).is_some_and(|n| n.nlink == 0) {
+                inner.nodes.remove(&ino);
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,553 @@
+use crate::err;
+use crate::file::{flags, File};
+use crate::proc::Proc;
+use crate::sync::mutex::Mutex;
+use crate::sync::spinlock::SpinLock;
+
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+// The virtual filesystem joins the mounted filesystems into a single tree. Filesystems implement
+// FileSystem, which names files by inode numbers and looks up names one directory at a time.
+// Paths are resolved here into dentries, which record the path taken to reach an inode, so that
+// ".." can leave a mounted filesystem through the directory it is mounted on.
+
+/// Types of files.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum FileType {
+    File,
+    Dir,
+    Device,
+}
+
+/// Metadata of a file.
+pub struct Stat {
+    pub ino: u64,
+    pub ftype: FileType,
+    pub nlink: u32,
+    pub size: u64,
+}
+
+/// An entry read from a directory.
+pub struct DirEntry {
+    pub ino: u64,
+    pub ftype: FileType,
+    pub name: String,
+}
+
+/// A mounted filesystem. Files are identified by inode numbers, which are only meaningful to the
+/// filesystem that returned them. Names passed to the filesystem are single path components,
+/// which are never "." or "..".
+pub trait FileSystem: Send + Sync {
+    /// Returns the inode number of the root directory.
+    fn root(&self) -> u64;
+
+    /// Returns the inode number of 'name' in directory 'dir'.
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, isize>;
+
+    /// Returns the inode number of the directory containing directory 'dir'. The root is its own
+    /// parent.
+    fn parent(&self, dir: u64) -> Result<u64, isize>;
+
+    /// Returns the metadata of inode 'ino'.
+    fn stat(&self, ino: u64) -> Result<Stat, isize>;
+
+    /// Creates a file of type 'ftype' named 'name' in directory 'dir' and returns its inode
+    /// number.
+    fn create(&self, dir: u64, name: &str, ftype: FileType) -> Result<u64, isize>;
+
+    /// Removes the file or empty directory 'name' from directory 'dir'.
+    fn unlink(&self, dir: u64, name: &str) -> Result<(), isize>;
+
+    /// Moves 'oldname' in directory 'olddir' to 'newname' in directory 'newdir', replacing
+    /// 'newname' if it exists (and is a file, or an empty directory if the moved file is a
+    /// directory). The caller checks that a directory is not moved into itself.
+    fn rename(&self, olddir: u64, oldname: &str, newdir: u64, newname: &str) -> Result<(), isize>;
+
+    /// Returns the entry at position 'pos' of directory 'dir' and the position of the next entry,
+    /// or None past the end. Position 0 is the first entry. The "." and ".." entries are not
+    /// returned.
+    fn readdir(&self, dir: u64, pos: usize) -> Result<Option<(DirEntry, usize)>, isize>;
+
+    /// Reads from inode 'ino' at offset 'off'. Returns 0 at end of file.
+    fn read(&self, ino: u64, off: usize, buf: &mut [u8]) -> Result<usize, isize>;
+
+    /// Writes to inode 'ino' at offset 'off', growing the file if necessary. The offset may not
+    /// be past the end of the file.
+    fn write(&self, ino: u64, off: usize, buf: &[u8]) -> Result<usize, isize>;
+
+    /// Truncates inode 'ino' to zero length.
+    fn truncate(&self, ino: u64) -> Result<(), isize>;
+
+    /// Records that inode 'ino' has been opened.
+    fn open(&self, ino: u64);
+
+    /// Records that an open file for inode 'ino' was closed.
+    fn close(&self, ino: u64) -> Result<(), isize>;
+}
+
+/// A file on a mounted filesystem.
+#[derive(Clone)]
+pub struct Inode {
+    pub fs: Arc<dyn FileSystem>,
+    pub ino: u64,
+}
+
+impl Inode {
+    /// Returns true if both refer to the same file.
+    pub fn is(&self, other: &Inode) -> bool {
+        same_fs(&self.fs, &other.fs) && self.ino == other.ino
+    }
+
+    /// Returns the metadata of the file.
+    pub fn stat(&self) -> Result<Stat, isize> {
+        self.fs.stat(self.ino)
+    }
+
+    // Returns true if this directory is 'dir' or contains it. The filesystem's ".." entries are
+    // followed, since dentries may be out of date after a rename.
+    fn contains(&self, dir: &Inode) -> Result<bool, isize> {
+        if !same_fs(&self.fs, &dir.fs) {
+            return Ok(false);
+        }
+        let root = self.fs.root();
+        let mut cur = dir.ino;
+        loop {
+            if cur == self.ino {
+                return Ok(true);
+            }
+            if cur == root {
+                return Ok(false);
+            }
+            cur = self.fs.parent(cur)?;
+        }
+    }
+}
+
+fn same_fs(a: &Arc<dyn FileSystem>, b: &Arc<dyn FileSystem>) -> bool {
+    core::ptr::eq(Arc::as_ptr(a) as *const u8, Arc::as_ptr(b) as *const u8)
+}
+
+/// A resolved path: an inode along with the directory it was reached from. The inode is open
+/// while the dentry exists, so that it is not freed or reused while it is in use (for example as
+/// a working directory).
+pub struct Dentry {
+    pub inode: Inode,
+    // Directory containing this entry, or None for the root of the tree. The root of a mounted
+    // filesystem has the directory containing the mount point as its parent.
+    parent: Option<Arc<Dentry>>,
+}
+
+impl Dentry {
+    fn new(inode: Inode, parent: Option<Arc<Dentry>>) -> Result<Arc<Dentry>, isize> {
+        inode.fs.open(inode.ino);
+        Arc::try_new(Dentry { inode, parent }).map_err(|_| err::NOMEM)
+    }
+}
+
+impl Drop for Dentry {
+    fn drop(&mut self) {
+        if let Err(e) = self.inode.fs.close(self.inode.ino) {
+            println!("fs: error closing inode {}: {}", self.inode.ino, e);
+        }
+    }
+}
+
+struct Mount {
+    // Directory the filesystem is mounted on.
+    at: Inode,
+    root: Inode,
+}
+
+struct Mounts {
+    root: Option<Arc<Dentry>>,
+    mounts: Vec<Mount>,
+}
+
+static MOUNTS: SpinLock<Mounts> = SpinLock::new(Mounts {
+    root: None,
+    mounts: Vec::new(),
+});
+
+/// Returns the root of the tree, if a root filesystem has been mounted.
+pub fn root() -> Option<Arc<Dentry>> {
+    MOUNTS.lock().root.clone()
+}
+
+/// Makes 'fs' the root of the tree.
+pub fn mount_root(fs: Arc<dyn FileSystem>) -> Result<(), isize> {
+    let ino = fs.root();
+    let d = Dentry::new(Inode { fs, ino }, None)?;
+    MOUNTS.lock().root = Some(d);
+    Ok(())
+}
+
+/// Mounts 'fs' on the directory at 'path', hiding its contents.
+pub fn mount(path: &str, fs: Arc<dyn FileSystem>) -> Result<(), isize> {
+    let d = resolve(None, path)?;
+    if d.inode.stat()?.ftype != FileType::Dir {
+        return Err(err::NOTDIR);
+    }
+    let mut m = MOUNTS.lock();
+    if m.mounts.iter().any(|mnt| mnt.at.is(&d.inode)) {
+        return Err(err::BUSY);
+    }
+    m.mounts.try_reserve(1).map_err(|_| err::NOMEM)?;
+    let ino = fs.root();
+    m.mounts.push(Mount {
+        at: d.inode.clone(),
+        root: Inode { fs, ino },
+    });
+    Ok(())
+}
+
+// Returns the root of the filesystem mounted on 'inode', if there is one.
+fn mounted_on(inode: &Inode) -> Option<Inode> {
+    let m = MOUNTS.lock();
+    m.mounts
+        .iter()
+        .find(|mnt| mnt.at.is(inode))
+        .map(|mnt| mnt.root.clone())
+}
+
+fn is_mount_root(d: &Dentry) -> bool {
+    d.parent.is_none() || MOUNTS.lock().mounts.iter().any(|mnt| mnt.root.is(&d.inode))
+}
+
+// Returns the entry 'name' in directory 'dir'.
+fn step(dir: &Arc<Dentry>, name: &str) -> Result<Arc<Dentry>, isize> {
+    match name {
+        "." => return Ok(dir.clone()),
+        ".." => return Ok(dir.parent.clone().unwrap_or_else(|| dir.clone())),
+        _ => {}
+    }
+    let ino = dir.inode.fs.lookup(dir.inode.ino, name)?;
+    let mut inode = Inode {
+        fs: dir.inode.fs.clone(),
+        ino,
+    };
+    if let Some(root) = mounted_on(&inode) {
+        inode = root;
+    }
+    Dentry::new(inode, Some(dir.clone()))
+}
+
+fn start(cwd: Option<&Arc<Dentry>>, path: &str) -> Result<Arc<Dentry>, isize> {
+    match cwd {
+        Some(cwd) if !path.starts_with('/') => Ok(cwd.clone()),
+        _ => root().ok_or(err::NOENT),
+    }
+}
+
+/// Resolves 'path', which is relative to 'cwd' (or the root if there is none) unless it is
+/// absolute.
+pub fn resolve(cwd: Option<&Arc<Dentry>>, path: &str) -> Result<Arc<Dentry>, isize> {
+    if path.is_empty() {
+        return Err(err::NOENT);
+    }
+    let mut d = start(cwd, path)?;
+    for name in path.split('/').filter(|c| !c.is_empty()) {
+        d = step(&d, name)?;
+    }
+    Ok(d)
+}
+
+// Resolves the directory containing the last component of 'path', and returns it along with the
+// component, which may not be "." or "..".
+fn resolve_parent<'a>(
+    cwd: Option<&Arc<Dentry>>,
+    path: &'a str,
+) -> Result<(Arc<Dentry>, &'a str), isize> {
+    let path = path.trim_end_matches('/');
+    let (dir, name) = match path.rfind('/') {
+        Some(i) => (&path[..i + 1], &path[i + 1..]),
+        None => ("", path),
+    };
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(if path.is_empty() {
+            err::NOENT
+        } else {
+            err::INVAL
+        });
+    }
+    let mut d = start(cwd, path)?;
+    for c in dir.split('/').filter(|c| !c.is_empty()) {
+        d = step(&d, c)?;
+    }
+    let st = d.inode.stat()?;
+    if st.ftype != FileType::Dir {
+        return Err(err::NOTDIR);
+    }
+    // Nothing may be added to a directory that has been removed, but is still in use.
+    if st.nlink == 0 {
+        return Err(err::NOENT);
+    }
+    Ok((d, name))
+}
+
+/// Opens the file at 'path' with the given open flags.
+pub fn open(cwd: Option<&Arc<Dentry>>, path: &str, oflags: usize) -> Result<Arc<dyn File>, isize> {
+    let d = match resolve(cwd, path) {
+        Ok(_) if oflags & flags::O_CREAT != 0 && oflags & flags::O_EXCL != 0 => {
+            return Err(err::EXIST)
+        }
+        Ok(d) => d,
+        Err(err::NOENT) if oflags & flags::O_CREAT != 0 => {
+            let (dir, name) = resolve_parent(cwd, path)?;
+            match dir.inode.fs.create(dir.inode.ino, name, FileType::File) {
+                // Somebody else created the file in the meantime.
+                Err(err::EXIST) if oflags & flags::O_EXCL == 0 => {}
+                res => {
+                    res?;
+                }
+            }
+            step(&dir, name)?
+        }
+        Err(e) => return Err(e),
+    };
+
+    let accmode = oflags & flags::O_ACCMODE;
+    let readable = accmode == flags::O_RDONLY || accmode == flags::O_RDWR;
+    let writable = accmode == flags::O_WRONLY || accmode == flags::O_RDWR;
+    let stat = d.inode.stat()?;
+    if stat.ftype == FileType::Dir && writable {
+        return Err(err::ISDIR);
+    }
+    if writable && oflags & flags::O_TRUNC != 0 {
+        d.inode.fs.truncate(d.inode.ino)?;
+    }
+    let append = oflags & flags::O_APPEND != 0;
+    let f = Arc::try_new(InodeFile::new(
+        d,
+        stat.ftype == FileType::Dir,
+        readable,
+        writable,
+        append,
+    ))
+    .map_err(|_| err::NOMEM)?;
+    Ok(f)
+}
+
+/// Returns the metadata of the file at 'path'.
+pub fn stat(cwd: Option<&Arc<Dentry>>, path: &str) -> Result<Stat, isize> {
+    resolve(cwd, path)?.inode.stat()
+}
+
+/// Creates a directory at 'path'.
+pub fn mkdir(cwd: Option<&Arc<Dentry>>, path: &str) -> Result<(), isize> {
+    let (dir, name) = match resolve_parent(cwd, path) {
+        // "." and ".." always exist.
+        Err(err::INVAL) => return Err(err::EXIST),
+        res => res?,
+    };
+    dir.inode.fs.create(dir.inode.ino, name, FileType::Dir)?;
+    Ok(())
+}
+
+/// Removes the file at 'path', or the empty directory at 'path' if 'dir' is true.
+pub fn unlink(cwd: Option<&Arc<Dentry>>, path: &str, dir: bool) -> Result<(), isize> {
+    let (parent, name) = resolve_parent(cwd, path)?;
+    let d = step(&parent, name)?;
+    if is_mount_root(&d) {
+        return Err(err::BUSY);
+    }
+    match (d.inode.stat()?.ftype == FileType::Dir, dir) {
+        (true, false) => return Err(err::ISDIR),
+        (false, true) => return Err(err::NOTDIR),
+        _ => {}
+    }
+    // Close the file, so that it only remains open if it is in use elsewhere.
+    drop(d);
+    parent.inode.fs.unlink(parent.inode.ino, name)
+}
+
+// Serializes renames, so that two renames cannot together move a directory into itself.
+static RENAME: Mutex<()> = Mutex::new(());
+
+/// Moves the file at 'oldpath' to 'newpath'. Both must be on the same filesystem.
+pub fn rename(cwd: Option<&Arc<Dentry>>, oldpath: &str, newpath: &str) -> Result<(), isize> {
+    let _rename = RENAME.lock();
+    let (olddir, oldname) = resolve_parent(cwd, oldpath)?;
+    let (newdir, newname) = resolve_parent(cwd, newpath)?;
+    let old = step(&olddir, oldname)?;
+    if !same_fs(&olddir.inode.fs, &newdir.inode.fs) {
+        return Err(err::XDEV);
+    }
+    if is_mount_root(&old) {
+        return Err(err::BUSY);
+    }
+    if let Ok(new) = step(&newdir, newname) {
+        if is_mount_root(&new) {
+            return Err(err::BUSY);
+        }
+    }
+    // A directory cannot be moved into itself.
+    if old.inode.stat()?.ftype == FileType::Dir && old.inode.contains(&newdir.inode)? {
+        return Err(err::INVAL);
+    }
+    // Close the file, so that it only remains open if it is in use elsewhere.
+    drop(old);
+    olddir
+        .inode
+        .fs
+        .rename(olddir.inode.ino, oldname, newdir.inode.ino, newname)
+}
+
+/// Resolves 'path' as a new working directory.
+pub fn chdir(cwd: Option<&Arc<Dentry>>, path: &str) -> Result<Arc<Dentry>, isize> {
+    let d = resolve(cwd, path)?;
+    if d.inode.stat()?.ftype != FileType::Dir {
+        return Err(err::NOTDIR);
+    }
+    Ok(d)
+}
+
+/// Reads the whole file at 'path' into memory.
+pub fn read_file(cwd: Option<&Arc<Dentry>>, path: &str) -> Result<Vec<u8>, isize> {
+    let d = resolve(cwd, path)?;
+    let stat = d.inode.stat()?;
+    if stat.ftype == FileType::Dir {
+        return Err(err::ISDIR);
+    }
+    let mut data = Vec::new();
+    data.try_reserve_exact(stat.size as usize)
+        .map_err(|_| err::NOMEM)?;
+    data.resize(stat.size as usize, 0);
+    let mut n = 0;
+    while n < data.len() {
+        match d.inode.fs.read(d.inode.ino, n, &mut data[n..])? {
+            0 => break,
+            m => n += m,
+        }
+    }
+    data.truncate(n);
+    Ok(data)
+}
+
+// Types in the d_type field of directory entries.
+mod dtype {
+    pub const CHR: u8 = 2;
+    pub const DIR: u8 = 4;
+    pub const REG: u8 = 8;
+}
+
+// Size of the fixed part of a directory entry returned by getdents: d_ino (u64), d_off (i64),
+// d_reclen (u16) and d_type (u8), followed by the NUL-terminated name. Entries are 8-byte
+// aligned, as for Linux's getdents64.
+const DIRENT_HDR: usize = 19;
+
+// Encodes a directory entry into 'buf'. Returns the size of the entry, or None if it does not
+// fit.
+fn put_dirent(buf: &mut [u8], ino: u64, next: usize, ftype: FileType, name: &str) -> Option<usize> {
+    let reclen = (DIRENT_HDR + name.len() + 1).next_multiple_of(8);
+    if reclen > buf.len() {
+        return None;
+    }
+    let d = &mut buf[..reclen];
+    d.fill(0);
+    d[0..8].copy_from_slice(&ino.to_le_bytes());
+    d[8..16].copy_from_slice(&(next as u64).to_le_bytes());
+    d[16..18].copy_from_slice(&(reclen as u16).to_le_bytes());
+    d[18] = match ftype {
+        FileType::File => dtype::REG,
+        FileType::Dir => dtype::DIR,
+        FileType::Device => dtype::CHR,
+    };
+    d[DIRENT_HDR..DIRENT_HDR + name.len()].copy_from_slice(name.as_bytes());
+    Some(reclen)
+}
+
+/// An open file on a mounted filesystem.
+pub struct InodeFile {
+    dentry: Arc<Dentry>,
+    dir: bool,
+    readable: bool,
+    writable: bool,
+    append: bool,
+    // Offset in the file, or for directories the position of the next entry, where positions 0
+    // and 1 are "." and "..", and position n + 2 is position n of the filesystem.
+    off: Mutex<usize>,
+}
+
+impl InodeFile {
+    pub fn new(
+        dentry: Arc<Dentry>,
+        dir: bool,
+        readable: bool,
+        writable: bool,
+        append: bool,
+    ) -> Self {
+        Self {
+            dentry,
+            dir,
+            readable,
+            writable,
+            append,
+            off: Mutex::new(0),
+        }
+    }
+
+    fn inode(&self) -> &Inode {
+        &self.dentry.inode
+    }
+}
+
+impl File for InodeFile {
+    fn read(&self, _p: &mut Proc, buf: &mut [u8]) -> Result<usize, isize> {
+        if self.dir {
+            return Err(err::ISDIR);
+        }
+        if !self.readable {
+            return Err(err::BADF);
+        }
+        let mut off = self.off.lock();
+        let n = self.inode().fs.read(self.inode().ino, *off, buf)?;
+        *off += n;
+        Ok(n)
+    }
+
+    fn write(&self, _p: &mut Proc, buf: &[u8]) -> Result<usize, isize> {
+        if !self.writable {
+            return Err(err::BADF);
+        }
+        let mut off = self.off.lock();
+        if self.append {
+            *off = self.inode().stat()?.size as usize;
+        }
+        let n = self.inode().fs.write(self.inode().ino, *off, buf)?;
+        *off += n;
+        Ok(n)
+    }
+
+    fn getdents(&self, _p: &mut Proc, buf: &mut [u8]) -> Result<usize, isize> {
+        if !self.dir {
+            return Err(err::NOTDIR);
+        }
+        let parent = self.dentry.parent.as_ref().unwrap_or(&self.dentry);
+        let mut off = self.off.lock();
+        let mut n = 0;
+        loop {
+            let entry;
+            let (ino, ftype, name, next) = match *off {
+                0 => (self.inode().ino, FileType::Dir, ".", 1),
+                1 => (parent.inode.ino, FileType::Dir, "..", 2),
+                pos => match self.inode().fs.readdir(self.inode().ino, pos - 2)? {
+                    None => break,
+                    Some((e, next)) => {
+                        entry = e;
+                        (entry.ino, entry.ftype, entry.name.as_str(), next + 2)
+                    }
+                },
+            };
+            match put_dirent(&mut buf[n..], ino, next, ftype, name) {
+                // The buffer must have room for at least one entry.
+                None if n == 0 => return Err(err::INVAL),
+                None => break,
+                Some(len) => n += len,
+            }
+            *off = next;
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,120 @@
+use super::{rd32, wr32, BSIZE};
+use crate::err;
+use crate::fs::bcache::Bcache;
+
+use alloc::vec::Vec;
+
+/// Write-ahead log. Blocks modified by a filesystem operation are recorded in the log (and stay
+/// pinned in the buffer cache) instead of being written in place. On commit, the blocks are
+/// first copied into the log area on disk, then the log header is written (the commit point),
+/// and finally the blocks are installed at their home locations. A crash before the commit point
+/// loses the operation; a crash after it is repaired by replaying the log during recovery.
+///
+/// On-disk layout: a header block at 'start' holding the number of logged blocks followed by
+/// their block numbers, then 'size - 1' blocks of logged data.
+pub struct Log {
+    start: u32,
+    size: u32,
+    blocks: Vec<u32>,
+}
+
+impl Log {
+    pub fn new(start: u32, size: u32) -> Self {
+        Self {
+            start,
+            size,
+            blocks: Vec::new(),
+        }
+    }
+
+    // Maximum number of blocks in a transaction.
+    fn capacity(&self) -> usize {
+        core::cmp::min(self.size as usize - 1, BSIZE / 4 - 1)
+    }
+
+    fn read_head(&self, cache: &mut Bcache) -> Result<Vec<u32>, isize> {
+        let head = cache.get(self.start)?;
+        let n = rd32(head, 0) as usize;
+        if n > BSIZE / 4 - 1 {
+            return Err(err::IO);
+        }
+        Ok((0..n).map(|i| rd32(head, 4 + 4 * i)).collect())
+    }
+
+    fn write_head(&self, cache: &mut Bcache, blocks: &[u32]) -> Result<(), isize> {
+        let head = cache.zero(self.start)?;
+        wr32(head, 0, blocks.len() as u32);
+        for (i, b) in blocks.iter().enumerate() {
+            wr32(head, 4 + 4 * i, *b);
+        }
+        cache.flush(self.start)
+    }
+
+    // Copies block 'from' to block 'to' and writes 'to' to disk.
+    fn copy(cache: &mut Bcache, from: u32, to: u32) -> Result<(), isize> {
+        let data = cache.get(from)?.to_vec();
+        cache.zero(to)?.copy_from_slice(&data);
+        cache.flush(to)
+    }
+
+    /// Installs any committed transaction that was not fully installed before a crash.
+    pub fn recover(&mut self, cache: &mut Bcache) -> Result<(), isize> {
+        let blocks = self.read_head(cache)?;
+        if blocks.len() > self.capacity() {
+            return Err(err::IO);
+        }
+        for (i, b) in blocks.iter().enumerate() {
+            Self::copy(cache, self.start + 1 + i as u32, *b)?;
+        }
+        self.write_head(cache, &[])
+    }
+
+    /// Records that 'blockno' was modified by the current transaction. The modification must
+    /// already be made in the buffer cache.
+    pub fn write(&mut self, cache: &mut Bcache, blockno: u32) -> Result<(), isize> {
+        if self.blocks.contains(&blockno) {
+            return Ok(());
+        }
+        if self.blocks.len() >= self.capacity() {
+            // The modification cannot be logged, so it must not reach the disk.
+            cache.invalidate(blockno);
+            return Err(err::NOSPC);
+        }
+        cache.pin(blockno, true)?;
+        self.blocks.push(blockno);
+        Ok(())
+    }
+
+    /// Abandons the current transaction. Its blocks are dropped from the cache, so that they are
+    /// read back from disk unmodified.
+    pub fn abort(&mut self, cache: &mut Bcache) {
+        for b in self.blocks.drain(..) {
+            cache.invalidate(b);
+        }
+    }
+
+    /// Commits the current transaction. The transaction is over even if this fails: it is
+    /// abandoned if the failure is before the commit point, and otherwise installed by recovery.
+    pub fn commit(&mut self, cache: &mut Bcache) -> Result<(), isize> {
+        if self.blocks.is_empty() {
+            return Ok(());
+        }
+        let logged = self
+            .blocks
+            .iter()
+            .enumerate()
+            .try_for_each(|(i, b)| Self::copy(cache, *b, self.start + 1 + i as u32))
+            .and_then(|_| self.write_head(cache, &self.blocks));
+        if let Err(e) = logged {
+            self.abort(cache);
+            return Err(e);
+        }
+        // Install (and unpin) every block even if some fail, and only clear the header once all
+        // of them are on disk.
+        let mut res = Ok(());
+        for b in self.blocks.drain(..) {
+            res = res.and(cache.flush(b)).and(cache.pin(b, false));
+        }
+        res.and_then(|_| self.write_head(cache, &[]))
+    }
+}
@@ -0,0 +1,671 @@
+mod log;
+
+use crate::dev::block::BlockDevice;
+use crate::err;
+use crate::fs::bcache::Bcache;
+use crate::fs::vfs::{DirEntry, FileSystem, FileType, Stat};
+use crate::fs::{rd16, rd32, wr16, wr32};
+use crate::sync::mutex::Mutex;
+
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use core::cmp::min;
+use core::str;
+
+use log::Log;
+
+// An xv6-style filesystem. Disk layout, in blocks of BSIZE bytes:
+//
+// [ boot | superblock | log | inodes | free bitmap | data ]
+//
+// Block 0 is unused, block 1 holds the superblock, which describes where the other regions
+// start. Files are described by inodes, which hold direct block pointers and a single indirect
+// block. Directories are files containing fixed-size entries. All updates go through the log.
+
+pub const BSIZE: usize = 1024;
+const FSMAGIC: u32 = 0x1020_3040;
+const NDIRECT: usize = 12;
+const NINDIRECT: usize = BSIZE / 4;
+const MAXFILE: usize = NDIRECT + NINDIRECT;
+const DINODE_SIZE: usize = 64;
+const IPB: u32 = (BSIZE / DINODE_SIZE) as u32;
+const BPB: u32 = (BSIZE * 8) as u32;
+/// Maximum length of a directory entry name.
+pub const DIRSIZ: usize = 14;
+const DIRENT_SIZE: usize = 2 + DIRSIZ;
+const ROOTINO: u32 = 1;
+const NBUF: usize = 64;
+// Maximum number of blocks written by a single operation, which bounds the size of writes that
+// are done in one transaction.
+const MAXOPBLOCKS: usize = 10;
+
+/// Inode types.
+pub mod kind {
+    pub const DIR: u16 = 1;
+    pub const FILE: u16 = 2;
+    pub const DEVICE: u16 = 3;
+}
+
+struct Superblock {
+    size: u32,
+    ninodes: u32,
+    nlog: u32,
+    logstart: u32,
+    inodestart: u32,
+    bmapstart: u32,
+}
+
+// On-disk inode.
+#[derive(Copy, Clone, Default)]
+struct Dinode {
+    kind: u16,
+    major: u16,
+    minor: u16,
+    nlink: u16,
+    size: u32,
+    addrs: [u32; NDIRECT + 1],
+}
+
+impl Dinode {
+    fn decode(b: &[u8]) -> Self {
+        let mut addrs = [0; NDIRECT + 1];
+        for (i, a) in addrs.iter_mut().enumerate() {
+            *a = rd32(b, 12 + 4 * i);
+        }
+        Self {
+            kind: rd16(b, 0),
+            major: rd16(b, 2),
+            minor: rd16(b, 4),
+            nlink: rd16(b, 6),
+            size: rd32(b, 8),
+            addrs,
+        }
+    }
+
+    fn encode(&self, b: &mut [u8]) {
+        wr16(b, 0, self.kind);
+        wr16(b, 2, self.major);
+        wr16(b, 4, self.minor);
+        wr16(b, 6, self.nlink);
+        wr32(b, 8, self.size);
+        for (i, a) in self.addrs.iter().enumerate() {
+            wr32(b, 12 + 4 * i, *a);
+        }
+    }
+}
+
+// On-disk directory entry.
+struct Dirent {
+    ino: u32,
+    name: [u8; DIRSIZ],
+}
+
+impl Dirent {
+    fn name(&self) -> &[u8] {
+        let len = self.name.iter().position(|&c| c == 0).unwrap_or(DIRSIZ);
+        &self.name[..len]
+    }
+}
+
+struct Inner {
+    cache: Bcache,
+    log: Log,
+    sb: Superblock,
+    // Number of open files for each inode that is open. Inodes that are unlinked while open are
+    // only freed once they are closed.
+    open: BTreeMap<u32, usize>,
+}
+
+/// A mounted xv6 filesystem. Operations are serialized by a single sleeping lock, and each
+/// operation that modifies the filesystem is committed to the log before it returns.
+pub struct Xv6Fs {
+    inner: Mutex<Inner>,
+}
+
+impl Inner {
+    fn inode_block(&self, ino: u32) -> u32 {
+        self.sb.inodestart + ino / IPB
+    }
+
+    fn iget(&mut self, ino: u32) -> Result<Dinode, isize> {
+        if ino == 0 || ino >= self.sb.ninodes {
+            return Err(err::IO);
+        }
+        let blk = self.inode_block(ino);
+        let off = (ino % IPB) as usize * DINODE_SIZE;
+        Ok(Dinode::decode(
+            &self.cache.get(blk)?[off..off + DINODE_SIZE],
+        ))
+    }
+
+    fn iupdate(&mut self, ino: u32, din: &Dinode) -> Result<(), isize> {
+        let blk = self.inode_block(ino);
+        let off = (ino % IPB) as usize * DINODE_SIZE;
+        din.encode(&mut self.cache.get(blk)?[off..off + DINODE_SIZE]);
+        self.log.write(&mut self.cache, blk)
+    }
+
+    // Allocates a new inode of the given kind.
+    fn ialloc(&mut self, kind: u16) -> Result<u32, isize> {
+        for ino in 1..self.sb.ninodes {
+            if self.iget(ino)?.kind == 0 {
+                let din = Dinode {
+                    kind,
+                    ..Default::default()
+                };
+                self.iupdate(ino, &din)?;
+                return Ok(ino);
+            }
+        }
+        Err(err::NOSPC)
+    }
+
+    // Allocates a zeroed disk block.
+    fn balloc(&mut self) -> Result<u32, isize> {
+        for base in (0..self.sb.size).step_by(BPB as usize) {
+            let bmap = self.sb.bmapstart + base / BPB;
+            let bits = self.cache.get(bmap)?;
+            let free = (0..min(BPB, self.sb.size - base))
+                .find(|&bi| bits[bi as usize / 8] & (1 << (bi % 8)) == 0);
+            if let Some(bi) = free {
+                bits[bi as usize / 8] |= 1 << (bi % 8);
+                self.log.write(&mut self.cache, bmap)?;
+                let b = base + bi;
+                self.cache.zero(b)?;
+                self.log.write(&mut self.cache, b)?;
+                return Ok(b);
+            }
+        }
+        Err(err::NOSPC)
+    }
+
+    fn bfree(&mut self, b: u32) -> Result<(), isize> {
+        let bmap = self.sb.bmapstart + b / BPB;
+        let bi = b % BPB;
+        let bits = self.cache.get(bmap)?;
+        assert!(
+            bits[bi as usize / 8] & (1 << (bi % 8)) != 0,
+            "freeing free block"
+        );
+        bits[bi as usize / 8] &= !(1 << (bi % 8));
+        self.log.write(&mut self.cache, bmap)
+    }
+
+    // Returns the disk block that holds block 'bn' of the inode, or 0 if it is not allocated. If
+    // 'alloc' is true, missing blocks are allocated (the caller must then update the inode).
+    fn bmap(&mut self, din: &mut Dinode, bn: usize, alloc: bool) -> Result<u32, isize> {
+        if bn < NDIRECT {
+            if din.addrs[bn] == 0 && alloc {
+                din.addrs[bn] = self.balloc()?;
+            }
+            return Ok(din.addrs[bn]);
+        }
+        let bn = bn - NDIRECT;
+        if bn >= NINDIRECT {
+            return Err(err::FBIG);
+        }
+        if din.addrs[NDIRECT] == 0 {
+            if !alloc {
+                return Ok(0);
+            }
+            din.addrs[NDIRECT] = self.balloc()?;
+        }
+        let ind = din.addrs[NDIRECT];
+        let mut b = rd32(self.cache.get(ind)?, 4 * bn);
+        if b == 0 && alloc {
+            b = self.balloc()?;
+            wr32(self.cache.get(ind)?, 4 * bn, b);
+            self.log.write(&mut self.cache, ind)?;
+        }
+        Ok(b)
+    }
+
+    // Frees all data blocks of the inode.
+    fn itrunc(&mut self, ino: u32, din: &mut Dinode) -> Result<(), isize> {
+        for i in 0..NDIRECT {
+            if din.addrs[i] != 0 {
+                self.bfree(din.addrs[i])?;
+                din.addrs[i] = 0;
+            }
+        }
+        if din.addrs[NDIRECT] != 0 {
+            let ind = din.addrs[NDIRECT];
+            for i in 0..NINDIRECT {
+                let b = rd32(self.cache.get(ind)?, 4 * i);
+                if b != 0 {
+                    self.bfree(b)?;
+                }
+            }
+            self.bfree(ind)?;
+            din.addrs[NDIRECT] = 0;
+        }
+        din.size = 0;
+        self.iupdate(ino, din)
+    }
+
+    fn readi(&mut self, ino: u32, off: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        let mut din = self.iget(ino)?;
+        let size = din.size as usize;
+        if off >= size {
+            return Ok(0);
+        }
+        let n = min(buf.len(), size - off);
+        let mut done = 0;
+        while done < n {
+            let pos = off + done;
+            let m = min(n - done, BSIZE - pos % BSIZE);
+            let b = self.bmap(&mut din, pos / BSIZE, false)?;
+            let dst = &mut buf[done..done + m];
+            if b == 0 {
+                dst.fill(0);
+            } else {
+                dst.copy_from_slice(&self.cache.get(b)?[pos % BSIZE..pos % BSIZE + m]);
+            }
+            done += m;
+        }
+        Ok(n)
+    }
+
+    fn writei(&mut self, ino: u32, off: usize, buf: &[u8]) -> Result<usize, isize> {
+        let mut din = self.iget(ino)?;
+        if off > din.size as usize {
+            return Err(err::INVAL);
+        }
+        if off + buf.len() > MAXFILE * BSIZE {
+            return Err(err::FBIG);
+        }
+        let mut done = 0;
+        let mut res = Ok(());
+        while done < buf.len() {
+            let pos = off + done;
+            let m = min(buf.len() - done, BSIZE - pos % BSIZE);
+            let b = match self.bmap(&mut din, pos / BSIZE, true) {
+                Err(e) => {
+                    res = Err(e);
+                    break;
+                }
+                Ok(b) => b,
+            };
+            self.cache.get(b)?[pos % BSIZE..pos % BSIZE + m].copy_from_slice(&buf[done..done + m]);
+            self.log.write(&mut self.cache, b)?;
+            done += m;
+        }
+        if off + done > din.size as usize {
+            din.size = (off + done) as u32;
+        }
+        // Write back the inode even if nothing was written, since bmap may have allocated blocks.
+        self.iupdate(ino, &din)?;
+        match res {
+            Err(e) if done == 0 => Err(e),
+            _ => Ok(done),
+        }
+    }
+
+    fn dirent(&mut self, dir: u32, off: usize) -> Result<Dirent, isize> {
+        let mut de = [0u8; DIRENT_SIZE];
+        if self.readi(dir, off, &mut de)? != DIRENT_SIZE {
+            return Err(err::IO);
+        }
+        let mut name = [0; DIRSIZ];
+        name.copy_from_slice(&de[2..]);
+        Ok(Dirent {
+            ino: rd16(&de, 0) as u32,
+            name,
+        })
+    }
+
+    // Looks up 'name' in directory 'dir'. Returns the inode number and the offset of the entry.
+    fn dirlookup(&mut self, dir: u32, name: &[u8]) -> Result<Option<(u32, usize)>, isize> {
+        let size = self.iget(dir)?.size as usize;
+        for off in (0..size).step_by(DIRENT_SIZE) {
+            let de = self.dirent(dir, off)?;
+            if de.ino != 0 && de.name() == name {
+                return Ok(Some((de.ino, off)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn dirlink(&mut self, dir: u32, name: &[u8], ino: u32) -> Result<(), isize> {
+        if name.len() > DIRSIZ {
+            return Err(err::NAMETOOLONG);
+        }
+        if self.dirlookup(dir, name)?.is_some() {
+            return Err(err::EXIST);
+        }
+        let size = self.iget(dir)?.size as usize;
+        let mut off = size;
+        for o in (0..size).step_by(DIRENT_SIZE) {
+            if self.dirent(dir, o)?.ino == 0 {
+                off = o;
+                break;
+            }
+        }
+        let mut de = [0u8; DIRENT_SIZE];
+        wr16(&mut de, 0, ino as u16);
+        de[2..2 + name.len()].copy_from_slice(name);
+        self.writei(dir, off, &de)?;
+        Ok(())
+    }
+
+    fn is_empty_dir(&mut self, dir: u32) -> Result<bool, isize> {
+        let size = self.iget(dir)?.size as usize;
+        // Skip "." and "..".
+        for off in (2 * DIRENT_SIZE..size).step_by(DIRENT_SIZE) {
+            if self.dirent(dir, off)?.ino != 0 {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Looks up 'name' in directory 'dir', checking that 'dir' is a directory.
+    fn lookup(&mut self, dir: u32, name: &[u8]) -> Result<Option<(u32, usize)>, isize> {
+        if self.iget(dir)?.kind != kind::DIR {
+            return Err(err::NOTDIR);
+        }
+        if name.len() > DIRSIZ {
+            return Err(err::NAMETOOLONG);
+        }
+        self.dirlookup(dir, name)
+    }
+
+    fn create(&mut self, dir: u32, name: &[u8], kind: u16) -> Result<u32, isize> {
+        if self.lookup(dir, name)?.is_some() {
+            return Err(err::EXIST);
+        }
+        let ino = self.ialloc(kind)?;
+        let mut din = self.iget(ino)?;
+        din.nlink = 1;
+        self.iupdate(ino, &din)?;
+
+        if kind == kind::DIR {
+            self.dirlink(ino, b".", ino)?;
+            self.dirlink(ino, b"..", dir)?;
+            // The new directory's ".." refers to the parent.
+            let mut ddin = self.iget(dir)?;
+            ddin.nlink += 1;
+            self.iupdate(dir, &ddin)?;
+        }
+        self.dirlink(dir, name, ino)?;
+        Ok(ino)
+    }
+
+    // Frees the inode if it has no links and is not open.
+    fn maybe_free(&mut self, ino: u32) -> Result<(), isize> {
+        let mut din = self.iget(ino)?;
+        if din.nlink == 0 && !self.open.contains_key(&ino) {
+            self.itrunc(ino, &mut din)?;
+            din.kind = 0;
+            self.iupdate(ino, &din)?;
+        }
+        Ok(())
+    }
+
+    // Removes the entry at offset 'off' of directory 'dir', which refers to 'ino'.
+    fn remove(&mut self, dir: u32, off: usize, ino: u32) -> Result<(), isize> {
+        let mut din = self.iget(ino)?;
+        if din.kind == kind::DIR && !self.is_empty_dir(ino)? {
+            return Err(err::NOTEMPTY);
+        }
+        self.writei(dir, off, &[0u8; DIRENT_SIZE])?;
+        if din.kind == kind::DIR {
+            // Drop the link from the directory's ".." and its own ".".
+            let mut ddin = self.iget(dir)?;
+            ddin.nlink -= 1;
+            self.iupdate(dir, &ddin)?;
+            din.nlink = 0;
+        } else {
+            din.nlink -= 1;
+        }
+        self.iupdate(ino, &din)?;
+        self.maybe_free(ino)
+    }
+
+    fn unlink(&mut self, dir: u32, name: &[u8]) -> Result<(), isize> {
+        let (ino, off) = self.lookup(dir, name)?.ok_or(err::NOENT)?;
+        self.remove(dir, off, ino)
+    }
+
+    fn rename(
+        &mut self,
+        olddir: u32,
+        oldname: &[u8],
+        newdir: u32,
+        newname: &[u8],
+    ) -> Result<(), isize> {
+        let (ino, off) = self.lookup(olddir, oldname)?.ok_or(err::NOENT)?;
+        let isdir = self.iget(ino)?.kind == kind::DIR;
+        match self.lookup(newdir, newname)? {
+            Some((target, _)) if target == ino => return Ok(()),
+            Some((target, toff)) => {
+                match (isdir, self.iget(target)?.kind == kind::DIR) {
+                    (false, true) => return Err(err::ISDIR),
+                    (true, false) => return Err(err::NOTDIR),
+                    _ => {}
+                }
+                self.remove(newdir, toff, target)?;
+            }
+            None => {}
+        }
+        self.dirlink(newdir, newname, ino)?;
+        self.writei(olddir, off, &[0u8; DIRENT_SIZE])?;
+        if isdir && olddir != newdir {
+            // Point ".." (the second entry) at the new parent and move its link.
+            let mut de = [0u8; DIRENT_SIZE];
+            wr16(&mut de, 0, newdir as u16);
+            de[2..4].copy_from_slice(b"..");
+            self.writei(ino, DIRENT_SIZE, &de)?;
+            let mut din = self.iget(olddir)?;
+            din.nlink -= 1;
+            self.iupdate(olddir, &din)?;
+            let mut din = self.iget(newdir)?;
+            din.nlink += 1;
+            self.iupdate(newdir, &din)?;
+        }
+        Ok(())
+    }
+}
+
+impl Xv6Fs {
+    /// Mounts the filesystem on 'dev', replaying the log if the filesystem was not cleanly
+    /// unmounted.
+    pub fn mount(dev: Arc<dyn BlockDevice>) -> Result<Arc<Self>, isize> {
+        let mut cache = Bcache::new(dev, BSIZE, NBUF)?;
+        let sbb = cache.get(1)?;
+        if rd32(sbb, 0) != FSMAGIC {
+            return Err(err::INVAL);
+        }
+        let sb = Superblock {
+            size: rd32(sbb, 4),
+            ninodes: rd32(sbb, 12),
+            nlog: rd32(sbb, 16),
+            logstart: rd32(sbb, 20),
+            inodestart: rd32(sbb, 24),
+            bmapstart: rd32(sbb, 28),
+        };
+        if sb.size as u64 > cache.nblocks() || (sb.nlog as usize) < MAXOPBLOCKS + 1 {
+            return Err(err::INVAL);
+        }
+        // Directory entries hold 16-bit inode numbers.
+        if sb.ninodes > u16::MAX as u32 + 1 {
+            return Err(err::INVAL);
+        }
+        let mut log = Log::new(sb.logstart, sb.nlog);
+        log.recover(&mut cache)?;
+        Arc::try_new(Self {
+            inner: Mutex::new(Inner {
+                cache,
+                log,
+                sb,
+                open: BTreeMap::new(),
+            }),
+        })
+        .map_err(|_| err::NOMEM)
+    }
+
+    // Runs 'op' as a single transaction. If the operation fails, none of its changes are kept.
+    fn transaction<T>(&self, op: impl FnOnce(&mut Inner) -> Result<T, isize>) -> Result<T, isize> {
+        let mut inner = self.inner.lock();
+        let res = op(&mut inner);
+        let inner = &mut *inner;
+        match res {
+            Ok(_) => inner.log.commit(&mut inner.cache)?,
+            Err(_) => inner.log.abort(&mut inner.cache),
+        }
+        res
+    }
+}
+
+fn ftype(kind: u16) -> FileType {
+    match kind {
+        kind::DIR => FileType::Dir,
+        kind::DEVICE => FileType::Device,
+        _ => FileType::File,
+    }
+}
+
+// Converts an inode number from the VFS to an xv6 inode number.
+fn ino32(ino: u64) -> Result<u32, isize> {
+    u32::try_from(ino).map_err(|_| err::INVAL)
+}
+
+impl FileSystem for Xv6Fs {
+    fn root(&self) -> u64 {
+        ROOTINO as u64
+    }
+
+    fn lookup(&self, dir: u64, name: &str) -> Result<u64, isize> {
+        let mut inner = self.inner.lock();
+        match inner.lookup(ino32(dir)?, name.as_bytes())? {
+            None => Err(err::NOENT),
+            Some((ino, _)) => Ok(ino as u64),
+        }
+    }
+
+    fn parent(&self, dir: u64) -> Result<u64, isize> {
+        let mut inner = self.inner.lock();
+        match inner.lookup(ino32(dir)?, b"..")? {
+            None => Err(err::NOENT),
+            Some((ino, _)) => Ok(ino as u64),
+        }
+    }
+
+    fn stat(&self, ino: u64) -> Result<Stat, isize> {
+        let din = self.inner.lock().iget(ino32(ino)?)?;
+        Ok(Stat {
+            ino,
+            ftype: ftype(din.kind),
+            nlink: din.nlink as u32,
+            size: din.size as u64,
+        })
+    }
+
+    fn create(&self, dir: u64, name: &str, ftype: FileType) -> Result<u64, isize> {
+        let dir = ino32(dir)?;
+        let kind = match ftype {
+            FileType::File => kind::FILE,
+            FileType::Dir => kind::DIR,
+            FileType::Device => return Err(err::INVAL),
+        };
+        Ok(self.transaction(|inner| inner.create(dir, name.as_bytes(), kind))? as u64)
+    }
+
+    // The inode is freed once it has no links and is no longer open.
+    fn unlink(&self, dir: u64, name: &str) -> Result<(), isize> {
+        let dir = ino32(dir)?;
+        self.transaction(|inner| inner.unlink(dir, name.as_bytes()))
+    }
+
+    fn rename(&self, olddir: u64, oldname: &str, newdir: u64, newname: &str) -> Result<(), isize> {
+        let (olddir, newdir) = (ino32(olddir)?, ino32(newdir)?);
+        self.transaction(|inner| {
+            inner.rename(olddir, oldname.as_bytes(), newdir, newname.as_bytes())
+        })
+    }
+
+    // Positions are indices of directory entries.
+    fn readdir(&self, dir: u64, pos: usize) -> Result<Option<(DirEntry, usize)>, isize> {
+        let dir = ino32(dir)?;
+        let mut inner = self.inner.lock();
+        let din = inner.iget(dir)?;
+        if din.kind != kind::DIR {
+            return Err(err::NOTDIR);
+        }
+        for idx in pos..din.size as usize / DIRENT_SIZE {
+            let de = inner.dirent(dir, idx * DIRENT_SIZE)?;
+            if de.ino == 0 || de.name() == b"." || de.name() == b".." {
+                continue;
+            }
+            let name = str::from_utf8(de.name()).map_err(|_| err::IO)?;
+            let mut s = String::new();
+            s.try_reserve_exact(name.len()).map_err(|_| err::NOMEM)?;
+            s.push_str(name);
+            let entry = DirEntry {
+                ino: de.ino as u64,
+                ftype: ftype(inner.iget(de.ino)?.kind),
+                name: s,
+            };
+            return Ok(Some((entry, idx + 1)));
+        }
+        Ok(None)
+    }
+
+    fn read(&self, ino: u64, off: usize, buf: &mut [u8]) -> Result<usize, isize> {
+        self.inner.lock().readi(ino32(ino)?, off, buf)
+    }
+
+    // Large writes are split over several transactions so that each fits in the log.
+    fn write(&self, ino: u64, off: usize, buf: &[u8]) -> Result<usize, isize> {
+        let ino = ino32(ino)?;
+        // Blocks per transaction: leave room for the inode, an indirect block and two bitmap
+        // blocks, and account for unaligned writes touching an extra block.
+        let max = ((MAXOPBLOCKS - 1 - 1 - 2) / 2) * BSIZE;
+        let mut done = 0;
+        while done < buf.len() {
+            let n = min(buf.len() - done, max);
+            let w = match self
+                .transaction(|inner| inner.writei(ino, off + done, &buf[done..done + n]))
+            {
+                Err(e) if done == 0 => return Err(e),
+                Err(_) => break,
+                Ok(w) => w,
+            };
+            done += w;
+            if w < n {
+                break;
+            }
+        }
+        Ok(done)
+    }
+
+    fn truncate(&self, ino: u64) -> Result<(), isize> {
+        let ino = ino32(ino)?;
+        self.transaction(|inner| {
+            let mut din = inner.iget(ino)?;
+            inner.itrunc(ino, &mut din)
+        })
+    }
+
+    fn open(&self, ino: u64) {
+        *self.inner.lock().open.entry(ino as u32).or_insert(0) += 1;
+    }
+
+    // Frees the inode if this was the last reference to an unlinked inode.
+    fn close(&self, ino: u64) -> Result<(), isize> {
+        let ino = ino32(ino)?;
+        self.transaction(|inner| {
+            let cnt = inner
+                .open
+                .get_mut(&ino)
+                .expect("closing inode that is not open");
+            *cnt -= 1;
+            if *cnt == 0 {
+                inner.open.remove(&ino);
+                inner.maybe_free(ino)?;
+            }
+            Ok(())
+        })
+    }
+}
@@ -1,4 +1,5 @@
 mod kr;
+pub mod pgref;
 
 use crate::sync::spinlock::SpinLock;
 use crate::sys;
@@ -0,0 +1,53 @@
+use crate::board::machine;
+use crate::sys;
+use crate::vm::ka2pa;
+
+use core::sync::atomic::{AtomicU16, Ordering};
+
+const NPAGES: usize = machine::MAIN_MEMORY.size / sys::PAGESIZE;
+
+// Per-page reference counts for pages that are mapped by more than one pagetable (for example
+// after a copy-on-write fork). The count stores the number of references in addition to the
+// first one, so that newly allocated pages (which have a single owner) do not need to be
+// registered here.
+static REFS: [AtomicU16; NPAGES] = [const { AtomicU16::new(0) }; NPAGES];
+
+fn refcnt(pa: usize) -> &'static AtomicU16 {
+    assert!(pa >= machine::MAIN_MEMORY.start);
+    &REFS[(pa - machine::MAIN_MEMORY.start) / sys::PAGESIZE]
+}
+
+/// Records an additional reference to the page at physical address 'pa'.
+pub fn dup(pa: usize) {
+    let old = refcnt(pa).fetch_add(1, Ordering::Relaxed);
+    assert!(old != u16::MAX);
+}
+
+/// Drops a reference to the page at physical address 'pa'. Returns true if this was the last
+/// reference, in which case the caller is responsible for freeing the page.
+pub fn put(pa: usize) -> bool {
+    let cnt = refcnt(pa);
+    let mut old = cnt.load(Ordering::Acquire);
+    loop {
+        if old == 0 {
+            return true;
+        }
+        match cnt.compare_exchange_weak(old, old - 1, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => return false,
+            Err(cur) => old = cur,
+        }
+    }
+}
+
+/// Returns true if the page at physical address 'pa' only has a single reference.
+pub fn unique(pa: usize) -> bool {
+    refcnt(pa).load(Ordering::Acquire) == 0
+}
+
+/// Drops a reference to the page at kernel address 'pg' and frees the page if it was the last
+/// reference.
+pub unsafe fn kfree_pg(pg: *mut u8) {
+    if put(ka2pa(pg as usize)) {
+        super::kfree(pg as *mut [u8; sys::PAGESIZE]);
+    }
+}
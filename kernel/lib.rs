@@ -24,14 +24,21 @@ pub mod arch;
 pub mod bit;
 pub mod board;
 pub mod builtin;
+pub mod console;
 pub mod cpu;
 pub mod crc;
 pub mod dev;
 pub mod elf;
+pub mod err;
+pub mod exec;
+pub mod file;
+pub mod fs;
 pub mod kalloc;
+pub mod pipe;
 pub mod primary;
 pub mod proc;
 pub mod schedule;
+pub mod signal;
 pub mod start;
 pub mod sync;
 pub mod sys;
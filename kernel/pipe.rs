@@ -0,0 +1,130 @@
+use crate::err;
+use crate::file::File;
+use crate::proc::Proc;
+use crate::schedule::{Queue, QueueType};
+use crate::sync::spinlock::SpinLock;
+
+use alloc::boxed::Box;
+use alloc::sync::Arc;
+use core::cmp::min;
+
+/// Capacity of a pipe, in bytes.
+pub const PIPESIZE: usize = 4096;
+
+// Bytes in [r, w) are buffered. Indices increase monotonically and wrap around the buffer.
+struct State {
+    buf: Box<[u8]>,
+    r: usize,
+    w: usize,
+    // False once the read or write end has been closed.
+    reading: bool,
+    writing: bool,
+}
+
+// Lock order: state, then readers or writers.
+struct Pipe {
+    state: SpinLock<State>,
+    // Processes waiting for data, and for free space.
+    readers: SpinLock<Queue>,
+    writers: SpinLock<Queue>,
+}
+
+/// The read end of a pipe.
+pub struct PipeReader(Arc<Pipe>);
+
+/// The write end of a pipe.
+pub struct PipeWriter(Arc<Pipe>);
+
+/// Creates a pipe and returns its read and write ends.
+pub fn new() -> Result<(Arc<PipeReader>, Arc<PipeWriter>), isize> {
+    let buf = Box::<[u8]>::try_new_zeroed_slice(PIPESIZE)
+        .map(|b| unsafe { b.assume_init() })
+        .map_err(|_| err::NOMEM)?;
+    let pipe = Arc::try_new(Pipe {
+        state: SpinLock::new(State {
+            buf,
+            r: 0,
+            w: 0,
+            reading: true,
+            writing: true,
+        }),
+        readers: SpinLock::new(Queue::new(QueueType::Pipe)),
+        writers: SpinLock::new(Queue::new(QueueType::Pipe)),
+    })
+    .map_err(|_| err::NOMEM)?;
+    let r = Arc::try_new(PipeReader(pipe.clone())).map_err(|_| err::NOMEM)?;
+    let w = Arc::try_new(PipeWriter(pipe)).map_err(|_| err::NOMEM)?;
+    Ok((r, w))
+}
+
+impl File for PipeReader {
+    /// Reads the buffered data, blocking until there is some. Returns 0 once the buffer is empty
+    /// and the write end is closed, and fails with INTR if a signal arrives while waiting.
+    fn read(&self, p: &mut Proc, buf: &mut [u8]) -> Result<usize, isize> {
+        let mut st = self.0.state.lock();
+        while st.r == st.w && st.writing {
+            p.block_intr(&self.0.readers, st)?;
+            st = self.0.state.lock();
+        }
+
+        let n = min(buf.len(), st.w - st.r);
+        for b in buf[..n].iter_mut() {
+            *b = st.buf[st.r % PIPESIZE];
+            st.r += 1;
+        }
+        self.0.writers.lock().wake_all();
+        Ok(n)
+    }
+}
+
+impl File for PipeWriter {
+    /// Writes all of 'buf', blocking while the buffer is full. Fails with PIPE if the read end is
+    /// closed, or with INTR if a signal arrives, before anything was written.
+    fn write(&self, p: &mut Proc, buf: &[u8]) -> Result<usize, isize> {
+        let mut st = self.0.state.lock();
+        let mut n = 0;
+        while n < buf.len() {
+            if !st.reading {
+                if n == 0 {
+                    return Err(err::PIPE);
+                }
+                break;
+            }
+            if st.w - st.r == PIPESIZE {
+                self.0.readers.lock().wake_all();
+                match p.block_intr(&self.0.writers, st) {
+                    Err(e) if n == 0 => return Err(e),
+                    Err(_) => return Ok(n),
+                    Ok(()) => {}
+                }
+                st = self.0.state.lock();
+                continue;
+            }
+            let m = min(buf.len() - n, PIPESIZE - (st.w - st.r));
+            for &b in buf[n..n + m].iter() {
+                let w = st.w;
+                st.buf[w % PIPESIZE] = b;
+                st.w += 1;
+            }
+            n += m;
+        }
+        self.0.readers.lock().wake_all();
+        Ok(n)
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut st = self.0.state.lock();
+        st.reading = false;
+        self.0.writers.lock().wake_all();
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        let mut st = self.0.state.lock();
+        st.writing = false;
+        self.0.readers.lock().wake_all();
+    }
+}
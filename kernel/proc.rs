@@ -1,18 +1,124 @@
 use crate::arch::regs::Context;
 use crate::arch::trap::{usertrapret, Trapframe};
-use crate::arch::vm::{kernel_procmap, Pagetable};
-use crate::elf;
-use crate::kalloc::{kallocpage, kfree, zalloc, zallocpage};
-use crate::schedule::{Queue, QueueType};
+use crate::arch::vm::{kernel_procmap, vm_fence, vm_install, Pagetable, PtLevel};
+use crate::console::Console;
+use crate::err;
+use crate::exec;
+use crate::file::{FdTable, File};
+use crate::fs::vfs::{self, Dentry};
+use crate::kalloc::pgref;
+use crate::kalloc::zalloc;
+use crate::schedule::{self, Queue, QueueType, SchedData, EXIT_QUEUE, WAIT_QUEUE};
+use crate::signal::{self, Signals};
+use crate::sync::spinlock::{Guard, SpinLock};
 use crate::sys;
-use crate::vm::{perm, PageMap, PtIter};
+use crate::vm::{free_upages, perm, PtIter};
 
 use alloc::boxed::Box;
+use alloc::sync::Arc;
 use core::ptr::{addr_of_mut, null_mut};
 
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// PID of the init process, which is the first process started and inherits orphaned processes.
+pub const INIT_PID: u32 = 1;
+/// Maximum number of processes, including exited processes that have not been waited for.
+pub const NPROC: usize = 64;
+// PIDs are allocated in increasing order and wrap around after PID_MAX, so that the PID of a freed
+// process is not reused right away.
+const PID_MAX: u32 = 32767;
+
+/// Table of all processes that have not been freed yet, which is independent of the queues that
+/// the processes are on.
+pub struct ProcTable {
+    // Free slots are null.
+    procs: [*mut Proc; NPROC],
+    next_pid: u32,
+}
+
+unsafe impl Send for ProcTable {}
+
+static PROCS: SpinLock<ProcTable> = SpinLock::new(ProcTable {
+    procs: [null_mut(); NPROC],
+    next_pid: INIT_PID,
+});
+
+/// Locks and returns the process table. Processes in the table are not freed while it is locked.
+/// Lock order: EXIT_QUEUE, then the process table.
+pub fn table() -> Guard<'static, ProcTable> {
+    PROCS.lock()
+}
+
+impl ProcTable {
+    /// Returns the process with the given PID.
+    pub fn get(&self, pid: u32) -> Option<*mut Proc> {
+        self.iter().find(|&p| unsafe { (*p).data.pid } == pid)
+    }
+
+    /// Returns an iterator over all processes in the table.
+    pub fn iter(&self) -> impl Iterator<Item = *mut Proc> + '_ {
+        self.procs.iter().copied().filter(|p| !p.is_null())
+    }
+
+    // Adds 'p' to the table and assigns it a free PID. Fails with AGAIN if the table is full.
+    fn insert(&mut self, p: *mut Proc) -> Result<(), isize> {
+        let slot = self
+            .procs
+            .iter()
+            .position(|p| p.is_null())
+            .ok_or(err::AGAIN)?;
+        let next = |pid: u32| if pid >= PID_MAX { INIT_PID } else { pid + 1 };
+        // There are fewer processes than PIDs, so this finds a free one.
+        let mut pid = self.next_pid;
+        while self.get(pid).is_some() {
+            pid = next(pid);
+        }
+        self.next_pid = next(pid);
+        unsafe { (*p).data.pid = pid };
+        self.procs[slot] = p;
+        Ok(())
+    }
+
+    fn remove(&mut self, p: *mut Proc) {
+        if let Some(slot) = self.procs.iter().position(|&q| q == p) {
+            self.procs[slot] = null_mut();
+        }
+    }
+}
 
-static NEXTPID: AtomicU32 = AtomicU32::new(1);
+/// Prints a listing of all processes to the console.
+pub fn dump() {
+    let procs = table();
+    println!(
+        "{:>5} {:>5} {:<16} {:>4} {:>8} NAME",
+        "PID", "PPID", "STATE", "NICE", "TIME(ms)"
+    );
+    for p in procs.iter() {
+        let d = unsafe { &(*p).data };
+        // The parent cannot be freed before it has handed its children over to init, which
+        // requires the table to be locked.
+        let ppid = if d.parent.is_null() {
+            0
+        } else {
+            unsafe { (*d.parent).data.pid }
+        };
+        let state = match d.state {
+            _ if d.on_cpu.load(Ordering::Relaxed) => "running",
+            ProcState::Runnable => "runnable",
+            ProcState::Blocked => d.wq.map_or("blocked", |q| q.name()),
+            ProcState::Exited => "zombie",
+        };
+        println!(
+            "{:>5} {:>5} {:<16} {:>4} {:>8} {}",
+            d.pid,
+            ppid,
+            state,
+            d.sched.nice(),
+            d.sched.runtime / 1000,
+            d.name()
+        );
+    }
+}
 
 #[derive(PartialEq)]
 pub enum ProcState {
@@ -24,10 +130,25 @@ pub enum ProcState {
 /// Process metadata.
 pub struct ProcData {
     pub pid: u32,
+    // Name of the program, padded with zeros.
+    pub name: [u8; 16],
     pub pt: Box<Pagetable>,
-    pub nchild: usize,
+    // Parent process, which is changed to init if the parent exits first. It is only modified
+    // with both EXIT_QUEUE and the process table locked.
     pub parent: *mut Proc,
+    // Start of the heap and current program break.
+    pub start_brk: usize,
+    pub brk: usize,
+    pub files: FdTable,
+    // Current working directory, against which relative paths are resolved.
+    pub cwd: Option<Arc<Dentry>>,
     pub wq: Option<QueueType>,
+    // Wait status reported to the parent once this process has exited.
+    pub xstatus: i32,
+    // Time at which a sleeping process is woken up (see timer::sleep).
+    pub wakeup: u64,
+    pub signals: Signals,
+    pub sched: SchedData,
 
     // Context for kernel context switches.
     pub context: Context,
@@ -37,6 +158,9 @@ pub struct ProcData {
     pub prev: *mut Proc,
 
     pub state: ProcState,
+    // True while the process is executing on some core, until it has fully switched back to that
+    // core's scheduler.
+    pub on_cpu: AtomicBool,
 }
 
 /// Process struct, which contains the process metadata, trapframe information, and the kernel
@@ -54,47 +178,73 @@ pub struct Proc {
 #[repr(align(16))]
 struct KStack([u8; 3008]);
 
+impl ProcData {
+    /// Returns the name of the program that the process runs.
+    pub fn name(&self) -> &str {
+        let n = self
+            .name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.name.len());
+        core::str::from_utf8(&self.name[..n]).unwrap_or("?")
+    }
+}
+
 impl Proc {
     /// Virtual address of the user stack.
     pub const STACK_VA: usize = 0x7fff0000;
     /// Size of a user stack.
     pub const STACK_SIZE: usize = sys::PAGESIZE;
+    /// Virtual address of the read-only time page (see timer::TimePage), above the stack. Also
+    /// defined as TIME_VA in syslib.
+    pub const TIME_VA: usize = Self::STACK_VA + Self::STACK_SIZE;
     /// Maximum virtual address that a user process can access.
-    pub const MAX_VA: usize = Self::STACK_VA + Self::STACK_SIZE;
+    pub const MAX_VA: usize = Self::TIME_VA + sys::PAGESIZE;
     /// Stack canary.
     pub const CANARY: u64 = 0xfeedface_deadbeef;
 
-    /// Constructs a new empty process. This process is given an empty pagetable with only the
-    /// kernel mappings, and a valid kernel context that initializes the process to return into
-    /// Proc::forkret.
-    fn new_empty() -> Option<Box<Proc>> {
+    /// Constructs a new empty child of 'parent' (which may be null). This process is given an
+    /// empty pagetable with only the kernel mappings, and a valid kernel context that initializes
+    /// the process to return into Proc::forkret. Fails with AGAIN if there are too many processes.
+    fn new_empty(parent: *mut Proc) -> Result<Box<Proc>, isize> {
         let mut pt = match zalloc::<Pagetable>() {
             Err(_) => {
-                return None;
+                return Err(err::NOMEM);
             }
             Ok(pt) => pt,
         };
         kernel_procmap(&mut pt);
+        let signals = Signals::new()?;
 
         // We have to use try_new_uninit to make sure this process is allocated directly into the
         // heap (otherwise might cause a stack overflow).
         let mut proc = unsafe {
             let mut data = match Box::<Proc>::try_new_uninit() {
                 Err(_) => {
-                    return None;
+                    return Err(err::NOMEM);
                 }
                 Ok(data) => data,
             };
             let proc = data.as_mut_ptr();
             addr_of_mut!((*proc).data).write(ProcData {
-                pid: NEXTPID.fetch_add(1, Ordering::Relaxed),
+                // Assigned when the process is added to the process table.
+                pid: 0,
+                name: [0; 16],
                 pt,
-                nchild: 0,
+                start_brk: 0,
+                brk: 0,
+                files: FdTable::new(),
+                cwd: None,
                 state: ProcState::Runnable,
-                parent: null_mut(),
+                on_cpu: AtomicBool::new(false),
+                parent,
                 next: null_mut(),
                 prev: null_mut(),
                 wq: None,
+                xstatus: 0,
+                wakeup: 0,
+                signals,
+                sched: SchedData::default(),
                 context: Context::new(
                     Self::kstackp(proc) as usize,
                     Self::forkret as *const () as usize,
@@ -106,53 +256,113 @@ impl Proc {
 
         proc.data.context.set_pt(&proc.data.pt);
 
-        Some(proc)
-    }
+        table().insert(&mut *proc as *mut Proc)?;
 
-    /// Allocates a new process given a parent. The new process copies all user mappings from the
-    /// parent into its pagetable, and copies over the parent's trapframe (registers and epc).
-    pub fn new_from_parent(parent: &mut Proc) -> Option<Box<Proc>> {
-        let mut p = Self::new_empty()?;
+        Ok(proc)
+    }
 
-        for map in PtIter::new(&mut parent.data.pt) {
-            let mut pg = match kallocpage() {
-                Err(_) => {
-                    return None;
-                }
-                Ok(pg) => pg,
-            };
-            pg.copy_from_slice(map.pg());
-            p.data.pt.mappg(map.va(), pg, map.perm())?;
+    /// Allocates a new process given a parent. The new process shares all user pages with the
+    /// parent, and copies over the parent's trapframe (registers and epc). Writable pages are
+    /// marked copy-on-write in both pagetables so that they are only copied once one of the
+    /// processes writes to them.
+    pub fn new_from_parent(parent: &mut Proc) -> Result<Box<Proc>, isize> {
+        let mut p = Self::new_empty(parent)?;
+
+        let mut shared = true;
+        for mut map in PtIter::new(&mut parent.data.pt) {
+            let mut perm = map.perm();
+            if perm & perm::WRITE != 0 {
+                perm = (perm & !perm::WRITE) | perm::COW;
+                map.pte().set_perm(perm);
+            }
+            pgref::dup(map.pa());
+            if !p.data.pt.map(map.va(), map.pa(), PtLevel::Normal, perm) {
+                pgref::put(map.pa());
+                shared = false;
+                break;
+            }
+        }
+        // The parent's mappings may have lost write permission, so flush any stale translations.
+        vm_fence();
+        if !shared {
+            return Err(err::NOMEM);
         }
 
-        p.data.parent = parent as *mut Proc;
+        p.data.start_brk = parent.data.start_brk;
+        p.data.brk = parent.data.brk;
+        p.data.files = parent.data.files.clone();
+        p.data.cwd = parent.data.cwd.clone();
+        p.data.signals.inherit(&parent.data.signals);
+        p.data.name = parent.data.name;
+        p.data.sched = parent.data.sched.fork();
         p.trapframe = parent.trapframe;
 
+        Ok(p)
+    }
+
+    /// Allocates a new process from an ELF binary. The process pagetable is initialized from the
+    /// ELF segments and is given a valid user stack holding 'argv', and a trapframe that returns
+    /// into the ELF entrypoint. The console is opened as stdin, stdout and stderr, and the working
+    /// directory is the root.
+    pub fn new_from_elf(bin: &[u8], argv: &[&[u8]]) -> Option<Box<Proc>> {
+        let mut p = Self::new_empty(null_mut()).ok()?;
+        let image = exec::load(bin, argv).ok()?;
+        p.install(image);
+        p.set_name(argv);
+        p.data.cwd = vfs::root();
+
+        let console: Arc<dyn File> = Arc::try_new(Console).ok()?;
+        for _ in 0..3 {
+            p.data.files.alloc(console.clone()).ok()?;
+        }
         Some(p)
     }
 
-    /// Allocates a new process from an ELF binary. The bytes must be 64-bit aligned. The process
-    /// pagetable is initialized from the ELF segments and is given a valid user stack and
-    /// trapframe that returns into the ELF entrypoint.
-    pub fn new_from_elf(bin: &[u8]) -> Option<Box<Proc>> {
-        let mut p = Self::new_empty()?;
+    /// Replaces this process's address space with the ELF binary 'bin', passing it 'argv'. On
+    /// success the process returns into the new program's entrypoint, and the return value is the
+    /// argument count (which ends up in a0). On failure the old address space is left untouched.
+    pub fn exec(&mut self, bin: &[u8], argv: &[&[u8]]) -> Result<usize, isize> {
+        let image = exec::load(bin, argv)?;
+        let argc = image.argc;
+        let mut old = self.install(image);
+        // Stop using the old pagetable before it is freed.
+        vm_install(&self.data.pt);
+        free_upages(&mut old);
+        self.data.signals.exec();
+        self.set_name(argv);
+        Ok(argc)
+    }
 
-        // Map code by loading the elf data.
-        let (entry, _) = elf::load64(&mut p.data.pt, bin)?;
+    // Names the process after the last path component of its program, truncated to fit.
+    fn set_name(&mut self, argv: &[&[u8]]) {
+        let path = argv.first().copied().unwrap_or_default();
+        let base = path.rsplit(|&c| c == b'/').next().unwrap_or_default();
+        let n = core::cmp::min(base.len(), self.data.name.len());
+        self.data.name = [0; 16];
+        self.data.name[..n].copy_from_slice(&base[..n]);
+    }
 
-        // Allocate/map stack.
-        let ustack = match zallocpage() {
-            Err(_) => {
-                return None;
-            }
-            Ok(ustack) => ustack,
-        };
-        p.data.pt.mappg(Self::STACK_VA, ustack, perm::URW)?;
-        p.trapframe = Trapframe::default();
-        p.trapframe.regs.sp = Self::STACK_VA + sys::PAGESIZE - 16;
-        p.trapframe.epc = entry as usize;
+    // Installs a loaded image as this process's address space, and resets the trapframe to enter
+    // it. Returns the previous pagetable.
+    fn install(&mut self, image: exec::Image) -> Box<Pagetable> {
+        let old = core::mem::replace(&mut self.data.pt, image.pt);
+        self.data.context.set_pt(&self.data.pt);
+        self.data.start_brk = image.brk;
+        self.data.brk = image.brk;
+        self.trapframe = Trapframe::default();
+        self.trapframe.epc = image.entry;
+        self.trapframe.regs.sp = image.sp;
+        self.trapframe.regs.a0 = image.argc;
+        self.trapframe.regs.a1 = image.argv;
+        self.trapframe.regs.a2 = image.envp;
+        old
+    }
 
-        Some(p)
+    /// Returns the process running on the current core, or None if the core is not running a
+    /// process (for example during boot). Interrupts must be disabled.
+    pub unsafe fn current() -> Option<&'static mut Proc> {
+        use crate::cpu::cpu_noguard;
+        cpu_noguard().proc.as_mut()
     }
 
     /// Returns a pointer to this process's kernel stack.
@@ -175,10 +385,11 @@ impl Proc {
     /// disabled to call this function.
     pub fn yield_(&mut self) {
         use crate::arch::trap::irq;
-        use crate::schedule::{kswitch, CONTEXT};
+        use crate::cpu::cpu_noguard;
+        use crate::schedule::kswitch;
         assert!(!irq::enabled());
 
-        unsafe { kswitch(&mut self.data.context, &mut CONTEXT) }
+        unsafe { kswitch(&mut self.data.context, &mut cpu_noguard().context) }
         self.watch_canary();
     }
 
@@ -187,9 +398,126 @@ impl Proc {
         self.wait(queue, ProcState::Blocked);
     }
 
-    /// Puts this process on the given exit queue.
-    pub fn exit(&mut self, queue: &mut Queue) {
-        self.wait(queue, ProcState::Exited);
+    /// Puts this process on 'queue' and yields, like block, but returns early with INTR if a
+    /// signal that is neither blocked nor ignored arrives. The 'guard' protects the condition
+    /// being waited for, and is released once the process is on the queue.
+    pub fn block_intr<G>(&mut self, queue: &SpinLock<Queue>, guard: G) -> Result<(), isize> {
+        let mask = self.data.signals.interrupting();
+        self.block_until(queue, guard, mask)
+    }
+
+    /// Like block_intr, but the wait is interrupted by the signals in 'mask'.
+    pub fn block_until<G>(
+        &mut self,
+        queue: &SpinLock<Queue>,
+        guard: G,
+        mask: u64,
+    ) -> Result<(), isize> {
+        self.block_with(queue, guard, mask, |p, q| p.block(q))
+    }
+
+    /// Blocks this process on 'queue', which is ordered by deadline, until it is woken up after
+    /// 'deadline' (see timer::sleep). Fails with INTR if a signal that is neither blocked nor
+    /// ignored arrives.
+    pub fn sleep_until(&mut self, queue: &SpinLock<Queue>, deadline: u64) -> Result<(), isize> {
+        let mask = self.data.signals.interrupting();
+        self.data.wakeup = deadline;
+        self.block_with(queue, (), mask, |p, q| {
+            p.data.state = ProcState::Blocked;
+            p.data.wq = Some(q.id);
+            unsafe { q.insert_raw(p, |o| (*o).data.wakeup > deadline) };
+            // Idle cores must reprogram their timers for a new earliest deadline.
+            if q.front() == Some(p as *mut Proc) {
+                schedule::kick_idle();
+            }
+        })
+    }
+
+    // Like block_until, but 'block' puts the process on the locked queue.
+    fn block_with<G>(
+        &mut self,
+        queue: &SpinLock<Queue>,
+        guard: G,
+        mask: u64,
+        block: impl FnOnce(&mut Self, &mut Queue),
+    ) -> Result<(), isize> {
+        // Senders wake the process up if it is on the queue by the time they see the wait, and
+        // otherwise the signal is already pending when checked with the queue locked.
+        self.data.signals.set_wait(queue, mask);
+        let mut q = queue.lock();
+        let r = if self.data.signals.pending() & mask != 0 {
+            drop(q);
+            drop(guard);
+            Err(err::INTR)
+        } else {
+            block(self, &mut q);
+            drop(q);
+            drop(guard);
+            self.yield_();
+            Ok(())
+        };
+        self.data.signals.clear_wait();
+        r
+    }
+
+    /// Exits this process with the given wait status and switches back to the scheduler. The
+    /// process becomes a zombie on the exit queue until its parent waits for it, and its children
+    /// are handed over to init.
+    pub fn exit(&mut self, xstatus: i32) -> ! {
+        println!("{}: exited", self.data.pid);
+        if self.data.pid == INIT_PID {
+            panic!("init exited with status {:#x}", xstatus);
+        }
+        self.data.state = ProcState::Exited;
+        self.data.xstatus = xstatus;
+        self.data.files.clear();
+        self.data.cwd = None;
+
+        // Become a zombie before checking on the parent, and keep the exit queue locked until the
+        // parent has been woken up. A parent in sys_wait holds the exit queue lock while scanning
+        // for zombies and blocking, so it either finds us or is already on the wait queue. The
+        // lock also keeps the parent from exiting, so it cannot be freed while we use it.
+        let mut exited = EXIT_QUEUE.lock();
+        self.wait(&mut exited, ProcState::Exited);
+        if let Some(init) = self.reparent() {
+            unsafe { Self::wake_parent(init) };
+        }
+        if !self.data.parent.is_null() {
+            unsafe {
+                Self::wake_parent(self.data.parent);
+                signal::send(self.data.parent, signal::SIGCHLD);
+            }
+        }
+        drop(exited);
+        self.yield_();
+        panic!("exited process resumed");
+    }
+
+    // Hands the children of this process over to init, and returns init if any of them has
+    // already exited. The exit queue must be locked.
+    fn reparent(&mut self) -> Option<*mut Proc> {
+        let me = self as *mut Proc;
+        let procs = table();
+        let init = procs.get(INIT_PID).unwrap_or(null_mut());
+        let mut zombies = false;
+        for p in procs.iter() {
+            unsafe {
+                if (*p).data.parent == me {
+                    (*p).data.parent = init;
+                    zombies |= (*p).data.state == ProcState::Exited;
+                }
+            }
+        }
+        (zombies && !init.is_null()).then_some(init)
+    }
+
+    // Wakes up 'parent' if it is waiting for a child to exit. The exit queue must be locked.
+    unsafe fn wake_parent(parent: *mut Proc) {
+        let mut waiting = WAIT_QUEUE.lock();
+        if (*parent).data.state == ProcState::Blocked && (*parent).data.wq == Some(QueueType::Wait)
+        {
+            waiting.wake(parent);
+        }
     }
 
     // Assigns this process state and registers it on the given queue. It is up to the caller to
@@ -213,9 +541,11 @@ impl Proc {
 
 impl Drop for Proc {
     fn drop(&mut self) {
-        println!("{}: dropped", self.data.pid);
-        for mut map in PtIter::new(&mut self.data.pt) {
-            unsafe { kfree(map.pg_raw()) };
+        // A process without a PID was never added to the table (which was full).
+        if self.data.pid != 0 {
+            println!("{}: dropped", self.data.pid);
+            table().remove(self as *mut Proc);
         }
+        free_upages(&mut self.data.pt);
     }
 }
@@ -0,0 +1,85 @@
+use crate::proc::Proc;
+use crate::schedule::{Policy, Queue, QueueIter, QueueType, NICE_MIN};
+
+use alloc::boxed::Box;
+use core::cmp::max;
+
+// Period in which each runnable process should get to run once, unless there are so many that
+// their slices would be shorter than MIN_SLICE_US.
+const LATENCY_US: u64 = 40_000;
+const MIN_SLICE_US: u64 = 10_000;
+
+// Weights of the nice values from -20 to 19. Each step changes the share of CPU time by about 10%
+// (these match Linux's values).
+const NICE_0_WEIGHT: u64 = 1024;
+const WEIGHTS: [u64; 40] = [
+    88761, 71755, 56483, 46273, 36291, 29154, 23254, 18705, 14949, 11916, 9548, 7620, 6100, 4904,
+    3906, 3121, 2501, 1991, 1586, 1277, 1024, 820, 655, 526, 423, 335, 272, 215, 172, 137, 110, 87,
+    70, 56, 45, 36, 29, 23, 18, 15,
+];
+
+fn weight(p: &Proc) -> u64 {
+    WEIGHTS[(p.data.sched.nice() - NICE_MIN) as usize]
+}
+
+/// Completely fair scheduling, modeled on Linux's CFS. Processes accumulate virtual runtime, which
+/// is the CPU time they used scaled by the inverse of their weight, and the process with the least
+/// virtual runtime runs next. Processes thus share the CPU in proportion to their weights, which
+/// are determined by their nice values.
+pub struct Cfs {
+    // Runnable processes, in no particular order. There are few enough processes that finding the
+    // one with the least virtual runtime by scanning the queue is cheap.
+    queue: Queue,
+    // Lower bound on the virtual runtime of runnable processes, which only increases.
+    min_vruntime: u64,
+}
+
+impl Cfs {
+    pub const fn new() -> Self {
+        Self {
+            queue: Queue::new(QueueType::Run),
+            min_vruntime: 0,
+        }
+    }
+}
+
+impl Default for Cfs {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Policy for Cfs {
+    fn push(&mut self, mut p: Box<Proc>) {
+        // A process that has been blocked for a while has fallen behind the others. It gets to
+        // run soon, but not for longer than the others until it has caught up.
+        let sched = &mut p.data.sched;
+        sched.vruntime = max(
+            sched.vruntime,
+            self.min_vruntime.saturating_sub(LATENCY_US / 2),
+        );
+        self.queue.push_front(p);
+    }
+
+    fn pop(&mut self) -> Option<Box<Proc>> {
+        let p = QueueIter::new(&self.queue).min_by_key(|&p| unsafe { (*p).data.sched.vruntime })?;
+        unsafe {
+            self.queue.remove(p);
+            self.min_vruntime = max(self.min_vruntime, (*p).data.sched.vruntime);
+            Some(Box::<Proc>::from_raw(p))
+        }
+    }
+
+    fn slice(&self, p: &Proc) -> u64 {
+        // Divide the latency period among the runnable processes according to their weights.
+        let w = weight(p);
+        let total: u64 = w + QueueIter::new(&self.queue)
+            .map(|q| unsafe { weight(&*q) })
+            .sum::<u64>();
+        max(LATENCY_US * w / total, MIN_SLICE_US)
+    }
+
+    fn charge(&mut self, p: &mut Proc, us: u64) {
+        p.data.sched.vruntime += us * NICE_0_WEIGHT / weight(p);
+    }
+}
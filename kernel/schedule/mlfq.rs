@@ -0,0 +1,100 @@
+use crate::proc::Proc;
+use crate::schedule::{Policy, Queue, QueueType, NICE_MAX};
+use crate::timer;
+
+use alloc::boxed::Box;
+use core::cmp::{max, min};
+
+// Number of priority levels. Level 0 has the highest priority.
+const NLEVELS: usize = 4;
+// Time slice at level 0, which doubles at each lower level.
+const SLICE_US: u64 = 10_000;
+// Interval at which all processes are moved back up to their top level, so that processes that
+// have been demoted are not starved, and can become interactive again.
+const BOOST_US: u64 = 1_000_000;
+
+/// Multi-level feedback queue scheduling. Processes start at their top level, and move down a
+/// level once they have used a time slice's worth of CPU time at their current level. Processes
+/// that often block, such as interactive ones, therefore stay at high levels, which always run
+/// first. Processes on the same level run round-robin. The top level of a process is lowered by
+/// positive nice values.
+pub struct Mlfq {
+    levels: [Queue; NLEVELS],
+    // Time of the last boost, and the number of boosts so far.
+    last_boost: u64,
+    boosts: u64,
+}
+
+impl Mlfq {
+    pub fn new() -> Self {
+        Self {
+            levels: core::array::from_fn(|_| Queue::new(QueueType::Run)),
+            last_boost: timer::time(),
+            boosts: 0,
+        }
+    }
+
+    // Returns the highest level of 'p', based on its nice value.
+    fn top(p: &Proc) -> usize {
+        max(p.data.sched.nice(), 0) as usize * NLEVELS / (NICE_MAX as usize + 1)
+    }
+
+    // Moves every process back to its top level. Processes that are not runnable are moved once
+    // they are pushed.
+    fn boost(&mut self) {
+        self.boosts += 1;
+        self.last_boost = timer::time();
+        let mut demoted = Queue::new(QueueType::Run);
+        for level in 1..NLEVELS {
+            while let Some(p) = self.levels[level].pop_back() {
+                demoted.push_front(p);
+            }
+        }
+        while let Some(p) = demoted.pop_back() {
+            self.push(p);
+        }
+    }
+}
+
+impl Default for Mlfq {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Policy for Mlfq {
+    fn push(&mut self, mut p: Box<Proc>) {
+        let top = Self::top(&p);
+        let sched = &mut p.data.sched;
+        if sched.boosts != self.boosts {
+            sched.boosts = self.boosts;
+            sched.level = top;
+            sched.level_us = 0;
+        }
+        // The nice value may have changed since the process was last pushed.
+        sched.level = max(sched.level, top);
+        let level = sched.level;
+        self.levels[level].push_front(p);
+    }
+
+    fn pop(&mut self) -> Option<Box<Proc>> {
+        if timer::us_since(self.last_boost) >= BOOST_US {
+            self.boost();
+        }
+        self.levels.iter_mut().find_map(|q| q.pop_back())
+    }
+
+    fn slice(&self, p: &Proc) -> u64 {
+        SLICE_US << p.data.sched.level
+    }
+
+    fn charge(&mut self, p: &mut Proc, us: u64) {
+        let slice = self.slice(p);
+        let sched = &mut p.data.sched;
+        sched.level_us += us;
+        if sched.level_us >= slice {
+            sched.level = min(sched.level + 1, NLEVELS - 1);
+            sched.level_us = 0;
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use crate::arch::cpu::send_ipi;
+use crate::arch::regs::Context;
+use crate::arch::trap::irq;
+use crate::cpu::cpu_noguard;
+use crate::proc::{Proc, ProcState};
+use crate::sync::spinlock::SpinLock;
+use crate::timer;
+
+use alloc::boxed::Box;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+
+pub mod cfs;
+pub mod mlfq;
+pub mod rr;
+
+pub static RUN_QUEUE: SpinLock<RunQueue> = SpinLock::new(RunQueue { policy: None });
+pub static EXIT_QUEUE: SpinLock<Queue> = SpinLock::new(Queue::new(QueueType::Exit));
+pub static WAIT_QUEUE: SpinLock<Queue> = SpinLock::new(Queue::new(QueueType::Wait));
+
+// Cores that are looking for a process to run, or waiting for one, as a bitmask of core IDs.
+static IDLE_CORES: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum QueueType {
+    Run,     // runnable
+    Exit,    // exited
+    Wait,    // waiting for child
+    Sleep,   // sleeping until a deadline
+    Console, // waiting for console input
+    Disk,    // waiting for a disk request
+    Lock,    // waiting for a mutex
+    Cond,    // waiting on a condition variable
+    Sem,     // waiting for a semaphore
+    Pipe,    // waiting to read from or write to a pipe
+    Stopped, // stopped by a signal
+}
+
+impl QueueType {
+    /// Returns a short description of the processes on queues of this type.
+    pub fn name(self) -> &'static str {
+        match self {
+            QueueType::Run => "runnable",
+            QueueType::Exit => "zombie",
+            QueueType::Wait => "wait",
+            QueueType::Sleep => "sleep",
+            QueueType::Console => "console",
+            QueueType::Disk => "disk",
+            QueueType::Lock => "lock",
+            QueueType::Cond => "cond",
+            QueueType::Sem => "sem",
+            QueueType::Pipe => "pipe",
+            QueueType::Stopped => "stopped",
+        }
+    }
+}
+
+/// A scheduling policy, which decides the order in which runnable processes run and how long each
+/// of them may run before it is preempted.
+pub trait Policy: Send {
+    /// Adds a runnable process.
+    fn push(&mut self, p: Box<Proc>);
+    /// Removes and returns the process that should run next.
+    fn pop(&mut self) -> Option<Box<Proc>>;
+    /// Returns the time slice of 'p', in microseconds, which is about to start running.
+    fn slice(&self, p: &Proc) -> u64;
+    /// Records that 'p' ran for 'us' microseconds before it was switched out.
+    fn charge(&mut self, p: &mut Proc, us: u64);
+}
+
+/// Runnable processes, which are ordered by the scheduling policy.
+pub struct RunQueue {
+    policy: Option<Box<dyn Policy>>,
+}
+
+impl RunQueue {
+    fn policy(&mut self) -> &mut dyn Policy {
+        self.policy.as_deref_mut().expect("no scheduling policy")
+    }
+
+    /// Adds a runnable process.
+    pub fn push(&mut self, p: Box<Proc>) {
+        self.policy().push(p);
+    }
+
+    // Removes the next process to run, and sets its time slice.
+    fn pop(&mut self) -> Option<Box<Proc>> {
+        let policy = self.policy();
+        let mut p = policy.pop()?;
+        p.data.sched.slice = policy.slice(&p);
+        Some(p)
+    }
+}
+
+/// Makes 'p' runnable, and interrupts the idle cores so that one of them runs it.
+pub fn enqueue(p: Box<Proc>) {
+    RUN_QUEUE.lock().push(p);
+    kick_idle();
+}
+
+/// Interrupts the idle cores other than this one, so that they look for a runnable process and
+/// reprogram their timers.
+pub fn kick_idle() {
+    let me = 1 << unsafe { cpu_noguard() }.coreid;
+    let cores = IDLE_CORES.load(Ordering::SeqCst) & !me;
+    if cores != 0 {
+        send_ipi(cores);
+    }
+}
+
+/// Sets the scheduling policy. Must be called by the primary core during boot, before any process
+/// becomes runnable.
+pub fn set_policy<P: Policy + 'static>(policy: P) {
+    let policy = Box::try_new(policy).expect("out of memory");
+    RUN_QUEUE.lock().policy = Some(policy);
+}
+
+/// Scheduling state of a process.
+#[derive(Default)]
+pub struct SchedData {
+    /// Nice value, from -20 (most favored) to 19 (least favored).
+    pub nice: AtomicI32,
+    /// CPU time used by the process, in microseconds.
+    pub runtime: u64,
+    // Time at which the process last started running, and how long it may run before it is
+    // preempted.
+    start: u64,
+    slice: u64,
+    // Multi-level feedback queue state: the current level, the time used at that level, and the
+    // number of priority boosts that have been applied to the process.
+    level: usize,
+    level_us: u64,
+    boosts: u64,
+    // Virtual runtime for completely fair scheduling.
+    vruntime: u64,
+}
+
+/// Minimum and maximum nice values.
+pub const NICE_MIN: i32 = -20;
+pub const NICE_MAX: i32 = 19;
+
+impl SchedData {
+    /// Returns the state of a child forked from a process with this state. The child inherits the
+    /// priority, but not the CPU time used.
+    pub fn fork(&self) -> SchedData {
+        SchedData {
+            nice: AtomicI32::new(self.nice()),
+            level: self.level,
+            boosts: self.boosts,
+            vruntime: self.vruntime,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the nice value.
+    pub fn nice(&self) -> i32 {
+        self.nice.load(Ordering::Relaxed)
+    }
+
+    /// Sets the nice value, clamped to the valid range.
+    pub fn set_nice(&self, nice: i32) {
+        self.nice
+            .store(nice.clamp(NICE_MIN, NICE_MAX), Ordering::Relaxed);
+    }
+
+    /// Returns the CPU time used by the process, which is running, in microseconds. Unlike
+    /// 'runtime', it includes the current time slice.
+    pub fn cputime(&self) -> u64 {
+        self.runtime + timer::us_since(self.start)
+    }
+}
+
+/// Returns the time at which the slice of 'p', which is running, runs out (see timer::time).
+pub fn slice_end(p: &Proc) -> u64 {
+    p.data.sched.start + timer::from_us(p.data.sched.slice)
+}
+
+/// Returns true if the time slice of 'p', which is running on this core, has run out.
+pub fn expired(p: &Proc) -> bool {
+    timer::time() >= slice_end(p)
+}
+
+/// Iterator for all processes in a queue.
+pub struct QueueIter {
+    cur: *mut Proc,
+}
+
+impl QueueIter {
+    pub fn new(q: &Queue) -> QueueIter {
+        Self { cur: q.front }
+    }
+}
+
+impl Iterator for QueueIter {
+    type Item = *mut Proc;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.cur.is_null() {
+            return None;
+        }
+        let ret = self.cur;
+        // Move to the next element in the queue.
+        unsafe { self.cur = (*self.cur).data.next };
+        Some(ret)
+    }
+}
+
+pub struct Queue {
+    front: *mut Proc,
+    back: *mut Proc,
+    size: usize,
+    pub id: QueueType,
+}
+
+unsafe impl Send for Queue {}
+
+impl Queue {
+    pub const fn new(id: QueueType) -> Self {
+        Self {
+            front: null_mut(),
+            back: null_mut(),
+            size: 0,
+            id,
+        }
+    }
+
+    /// Pushes a process onto the queue.
+    pub fn push_front(&mut self, n: Box<Proc>) {
+        unsafe { self.push_front_raw(Box::<Proc>::into_raw(n)) };
+    }
+
+    /// Pushes a process onto the queue from a raw pointer.
+    pub unsafe fn push_front_raw(&mut self, n: *mut Proc) {
+        (*n).data.next = self.front;
+        (*n).data.prev = null_mut();
+        if !self.front.is_null() {
+            (*self.front).data.prev = n;
+        } else {
+            self.back = n;
+        }
+        self.front = n;
+        self.size += 1;
+    }
+
+    /// Removes a process from the queue. If the process is still switching out on another core
+    /// (it registered itself on this queue but has not yet yielded), this waits until its context
+    /// has been saved, so that the caller may safely run or free it.
+    pub unsafe fn remove(&mut self, n: *mut Proc) {
+        assert!(self.size > 0);
+        while (*n).data.on_cpu.load(Ordering::Acquire) {
+            core::hint::spin_loop();
+        }
+        if !(*n).data.next.is_null() {
+            (*(*n).data.next).data.prev = (*n).data.prev;
+        } else {
+            self.back = (*n).data.prev;
+        }
+        if !(*n).data.prev.is_null() {
+            (*(*n).data.prev).data.next = (*n).data.next;
+        } else {
+            self.front = (*n).data.next;
+        }
+        self.size -= 1;
+    }
+
+    /// Removes the last element from the queue (or None if the queue is empty). Ownership is moved
+    /// from the queue to the caller.
+    pub fn pop_back(&mut self) -> Option<Box<Proc>> {
+        let b = self.back;
+        if b.is_null() {
+            return None;
+        }
+        unsafe {
+            self.remove(b);
+            Some(Box::<Proc>::from_raw(b))
+        }
+    }
+
+    /// Wakes up all processes on the queue by removing them, marking them as runnable, and pushing
+    /// them onto the RUN_QUEUE.
+    pub fn wake_all(&mut self) {
+        while !self.front.is_null() {
+            unsafe {
+                // Removes front from the queue.
+                self.wake(self.front);
+            }
+        }
+    }
+
+    /// Wakes up the process that has been on the queue the longest, if any.
+    pub fn wake_one(&mut self) {
+        if !self.back.is_null() {
+            unsafe { self.wake(self.back) };
+        }
+    }
+
+    /// Returns the process at the front of the queue, if any.
+    pub fn front(&self) -> Option<*mut Proc> {
+        (!self.front.is_null()).then_some(self.front)
+    }
+
+    /// Inserts a process into the queue in front of the first process (starting from the front)
+    /// for which 'before' returns true, or at the back if there is none.
+    pub unsafe fn insert_raw(&mut self, n: *mut Proc, before: impl Fn(*mut Proc) -> bool) {
+        let Some(next) = QueueIter::new(self).find(|&q| before(q)) else {
+            (*n).data.next = null_mut();
+            (*n).data.prev = self.back;
+            if self.back.is_null() {
+                self.front = n;
+            } else {
+                (*self.back).data.next = n;
+            }
+            self.back = n;
+            self.size += 1;
+            return;
+        };
+        (*n).data.next = next;
+        (*n).data.prev = (*next).data.prev;
+        if (*next).data.prev.is_null() {
+            self.front = n;
+        } else {
+            (*(*next).data.prev).data.next = n;
+        }
+        (*next).data.prev = n;
+        self.size += 1;
+    }
+
+    /// Returns true if the process is on the queue.
+    pub fn contains(&self, p: *const Proc) -> bool {
+        QueueIter::new(self).any(|q| core::ptr::eq(q, p))
+    }
+
+    /// Wakes an individiaul process. The process must be blocked.
+    pub unsafe fn wake(&mut self, p: *mut Proc) {
+        assert!((*p).data.state == ProcState::Blocked);
+        self.remove(p);
+        (*p).data.state = ProcState::Runnable;
+        (*p).data.wq = None;
+        enqueue(Box::<Proc>::from_raw(p));
+    }
+}
+
+/// Returns the next process available to run, or blocks waiting for a process to become available.
+fn runnable_proc() -> Box<Proc> {
+    let me = 1 << unsafe { cpu_noguard() }.coreid;
+    loop {
+        // Handle pending interrupts, which may make processes runnable.
+        unsafe {
+            irq::on();
+            irq::off();
+        }
+        // Mark the core as idle before checking the run queue: a process enqueued after the check
+        // then interrupts the wait below.
+        IDLE_CORES.fetch_or(me, Ordering::SeqCst);
+        if let Some(proc) = RUN_QUEUE.lock().pop() {
+            IDLE_CORES.fetch_and(!me, Ordering::SeqCst);
+            return proc;
+        }
+        // No runnable procs -- wait until something happens. Interrupts stay disabled until then,
+        // so that an interrupt that makes a process runnable after the run queue was found empty
+        // is not missed: it stays pending, and ends the wait right away.
+        timer::idle();
+    }
+}
+
+extern "C" {
+    fn kswitch_proc(proc: *mut (), oldp: &mut Context, newp: &mut Context) -> *mut ();
+    pub fn kswitch(oldp: &mut Context, newp: &mut Context);
+}
+
+/// The scheduler selects the next process to run and switches the execution context. When the
+/// process is done executing, it switches back to the scheduler, which then chooses the next
+/// process to run.
+pub fn scheduler() -> ! {
+    loop {
+        // Get the next runnable process.
+        let mut p = Box::<Proc>::into_raw(runnable_proc());
+
+        unsafe {
+            irq::off();
+            (*p).data.on_cpu.store(true, Ordering::Relaxed);
+            cpu_noguard().proc = p;
+            (*p).data.sched.start = timer::time();
+            timer::program();
+            p = kswitch_proc(
+                p as *mut (),
+                &mut cpu_noguard().context,
+                &mut (*p).data.context,
+            ) as *mut Proc;
+            cpu_noguard().proc = null_mut();
+
+            // Account for the time the process ran. Nobody else can run it yet, since it is still
+            // marked as on this core.
+            let ran = timer::us_since((*p).data.sched.start);
+            (*p).data.sched.runtime += ran;
+            RUN_QUEUE.lock().policy().charge(&mut *p, ran);
+
+            // Read the process state before marking it as switched out, since from then on another
+            // core may wake it up (if it is on a wait queue) and run it.
+            let runnable = (*p).data.state == ProcState::Runnable;
+            let orphaned = !runnable && (*p).data.wq.is_none();
+            (*p).data.on_cpu.store(false, Ordering::Release);
+
+            if runnable {
+                // Put the process back on the run queue.
+                RUN_QUEUE.lock().push(Box::<Proc>::from_raw(p));
+            } else if orphaned {
+                // Not runnable and not on any queue means we can free this process.
+                drop(Box::<Proc>::from_raw(p));
+            }
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::proc::Proc;
+use crate::schedule::{Policy, Queue, QueueType};
+
+use alloc::boxed::Box;
+
+// Time slice of every process.
+const SLICE_US: u64 = 100_000;
+
+/// Round-robin scheduling: processes run in the order in which they became runnable, for the same
+/// time slice each. Nice values are ignored.
+pub struct RoundRobin {
+    queue: Queue,
+}
+
+impl RoundRobin {
+    pub const fn new() -> Self {
+        Self {
+            queue: Queue::new(QueueType::Run),
+        }
+    }
+}
+
+impl Default for RoundRobin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Policy for RoundRobin {
+    fn push(&mut self, p: Box<Proc>) {
+        self.queue.push_front(p);
+    }
+
+    fn pop(&mut self) -> Option<Box<Proc>> {
+        self.queue.pop_back()
+    }
+
+    fn slice(&self, _p: &Proc) -> u64 {
+        SLICE_US
+    }
+
+    fn charge(&mut self, _p: &mut Proc, _us: u64) {}
+}
@@ -0,0 +1,323 @@
+use crate::arch::regs::Regs;
+use crate::err;
+use crate::proc::{Proc, INIT_PID};
+use crate::schedule::{Queue, QueueType};
+use crate::sync::spinlock::SpinLock;
+use crate::vm::{copyin, copyout};
+
+use alloc::boxed::Box;
+use core::mem::size_of;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Signal numbers (these match newlib's values).
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGEMT: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGBUS: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGSYS: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGURG: usize = 16;
+pub const SIGSTOP: usize = 17;
+pub const SIGTSTP: usize = 18;
+pub const SIGCONT: usize = 19;
+pub const SIGCHLD: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGWINCH: usize = 28;
+pub const SIGUSR1: usize = 30;
+pub const SIGUSR2: usize = 31;
+/// Signals are numbered from 1 to NSIG - 1.
+pub const NSIG: usize = 32;
+
+/// Special handlers.
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// Ways of changing the blocked signals with SYS_SIGPROCMASK (these match newlib's values).
+pub const SIG_SETMASK: usize = 0;
+pub const SIG_BLOCK: usize = 1;
+pub const SIG_UNBLOCK: usize = 2;
+
+const fn bit(sig: usize) -> u64 {
+    1 << sig
+}
+
+const ALL: u64 = !1 & ((1 << NSIG) - 1);
+// Signals that cannot be caught, ignored or blocked.
+const UNCATCHABLE: u64 = bit(SIGKILL) | bit(SIGSTOP);
+const STOP: u64 = bit(SIGSTOP) | bit(SIGTSTP) | bit(SIGTTIN) | bit(SIGTTOU);
+const IGNORE: u64 = bit(SIGURG) | bit(SIGCONT) | bit(SIGCHLD) | bit(SIGWINCH);
+
+// Stopped processes wait here until they are continued.
+static STOP_QUEUE: SpinLock<Queue> = SpinLock::new(Queue::new(QueueType::Stopped));
+
+/// The disposition of a signal.
+#[derive(Copy, Clone)]
+pub struct Action {
+    /// SIG_DFL, SIG_IGN, or the address of the handler.
+    pub handler: usize,
+    /// Signals blocked while the handler runs, in addition to the signal itself.
+    pub mask: u64,
+    /// Address the handler returns to, which must call SYS_SIGRETURN.
+    pub restorer: usize,
+}
+
+struct State {
+    blocked: u64,
+    actions: Box<[Action; NSIG]>,
+    // Queue of an interruptible wait, and the signals that interrupt it.
+    queue: *const SpinLock<Queue>,
+    wake: u64,
+}
+
+unsafe impl Send for State {}
+
+impl State {
+    fn ignored(&self) -> u64 {
+        (1..NSIG)
+            .filter(|&sig| match self.actions[sig].handler {
+                SIG_IGN => true,
+                SIG_DFL => IGNORE & bit(sig) != 0,
+                _ => false,
+            })
+            .fold(0, |mask, sig| mask | bit(sig))
+    }
+}
+
+/// Signal state of a process.
+pub struct Signals {
+    // Senders set pending signals while holding 'state', but a process that is about to block
+    // reads them while holding the lock of the queue it waits on instead (see Proc::block_until).
+    pending: AtomicU64,
+    state: SpinLock<State>,
+}
+
+impl Signals {
+    /// Returns the state of a new process, with no pending or blocked signals and the default
+    /// action for all signals.
+    pub fn new() -> Result<Self, isize> {
+        // All-zero actions are SIG_DFL.
+        let actions = Box::<[Action; NSIG]>::try_new_zeroed()
+            .map(|a| unsafe { a.assume_init() })
+            .map_err(|_| err::NOMEM)?;
+        Ok(Self {
+            pending: AtomicU64::new(0),
+            state: SpinLock::new(State {
+                blocked: 0,
+                actions,
+                queue: core::ptr::null(),
+                wake: 0,
+            }),
+        })
+    }
+
+    /// Copies the blocked signals and actions of 'parent', for a forked child.
+    pub fn inherit(&self, parent: &Signals) {
+        let pst = parent.state.lock();
+        let mut st = self.state.lock();
+        st.blocked = pst.blocked;
+        *st.actions = *pst.actions;
+    }
+
+    /// Resets caught signals to their default action, since their handlers are gone after exec.
+    pub fn exec(&self) {
+        let mut st = self.state.lock();
+        for act in st.actions.iter_mut().filter(|a| a.handler != SIG_IGN) {
+            act.handler = SIG_DFL;
+            act.mask = 0;
+        }
+    }
+
+    /// Returns the pending signals.
+    pub fn pending(&self) -> u64 {
+        self.pending.load(Ordering::SeqCst)
+    }
+
+    /// Returns the signals that interrupt a wait: those that are neither blocked nor ignored.
+    pub fn interrupting(&self) -> u64 {
+        let st = self.state.lock();
+        ALL & !st.blocked & !st.ignored()
+    }
+
+    /// Records that the process waits on 'queue' until one of the signals in 'mask' arrives.
+    pub fn set_wait(&self, queue: &SpinLock<Queue>, mask: u64) {
+        let mut st = self.state.lock();
+        st.queue = queue;
+        st.wake = mask;
+    }
+
+    /// Records that the process has stopped waiting. The queue it waited on may be freed after
+    /// this returns.
+    pub fn clear_wait(&self) {
+        let mut st = self.state.lock();
+        st.queue = core::ptr::null();
+        st.wake = 0;
+    }
+
+    /// Changes the action for 'sig' to 'new' if it is given, and returns the previous action.
+    pub fn action(&self, sig: usize, new: Option<Action>) -> Result<Action, isize> {
+        if sig == 0 || sig >= NSIG || (new.is_some() && UNCATCHABLE & bit(sig) != 0) {
+            return Err(err::INVAL);
+        }
+        let mut st = self.state.lock();
+        let old = st.actions[sig];
+        if let Some(new) = new {
+            st.actions[sig] = new;
+            // Pending signals that are now ignored are discarded.
+            if st.ignored() & bit(sig) != 0 {
+                self.pending.fetch_and(!bit(sig), Ordering::SeqCst);
+            }
+        }
+        Ok(old)
+    }
+
+    /// Changes the blocked signals according to 'how' and 'set' if it is given, and returns the
+    /// previously blocked signals.
+    pub fn procmask(&self, how: usize, set: Option<u64>) -> Result<u64, isize> {
+        let mut st = self.state.lock();
+        let old = st.blocked;
+        if let Some(set) = set {
+            st.blocked = match how {
+                SIG_SETMASK => set,
+                SIG_BLOCK => old | set,
+                SIG_UNBLOCK => old & !set,
+                _ => return Err(err::INVAL),
+            } & ALL
+                & !UNCATCHABLE;
+        }
+        Ok(old)
+    }
+}
+
+/// Sends 'sig' to process 'p', and interrupts its wait if it is waiting for the signal. The
+/// process must not be freed during the call.
+pub unsafe fn send(p: *mut Proc, sig: usize) {
+    let sigs = &(*p).data.signals;
+    let st = sigs.state.lock();
+    // Init must not exit or stop, so it only receives the signals it has a handler for (which
+    // excludes SIGKILL and SIGSTOP).
+    if (*p).data.pid == INIT_PID && st.actions[sig].handler == SIG_DFL {
+        return;
+    }
+    if STOP & bit(sig) != 0 {
+        sigs.pending.fetch_and(!bit(SIGCONT), Ordering::SeqCst);
+    }
+    if sig == SIGCONT {
+        sigs.pending.fetch_and(!STOP, Ordering::SeqCst);
+    }
+    // Ignored signals are discarded unless they are blocked. SIGCONT is kept, since it also
+    // resumes a stopped process.
+    if sig != SIGCONT && st.ignored() & !st.blocked & bit(sig) != 0 {
+        return;
+    }
+    sigs.pending.fetch_or(bit(sig), Ordering::SeqCst);
+    if st.wake & bit(sig) != 0 {
+        let mut q = (*st.queue).lock();
+        if q.contains(p) {
+            q.wake(p);
+        }
+    }
+}
+
+// Saved state of a process running a signal handler, which is pushed onto its user stack.
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct Frame {
+    regs: Regs,
+    epc: usize,
+    blocked: u64,
+}
+
+// Sets up the process to run the handler in 'act' for 'sig' when it returns to user mode. The
+// interrupted state is saved on the user stack along with the signals that were blocked.
+fn push_frame(p: &mut Proc, sig: usize, act: &Action, blocked: u64) -> Result<(), isize> {
+    let frame = Frame {
+        regs: p.trapframe.regs,
+        epc: p.trapframe.epc,
+        blocked,
+    };
+    // The stack pointer must stay 16-byte aligned.
+    let sp = p
+        .trapframe
+        .regs
+        .sp
+        .checked_sub(size_of::<Frame>())
+        .ok_or(err::FAULT)?
+        & !0xf;
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&frame as *const Frame as *const u8, size_of::<Frame>())
+    };
+    copyout(&mut p.data.pt, sp, bytes)?;
+    p.trapframe.epc = act.handler;
+    p.trapframe.regs.sp = sp;
+    p.trapframe.regs.a0 = sig;
+    p.trapframe.regs.ra = act.restorer;
+    Ok(())
+}
+
+/// Restores the state saved when the current signal handler was entered. Returns the restored
+/// value of a0, so that it survives being used as the system call result.
+pub fn sigreturn(p: &mut Proc) -> Result<usize, isize> {
+    let mut bytes = [0u8; size_of::<Frame>()];
+    copyin(&mut p.data.pt, &mut bytes, p.trapframe.regs.sp)?;
+    let frame = unsafe { core::ptr::read_unaligned(bytes.as_ptr() as *const Frame) };
+    p.trapframe.regs = frame.regs;
+    p.trapframe.epc = frame.epc;
+    p.data.signals.state.lock().blocked = frame.blocked & ALL & !UNCATCHABLE;
+    Ok(p.trapframe.regs.a0)
+}
+
+// Stops the process until it receives SIGCONT or SIGKILL.
+fn stop(p: &mut Proc) {
+    let mask = bit(SIGCONT) | bit(SIGKILL);
+    while p.block_until(&STOP_QUEUE, (), mask).is_ok() {}
+}
+
+/// Handles the pending signals of 'p' that are not blocked, before it returns to user mode. At
+/// most one handler is entered; further signals are delivered when it returns.
+pub fn deliver(p: &mut Proc) {
+    loop {
+        let mut st = p.data.signals.state.lock();
+        let ready = p.data.signals.pending() & !st.blocked;
+        if ready == 0 {
+            return;
+        }
+        let sig = ready.trailing_zeros() as usize;
+        p.data
+            .signals
+            .pending
+            .fetch_and(!bit(sig), Ordering::SeqCst);
+        let act = st.actions[sig];
+        match act.handler {
+            SIG_IGN => {}
+            SIG_DFL if IGNORE & bit(sig) != 0 => {}
+            SIG_DFL if STOP & bit(sig) != 0 => {
+                drop(st);
+                stop(p);
+            }
+            SIG_DFL => {
+                drop(st);
+                p.exit(sig as i32);
+            }
+            _ => {
+                let blocked = st.blocked;
+                st.blocked |= (act.mask | bit(sig)) & ALL & !UNCATCHABLE;
+                drop(st);
+                if push_frame(p, sig, &act, blocked).is_err() {
+                    // The stack is unusable.
+                    p.exit(SIGSEGV as i32);
+                }
+                return;
+            }
+        }
+    }
+}
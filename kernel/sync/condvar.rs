@@ -0,0 +1,73 @@
+use crate::proc::Proc;
+use crate::schedule::{Queue, QueueType};
+use crate::sync::mutex::MutexGuard;
+use crate::sync::spinlock::SpinLock;
+
+/// A condition variable, on which processes sleep until a condition protected by a Mutex holds.
+/// The condition must only be changed with the mutex held, and waiters must check it again after
+/// waking up, since another process may have changed it in the meantime.
+pub struct CondVar {
+    waiting: SpinLock<Queue>,
+}
+
+impl CondVar {
+    pub const fn new() -> Self {
+        Self {
+            waiting: SpinLock::new(Queue::new(QueueType::Cond)),
+        }
+    }
+
+    /// Releases the mutex held by 'guard' and puts the calling process to sleep until it is
+    /// notified, then acquires the mutex again. If there is no current process (during boot), this
+    /// returns right away after releasing and acquiring the mutex.
+    pub fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        let mutex = guard.mutex();
+        match unsafe { Proc::current() } {
+            Some(p) => {
+                // The queue is locked before the mutex is released, and stays locked until the
+                // process is on it. A notification made after the condition changes must take the
+                // queue lock, so it cannot be missed.
+                let mut q = self.waiting.lock();
+                drop(guard);
+                p.block(&mut q);
+                drop(q);
+                p.yield_();
+            }
+            None => {
+                drop(guard);
+                core::hint::spin_loop();
+            }
+        }
+        mutex.lock()
+    }
+
+    /// Like wait, but returns early with INTR if a signal that is neither blocked nor ignored
+    /// arrives. The mutex is released in that case.
+    pub fn wait_intr<'a, T>(&self, guard: MutexGuard<'a, T>) -> Result<MutexGuard<'a, T>, isize> {
+        let mutex = guard.mutex();
+        match unsafe { Proc::current() } {
+            Some(p) => p.block_intr(&self.waiting, guard)?,
+            None => {
+                drop(guard);
+                core::hint::spin_loop();
+            }
+        }
+        Ok(mutex.lock())
+    }
+
+    /// Wakes up the process that has waited the longest, if any.
+    pub fn notify_one(&self) {
+        self.waiting.lock().wake_one();
+    }
+
+    /// Wakes up all waiting processes.
+    pub fn notify_all(&self) {
+        self.waiting.lock().wake_all();
+    }
+}
+
+impl Default for CondVar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
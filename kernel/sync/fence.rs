@@ -3,3 +3,8 @@ use core::arch::asm;
 pub fn insn_fence() {
     unsafe { asm!("fence.i") };
 }
+
+/// Orders all prior memory and device accesses before all subsequent ones.
+pub fn io_fence() {
+    unsafe { asm!("fence iorw, iorw") };
+}
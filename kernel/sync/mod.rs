@@ -1,2 +1,5 @@
+pub mod condvar;
 pub mod fence;
+pub mod mutex;
+pub mod semaphore;
 pub mod spinlock;
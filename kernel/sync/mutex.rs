@@ -0,0 +1,103 @@
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut};
+
+use crate::proc::Proc;
+use crate::schedule::{Queue, QueueType};
+use crate::sync::spinlock::SpinLock;
+
+struct State {
+    locked: bool,
+    waiting: Queue,
+}
+
+/// A lock that puts the calling process to sleep while the lock is held by someone else, so that
+/// it may be held across operations that block (such as disk I/O). If there is no current
+/// process (during boot), acquiring the lock spins instead.
+pub struct Mutex<T> {
+    state: SpinLock<State>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for Mutex<T> where T: Send {}
+unsafe impl<T> Send for Mutex<T> where T: Send {}
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: SpinLock::new(State {
+                locked: false,
+                waiting: Queue::new(QueueType::Lock),
+            }),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        loop {
+            let mut st = self.state.lock();
+            if !st.locked {
+                st.locked = true;
+                return MutexGuard { lock: self };
+            }
+            match unsafe { Proc::current() } {
+                Some(p) => {
+                    // The holder cannot release the lock before the process is on the queue,
+                    // since that requires the state, so the wakeup is not lost.
+                    p.block(&mut st.waiting);
+                    drop(st);
+                    p.yield_();
+                }
+                None => {
+                    drop(st);
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Acquires the lock if it is free, without blocking.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        let mut st = self.state.lock();
+        if st.locked {
+            return None;
+        }
+        st.locked = true;
+        Some(MutexGuard { lock: self })
+    }
+}
+
+pub struct MutexGuard<'a, T> {
+    lock: &'a Mutex<T>,
+}
+
+impl<'a, T> MutexGuard<'a, T> {
+    /// Returns the mutex that this guard holds.
+    pub fn mutex(&self) -> &'a Mutex<T> {
+        self.lock
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        // Safety: the existence of this guard guarantees we own the lock.
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        // Safety: the existence of this guard guarantees we own the lock.
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let mut st = self.lock.state.lock();
+        st.locked = false;
+        // Waking up one waiter is enough: if another process takes the lock first, the waiter
+        // goes back to sleep, and is woken up again when that process releases the lock.
+        st.waiting.wake_one();
+    }
+}
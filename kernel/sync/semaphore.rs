@@ -0,0 +1,64 @@
+use crate::proc::Proc;
+use crate::schedule::{Queue, QueueType};
+use crate::sync::spinlock::SpinLock;
+
+struct State {
+    count: usize,
+    waiting: Queue,
+}
+
+/// A counting semaphore. Taking a unit while the count is zero puts the calling process to sleep
+/// until another one is released. If there is no current process (during boot), it spins instead.
+pub struct Semaphore {
+    state: SpinLock<State>,
+}
+
+impl Semaphore {
+    pub const fn new(count: usize) -> Self {
+        Self {
+            state: SpinLock::new(State {
+                count,
+                waiting: Queue::new(QueueType::Sem),
+            }),
+        }
+    }
+
+    /// Takes a unit, waiting until one is available.
+    pub fn down(&self) {
+        loop {
+            let mut st = self.state.lock();
+            if st.count > 0 {
+                st.count -= 1;
+                return;
+            }
+            match unsafe { Proc::current() } {
+                Some(p) => {
+                    p.block(&mut st.waiting);
+                    drop(st);
+                    p.yield_();
+                }
+                None => {
+                    drop(st);
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+
+    /// Takes a unit if one is available, without blocking.
+    pub fn try_down(&self) -> bool {
+        let mut st = self.state.lock();
+        if st.count == 0 {
+            return false;
+        }
+        st.count -= 1;
+        true
+    }
+
+    /// Releases a unit, waking up a waiting process.
+    pub fn up(&self) {
+        let mut st = self.state.lock();
+        st.count += 1;
+        st.waiting.wake_one();
+    }
+}
@@ -1,6 +1,18 @@
-use crate::proc::{Proc, ProcState};
-use crate::schedule::{QueueIter, QueueType, EXIT_QUEUE, RUN_QUEUE, TICKS_QUEUE, WAIT_QUEUE};
-use core::slice;
+use crate::arch::vm::vm_fence;
+use crate::err;
+use crate::fs::vfs::{self, FileType};
+use crate::kalloc::zallocpage;
+use crate::pipe;
+use crate::proc::{self, Proc};
+use crate::schedule::{self, QueueIter, EXIT_QUEUE, NICE_MAX, NICE_MIN, WAIT_QUEUE};
+use crate::signal::{self, Action};
+use crate::sys;
+use crate::timer;
+use crate::vm::{copyin, copyinstr, copyout, perm, pgroundup, writable, PageMap};
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::mem::size_of;
 
 mod num {
     pub const SYS_WRITE: usize = 0;
@@ -10,34 +22,154 @@ mod num {
     pub const SYS_WAIT: usize = 4;
     pub const SYS_SBRK: usize = 5;
     pub const SYS_USLEEP: usize = 6;
+    pub const SYS_READ: usize = 7;
+    pub const SYS_EXEC: usize = 8;
+    pub const SYS_OPEN: usize = 9;
+    pub const SYS_CLOSE: usize = 10;
+    pub const SYS_DUP: usize = 11;
+    pub const SYS_DUP2: usize = 12;
+    pub const SYS_STAT: usize = 13;
+    pub const SYS_MKDIR: usize = 14;
+    pub const SYS_UNLINK: usize = 15;
+    pub const SYS_RENAME: usize = 16;
+    pub const SYS_GETDENTS: usize = 17;
+    pub const SYS_CHDIR: usize = 18;
+    pub const SYS_PIPE: usize = 19;
+    pub const SYS_KILL: usize = 20;
+    pub const SYS_SIGACTION: usize = 21;
+    pub const SYS_SIGRETURN: usize = 22;
+    pub const SYS_SIGPROCMASK: usize = 23;
+    pub const SYS_SETPRIORITY: usize = 24;
+    pub const SYS_GETPRIORITY: usize = 25;
+    pub const SYS_CLOCK_GETTIME: usize = 26;
+    pub const SYS_NANOSLEEP: usize = 27;
+}
+
+// Clocks for SYS_CLOCK_GETTIME (these match newlib's values).
+mod clock {
+    pub const REALTIME: usize = 1;
+    pub const PROCESS_CPUTIME_ID: usize = 2;
+    pub const MONOTONIC: usize = 4;
 }
 
-mod err {
-    pub const BADF: isize = -9;
-    pub const NOSYS: isize = -38;
-    pub const FAULT: isize = -14;
-    pub const NOMEM: isize = -12;
-    pub const CHILD: isize = -10;
+// File type bits of the mode returned by SYS_STAT.
+mod mode {
+    pub const S_IFCHR: u32 = 0o020000;
+    pub const S_IFDIR: u32 = 0o040000;
+    pub const S_IFREG: u32 = 0o100000;
 }
 
+// Flag for SYS_UNLINK to remove a directory instead of a file.
+const AT_REMOVEDIR: usize = 0x200;
+
+// Option for SYS_WAIT to return immediately if no child has exited.
+const WNOHANG: usize = 1;
+
+// Priorities can only be set for single processes.
+const PRIO_PROCESS: usize = 0;
+
 /// System call handler.
 pub fn syscall(p: &mut Proc, sysno: usize) -> isize {
     match sysno {
         num::SYS_GETPID => sys_getpid(p) as isize,
-        num::SYS_WRITE => sys_write(
+        num::SYS_WRITE => ret(sys_write(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+            p.trapframe.regs.arg2(),
+        )),
+        num::SYS_READ => ret(sys_read(
             p,
-            p.trapframe.regs.arg0() as i32,
+            p.trapframe.regs.arg0(),
             p.trapframe.regs.arg1(),
             p.trapframe.regs.arg2(),
-        ),
-        num::SYS_SBRK => sys_sbrk(p),
-        num::SYS_EXIT => sys_exit(p),
+        )),
+        num::SYS_OPEN => ret(sys_open(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
+        num::SYS_CLOSE => ret(sys_close(p, p.trapframe.regs.arg0())),
+        num::SYS_DUP => ret(sys_dup(p, p.trapframe.regs.arg0())),
+        num::SYS_DUP2 => ret(sys_dup2(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
+        num::SYS_STAT => ret(sys_stat(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
+        num::SYS_MKDIR => ret(sys_mkdir(p, p.trapframe.regs.arg0())),
+        num::SYS_UNLINK => ret(sys_unlink(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
+        num::SYS_RENAME => ret(sys_rename(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
+        num::SYS_GETDENTS => ret(sys_getdents(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+            p.trapframe.regs.arg2(),
+        )),
+        num::SYS_CHDIR => ret(sys_chdir(p, p.trapframe.regs.arg0())),
+        num::SYS_PIPE => ret(sys_pipe(p, p.trapframe.regs.arg0())),
+        num::SYS_SBRK => sys_sbrk(p, p.trapframe.regs.arg0() as isize),
+        num::SYS_EXIT => sys_exit(p, p.trapframe.regs.arg0()),
         num::SYS_FORK => sys_fork(p),
-        num::SYS_USLEEP => {
-            sys_usleep(p, p.trapframe.regs.arg0() as u64);
-            0
-        }
-        num::SYS_WAIT => sys_wait(p),
+        num::SYS_USLEEP => ret(sys_usleep(p, p.trapframe.regs.arg0() as u64)),
+        num::SYS_WAIT => ret(sys_wait(
+            p,
+            p.trapframe.regs.arg0() as isize,
+            p.trapframe.regs.arg1(),
+            p.trapframe.regs.arg2(),
+        )),
+        num::SYS_EXEC => ret(sys_exec(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
+        num::SYS_KILL => ret(sys_kill(p.trapframe.regs.arg0(), p.trapframe.regs.arg1())),
+        num::SYS_SIGACTION => ret(sys_sigaction(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+            p.trapframe.regs.arg2(),
+        )),
+        num::SYS_SIGRETURN => sys_sigreturn(p),
+        num::SYS_SIGPROCMASK => ret(sys_sigprocmask(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+            p.trapframe.regs.arg2(),
+        )),
+        num::SYS_SETPRIORITY => ret(sys_setpriority(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+            p.trapframe.regs.arg2() as isize,
+        )),
+        num::SYS_GETPRIORITY => ret(sys_getpriority(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
+        num::SYS_CLOCK_GETTIME => ret(sys_clock_gettime(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
+        num::SYS_NANOSLEEP => ret(sys_nanosleep(
+            p,
+            p.trapframe.regs.arg0(),
+            p.trapframe.regs.arg1(),
+        )),
         _ => {
             println!("unknown syscall {}", sysno);
             err::NOSYS
@@ -45,131 +177,491 @@ pub fn syscall(p: &mut Proc, sysno: usize) -> isize {
     }
 }
 
+// Converts a system call result into the value returned to user space.
+fn ret(r: Result<usize, isize>) -> isize {
+    match r {
+        Ok(v) => v as isize,
+        Err(e) => e,
+    }
+}
+
 /// Returns the process's PID.
 fn sys_getpid(p: &mut Proc) -> u32 {
     p.data.pid
 }
 
+// Allocates a kernel buffer for transferring at most 'sz' bytes to or from user memory.
+fn iobuf(sz: usize) -> Result<Vec<u8>, isize> {
+    let mut buf = Vec::new();
+    let len = core::cmp::min(sz, sys::PAGESIZE);
+    buf.try_reserve_exact(len).map_err(|_| err::NOMEM)?;
+    buf.resize(len, 0);
+    Ok(buf)
+}
+
 /// Write to 'sz' bytes at 'addr' into 'fd' on behalf of the process. Returns the number of bytes
 /// written, or an error.
-fn sys_write(_p: &mut Proc, fd: i32, addr: usize, sz: usize) -> isize {
-    if sz == 0 {
-        return 0;
-    }
+fn sys_write(p: &mut Proc, fd: usize, addr: usize, sz: usize) -> Result<usize, isize> {
+    let f = p.data.files.get(fd)?;
 
-    // Validate buffer.
-    let overflow = addr.wrapping_add(sz);
-    if overflow < addr || addr >= Proc::MAX_VA {
-        return err::FAULT;
+    // Copy the data through a kernel buffer, a page at a time.
+    let mut buf = iobuf(sz)?;
+    let mut written = 0;
+    while written < sz {
+        let n = core::cmp::min(buf.len(), sz - written);
+        copyin(&mut p.data.pt, &mut buf[..n], addr + written)?;
+        let w = match f.write(p, &buf[..n]) {
+            // Report a partial write if an error occurs after some data was written.
+            Err(e) if written == 0 => return Err(e),
+            Err(_) => break,
+            Ok(w) => w,
+        };
+        written += w;
+        if w < n {
+            break;
+        }
     }
 
-    // TODO: make sure all pages are user accessible
+    Ok(written)
+}
+
+/// Reads up to 'sz' bytes from 'fd' into 'addr' on behalf of the process. Returns the number of
+/// bytes read (0 at end of file), or an error. At most a page is read per call.
+fn sys_read(p: &mut Proc, fd: usize, addr: usize, sz: usize) -> Result<usize, isize> {
+    let f = p.data.files.get(fd)?;
 
-    // TODO: we only support stdout for now.
-    if fd != 1 {
-        return err::BADF;
+    let mut buf = iobuf(sz)?;
+    // Data read from a device or pipe cannot be put back, so check the destination first.
+    writable(&mut p.data.pt, addr, buf.len())?;
+    let n = f.read(p, &mut buf)?;
+    copyout(&mut p.data.pt, addr, &buf[..n])?;
+    Ok(n)
+}
+
+/// Opens the file at 'path' and returns a new file descriptor for it.
+fn sys_open(p: &mut Proc, path: usize, oflags: usize) -> Result<usize, isize> {
+    let path = copyinstr(&mut p.data.pt, path, MAXPATH)?;
+    let f = vfs::open(p.data.cwd.as_ref(), &path, oflags)?;
+    p.data.files.alloc(f)
+}
+
+/// Writes the metadata of the file at 'path' to 'addr', as the inode number (u64), the size
+/// (u64), the mode (u32) and the number of links (u32).
+fn sys_stat(p: &mut Proc, path: usize, addr: usize) -> Result<usize, isize> {
+    let path = copyinstr(&mut p.data.pt, path, MAXPATH)?;
+    let st = vfs::stat(p.data.cwd.as_ref(), &path)?;
+    let mode = match st.ftype {
+        FileType::File => mode::S_IFREG,
+        FileType::Dir => mode::S_IFDIR,
+        FileType::Device => mode::S_IFCHR,
+    };
+    let mut buf = [0u8; 24];
+    buf[0..8].copy_from_slice(&st.ino.to_le_bytes());
+    buf[8..16].copy_from_slice(&st.size.to_le_bytes());
+    buf[16..20].copy_from_slice(&mode.to_le_bytes());
+    buf[20..24].copy_from_slice(&st.nlink.to_le_bytes());
+    copyout(&mut p.data.pt, addr, &buf)?;
+    Ok(0)
+}
+
+/// Creates a directory at 'path'.
+fn sys_mkdir(p: &mut Proc, path: usize) -> Result<usize, isize> {
+    let path = copyinstr(&mut p.data.pt, path, MAXPATH)?;
+    vfs::mkdir(p.data.cwd.as_ref(), &path)?;
+    Ok(0)
+}
+
+/// Removes the file at 'path', or the empty directory at 'path' if 'flags' has AT_REMOVEDIR set.
+fn sys_unlink(p: &mut Proc, path: usize, flags: usize) -> Result<usize, isize> {
+    if flags & !AT_REMOVEDIR != 0 {
+        return Err(err::INVAL);
     }
+    let path = copyinstr(&mut p.data.pt, path, MAXPATH)?;
+    vfs::unlink(p.data.cwd.as_ref(), &path, flags & AT_REMOVEDIR != 0)?;
+    Ok(0)
+}
+
+/// Moves the file at 'oldpath' to 'newpath', replacing the file at 'newpath' if there is one.
+fn sys_rename(p: &mut Proc, oldpath: usize, newpath: usize) -> Result<usize, isize> {
+    let oldpath = copyinstr(&mut p.data.pt, oldpath, MAXPATH)?;
+    let newpath = copyinstr(&mut p.data.pt, newpath, MAXPATH)?;
+    vfs::rename(p.data.cwd.as_ref(), &oldpath, &newpath)?;
+    Ok(0)
+}
+
+/// Reads entries from the directory open as 'fd' into 'addr', in the layout of Linux's
+/// getdents64. Returns the number of bytes written (0 at the end of the directory). At most a
+/// page is read per call.
+fn sys_getdents(p: &mut Proc, fd: usize, addr: usize, sz: usize) -> Result<usize, isize> {
+    let f = p.data.files.get(fd)?;
+
+    let mut buf = iobuf(sz)?;
+    writable(&mut p.data.pt, addr, buf.len())?;
+    let n = f.getdents(p, &mut buf)?;
+    copyout(&mut p.data.pt, addr, &buf[..n])?;
+    Ok(n)
+}
+
+/// Changes the working directory to 'path'.
+fn sys_chdir(p: &mut Proc, path: usize) -> Result<usize, isize> {
+    let path = copyinstr(&mut p.data.pt, path, MAXPATH)?;
+    p.data.cwd = Some(vfs::chdir(p.data.cwd.as_ref(), &path)?);
+    Ok(0)
+}
 
-    let buf = unsafe { slice::from_raw_parts(addr as *const u8, sz) };
-    {
-        let mut uart = crate::board::UART.lock();
-        uart.write_bytes(buf);
+/// Creates a pipe, and writes the file descriptors of its read and write ends to 'addr' as two
+/// 32-bit integers.
+fn sys_pipe(p: &mut Proc, addr: usize) -> Result<usize, isize> {
+    let (r, w) = pipe::new()?;
+    let rfd = p.data.files.alloc(r)?;
+    let wfd = match p.data.files.alloc(w) {
+        Ok(fd) => fd,
+        Err(e) => {
+            p.data.files.close(rfd)?;
+            return Err(e);
+        }
+    };
+    let mut fds = [0u8; 8];
+    fds[0..4].copy_from_slice(&(rfd as i32).to_le_bytes());
+    fds[4..8].copy_from_slice(&(wfd as i32).to_le_bytes());
+    if let Err(e) = copyout(&mut p.data.pt, addr, &fds) {
+        p.data.files.close(rfd)?;
+        p.data.files.close(wfd)?;
+        return Err(e);
     }
+    Ok(0)
+}
+
+/// Closes 'fd'.
+fn sys_close(p: &mut Proc, fd: usize) -> Result<usize, isize> {
+    p.data.files.close(fd)?;
+    Ok(0)
+}
 
-    sz as isize
+/// Duplicates 'fd' onto the lowest free file descriptor, and returns it.
+fn sys_dup(p: &mut Proc, fd: usize) -> Result<usize, isize> {
+    p.data.files.dup(fd)
+}
+
+/// Duplicates 'fd' onto 'newfd', closing 'newfd' first if it is open. Returns 'newfd'.
+fn sys_dup2(p: &mut Proc, fd: usize, newfd: usize) -> Result<usize, isize> {
+    p.data.files.dup2(fd, newfd)
 }
 
 /// Create a new child process that is a clone of the current process. Returns:
 /// * 0 to the child.
-/// * Child's PID to the parent, or an error if the child could not be created.
+/// * Child's PID to the parent, or an error if the child could not be created (AGAIN if there
+///   are too many processes).
 fn sys_fork(p: &mut Proc) -> isize {
     let mut child = match Proc::new_from_parent(p) {
-        None => {
-            return err::NOMEM;
+        Err(e) => {
+            return e;
         }
-        Some(p) => p,
+        Ok(p) => p,
     };
     child.trapframe.regs.set_ret(0);
-    p.data.nchild += 1;
 
     let pid = child.data.pid;
-    RUN_QUEUE.lock().push_front(child);
+    schedule::enqueue(child);
     pid as isize
 }
 
-fn sys_sbrk(_p: &mut Proc) -> isize {
-    -1
-}
-
-/// Exit the current process and switches back to the scheduler.
-fn sys_exit(p: &mut Proc) -> ! {
-    println!("{}: exited", p.data.pid);
-    p.data.state = ProcState::Exited;
-
-    // TODO: reparent all children
+/// Grows or shrinks the program break by 'incr' bytes. New heap pages are zeroed. Returns the
+/// previous break, or NOMEM (leaving the break unchanged) if the new break is out of range.
+fn sys_sbrk(p: &mut Proc, incr: isize) -> isize {
+    let old = p.data.brk;
+    let new = match old.checked_add_signed(incr) {
+        None => {
+            return err::NOMEM;
+        }
+        Some(new) => new,
+    };
+    if new < p.data.start_brk {
+        // The break cannot be moved below the end of the program.
+        return err::NOMEM;
+    }
+    if new > Proc::STACK_VA {
+        // The heap would run into the stack.
+        return err::NOMEM;
+    }
 
-    if !p.data.parent.is_null() {
-        unsafe {
-            // Wake up the parent if it is waiting for the child to exit.
-            if (*p.data.parent).data.state == ProcState::Blocked
-                && (*p.data.parent).data.wq == Some(QueueType::Wait)
-            {
-                WAIT_QUEUE.lock().wake(p.data.parent);
+    let (oldtop, newtop) = (pgroundup(old), pgroundup(new));
+    if newtop > oldtop {
+        for va in (oldtop..newtop).step_by(sys::PAGESIZE) {
+            let mapped = match zallocpage() {
+                Err(_) => None,
+                Ok(pg) => p.data.pt.mappg(va, pg, perm::URW),
+            };
+            if mapped.is_none() {
+                // Undo the partial growth.
+                for va in (oldtop..va).step_by(sys::PAGESIZE) {
+                    p.data.pt.unmappg(va);
+                }
+                vm_fence();
+                return err::NOMEM;
             }
         }
+    } else if newtop < oldtop {
+        for va in (newtop..oldtop).step_by(sys::PAGESIZE) {
+            p.data.pt.unmappg(va);
+        }
+        vm_fence();
     }
 
-    p.exit(&mut EXIT_QUEUE.lock());
-    p.yield_();
-    panic!("exited process resumed");
+    p.data.brk = new;
+    old as isize
 }
 
-/// Wait for 'us' microseconds.
-fn sys_usleep(p: &mut Proc, us: u64) {
-    use crate::timer;
-    let start = timer::time();
+/// Maximum length of a path passed to a system call.
+const MAXPATH: usize = 256;
+/// Maximum number of arguments passed to exec, not counting the NULL terminator.
+const MAXARG: usize = 32;
 
+/// Replaces the current program with the executable at 'path', passing it the NULL-terminated
+/// argument array at 'argv'. On success this does not return to the old program; the new program
+/// starts with argc in a0, which is why argc is returned.
+fn sys_exec(p: &mut Proc, path: usize, argv: usize) -> Result<usize, isize> {
+    let path = copyinstr(&mut p.data.pt, path, MAXPATH)?;
+
+    let mut args = Vec::new();
     loop {
-        if timer::us_since(start) >= us {
+        let mut ptr = [0u8; size_of::<usize>()];
+        copyin(
+            &mut p.data.pt,
+            &mut ptr,
+            argv + args.len() * size_of::<usize>(),
+        )?;
+        let ptr = usize::from_le_bytes(ptr);
+        if ptr == 0 {
             break;
         }
-        // Enter the ticks wait queue that will be woken up every timer interrupt.
-        unsafe { p.block(&mut TICKS_QUEUE) };
-        p.yield_();
-        // A timer interrupt has occurred and we are now runnable. Recheck the condition, and jump
-        // back on the wait queue if there is still more time to wait.
+        if args.len() == MAXARG {
+            return Err(err::TOOBIG);
+        }
+        args.push(copyinstr(&mut p.data.pt, ptr, MAXPATH)?);
+    }
+
+    let bin = match vfs::read_file(p.data.cwd.as_ref(), &path) {
+        Err(err::ISDIR) => return Err(err::NOEXEC),
+        res => res?,
+    };
+    let args: Vec<&[u8]> = args.iter().map(|a| a.as_bytes()).collect();
+    p.exec(&bin, &args)
+}
+
+/// Exit the current process with exit code 'code' and switches back to the scheduler.
+fn sys_exit(p: &mut Proc, code: usize) -> ! {
+    // The exit code is in the second byte of the wait status.
+    p.exit(((code & 0xff) << 8) as i32);
+}
+
+/// Wait for 'us' microseconds. Fails with INTR if a signal arrives first.
+fn sys_usleep(p: &mut Proc, us: u64) -> Result<usize, isize> {
+    timer::sleep(p, timer::time().saturating_add(timer::from_us(us)))?;
+    Ok(0)
+}
+
+// Size of a struct timespec: seconds, and nanoseconds within the second.
+const TIMESPEC_SIZE: usize = 16;
+
+// Reads a struct timespec from user memory at 'addr', in nanoseconds.
+fn copyin_timespec(p: &mut Proc, addr: usize) -> Result<u64, isize> {
+    let mut buf = [0u8; TIMESPEC_SIZE];
+    copyin(&mut p.data.pt, &mut buf, addr)?;
+    let sec = i64::from_le_bytes(buf[0..8].try_into().unwrap());
+    let nsec = i64::from_le_bytes(buf[8..16].try_into().unwrap());
+    if sec < 0 || !(0..1_000_000_000).contains(&nsec) {
+        return Err(err::INVAL);
+    }
+    Ok((sec as u64)
+        .saturating_mul(1_000_000_000)
+        .saturating_add(nsec as u64))
+}
+
+// Writes 'ns' nanoseconds as a struct timespec to user memory at 'addr'.
+fn copyout_timespec(p: &mut Proc, addr: usize, ns: u64) -> Result<(), isize> {
+    let mut buf = [0u8; TIMESPEC_SIZE];
+    buf[0..8].copy_from_slice(&(ns / 1_000_000_000).to_le_bytes());
+    buf[8..16].copy_from_slice(&(ns % 1_000_000_000).to_le_bytes());
+    copyout(&mut p.data.pt, addr, &buf)
+}
+
+/// Write the time of clock 'id' to the struct timespec at 'tp'.
+fn sys_clock_gettime(p: &mut Proc, id: usize, tp: usize) -> Result<usize, isize> {
+    let ns = match id {
+        clock::REALTIME => timer::realtime(),
+        clock::MONOTONIC => timer::monotonic(),
+        clock::PROCESS_CPUTIME_ID => p.data.sched.cputime() * 1000,
+        _ => return Err(err::INVAL),
+    };
+    copyout_timespec(p, tp, ns)?;
+    Ok(0)
+}
+
+/// Wait for the time given by the struct timespec at 'req'. If a signal arrives first, fails with
+/// INTR and writes the time that was left to 'rem', unless it is 0.
+fn sys_nanosleep(p: &mut Proc, req: usize, rem: usize) -> Result<usize, isize> {
+    let deadline = timer::time().saturating_add(timer::from_ns(copyin_timespec(p, req)?));
+    match timer::sleep(p, deadline) {
+        Err(err::INTR) if rem != 0 => {
+            let left = timer::to_ns(deadline.saturating_sub(timer::time()));
+            copyout_timespec(p, rem, left)?;
+            Err(err::INTR)
+        }
+        r => r.map(|_| 0),
     }
 }
 
-/// Wait for a child to exit. Returns the PID of the exited child.
-fn sys_wait(p: &mut Proc) -> isize {
-    if p.data.nchild == 0 {
-        // No children.
-        return err::CHILD;
+/// Waits for the child with PID 'pid' to exit, or for any child if 'pid' is -1, and stores its
+/// wait status at 'status' unless it is null. Returns the PID of the exited child, or 0 if
+/// 'options' has WNOHANG and the child has not exited yet. Fails with INTR if a signal arrives
+/// first.
+fn sys_wait(p: &mut Proc, pid: isize, status: usize, options: usize) -> Result<usize, isize> {
+    // Process groups are not supported.
+    let pid = match pid {
+        -1 => None,
+        1.. => Some(u32::try_from(pid).map_err(|_| err::CHILD)?),
+        _ => return Err(err::INVAL),
+    };
+    if options & !WNOHANG != 0 {
+        return Err(err::INVAL);
     }
+    let me = p as *mut Proc;
 
     loop {
-        {
-            // Look through all processes that have exited and are waiting for a parent to wait for
-            // them (zombies).
-            let mut exited = EXIT_QUEUE.lock();
-            for zombie in QueueIter::new(&exited) {
-                unsafe {
-                    // This zombie has 'p' as a parent.
-                    if (*zombie).data.parent == p as *mut Proc {
-                        // Read the child's PID, and then remove it from the exit queue and free
-                        // it.
-                        let pid = (*zombie).data.pid;
-                        exited.remove(zombie);
-                        core::ptr::drop_in_place(zombie);
-                        p.data.nchild -= 1;
-                        return pid as isize;
-                    }
-                }
+        // Look through all processes that have exited and are waiting for a parent to wait for
+        // them (zombies).
+        let mut exited = EXIT_QUEUE.lock();
+        let child = proc::table().iter().any(|c| unsafe {
+            (*c).data.parent == me && pid.is_none_or(|pid| (*c).data.pid == pid)
+        });
+        if !child {
+            return Err(err::CHILD);
+        }
+        let zombie = QueueIter::new(&exited).find(|&z| unsafe {
+            (*z).data.parent == me && pid.is_none_or(|pid| (*z).data.pid == pid)
+        });
+        if let Some(zombie) = zombie {
+            // Remove the child from the exit queue and free it.
+            let (pid, xstatus) = unsafe {
+                exited.remove(zombie);
+                let zombie = Box::<Proc>::from_raw(zombie);
+                (zombie.data.pid, zombie.data.xstatus)
+            };
+            drop(exited);
+            if status != 0 {
+                copyout(&mut p.data.pt, status, &xstatus.to_le_bytes())?;
             }
+            return Ok(pid as usize);
+        }
+        if options & WNOHANG != 0 {
+            return Ok(0);
         }
         // Push onto the wait queue and yield. We will be woken up when one of our children exits.
-        p.block(&mut WAIT_QUEUE.lock());
-        p.yield_();
+        // The exit queue stays locked until we are on the wait queue so that an exiting child
+        // cannot miss us.
+        p.block_intr(&WAIT_QUEUE, exited)?;
+    }
+}
+
+/// Sends signal 'sig' to the process with PID 'pid'. A 'sig' of 0 only checks that the process
+/// exists.
+fn sys_kill(pid: usize, sig: usize) -> Result<usize, isize> {
+    // Process groups are not supported.
+    let pid = u32::try_from(pid).map_err(|_| err::INVAL)?;
+    if pid == 0 || sig >= signal::NSIG {
+        return Err(err::INVAL);
+    }
+    let procs = proc::table();
+    let target = procs.get(pid).ok_or(err::SRCH)?;
+    if sig != 0 {
+        unsafe { signal::send(target, sig) };
+    }
+    Ok(0)
+}
+
+/// Sets the action for 'sig' from the one at 'act', unless it is null, and stores the previous
+/// action at 'oldact', unless it is null. An action is 24 bytes: the handler, the mask of signals
+/// blocked while it runs, and the restorer that the handler returns to.
+fn sys_sigaction(p: &mut Proc, sig: usize, act: usize, oldact: usize) -> Result<usize, isize> {
+    let mut buf = [0u8; 24];
+    let new = if act != 0 {
+        copyin(&mut p.data.pt, &mut buf, act)?;
+        let word = |i: usize| u64::from_le_bytes(buf[i * 8..i * 8 + 8].try_into().unwrap());
+        Some(Action {
+            handler: word(0) as usize,
+            mask: word(1),
+            restorer: word(2) as usize,
+        })
+    } else {
+        None
+    };
+    let old = p.data.signals.action(sig, new)?;
+    if oldact != 0 {
+        buf[0..8].copy_from_slice(&(old.handler as u64).to_le_bytes());
+        buf[8..16].copy_from_slice(&old.mask.to_le_bytes());
+        buf[16..24].copy_from_slice(&(old.restorer as u64).to_le_bytes());
+        copyout(&mut p.data.pt, oldact, &buf)?;
+    }
+    Ok(0)
+}
+
+/// Returns from a signal handler to the code it interrupted. The process is killed if its saved
+/// state cannot be read.
+fn sys_sigreturn(p: &mut Proc) -> isize {
+    match signal::sigreturn(p) {
+        Ok(a0) => a0 as isize,
+        Err(_) => p.exit(signal::SIGSEGV as i32),
+    }
+}
+
+/// Changes the blocked signals according to 'how' and the mask at 'set', unless it is null, and
+/// stores the previously blocked signals at 'oldset', unless it is null.
+fn sys_sigprocmask(p: &mut Proc, how: usize, set: usize, oldset: usize) -> Result<usize, isize> {
+    let mut buf = [0u8; size_of::<u64>()];
+    let new = if set != 0 {
+        copyin(&mut p.data.pt, &mut buf, set)?;
+        Some(u64::from_le_bytes(buf))
+    } else {
+        None
+    };
+    let old = p.data.signals.procmask(how, new)?;
+    if oldset != 0 {
+        copyout(&mut p.data.pt, oldset, &old.to_le_bytes())?;
     }
+    Ok(0)
+}
+
+// Calls 'f' with the process selected by 'which' and 'who' for SYS_SETPRIORITY and
+// SYS_GETPRIORITY: the process with PID 'who', or the calling process if 'who' is 0.
+fn with_prio_target<T>(
+    p: &Proc,
+    which: usize,
+    who: usize,
+    f: impl FnOnce(&Proc) -> T,
+) -> Result<T, isize> {
+    if which != PRIO_PROCESS {
+        return Err(err::INVAL);
+    }
+    let pid = match who {
+        0 => p.data.pid,
+        _ => u32::try_from(who).map_err(|_| err::SRCH)?,
+    };
+    let procs = proc::table();
+    let target = procs.get(pid).ok_or(err::SRCH)?;
+    Ok(f(unsafe { &*target }))
+}
+
+/// Sets the nice value of a process to 'nice', clamped to the range from -20 to 19.
+fn sys_setpriority(p: &mut Proc, which: usize, who: usize, nice: isize) -> Result<usize, isize> {
+    let nice = nice.clamp(NICE_MIN as isize, NICE_MAX as isize) as i32;
+    with_prio_target(p, which, who, |t| t.data.sched.set_nice(nice))?;
+    Ok(0)
+}
+
+/// Returns 20 minus the nice value of a process, which is from 1 to 40 so that it cannot be
+/// mistaken for an error.
+fn sys_getpriority(p: &mut Proc, which: usize, who: usize) -> Result<usize, isize> {
+    let nice = with_prio_target(p, which, who, |t| t.data.sched.nice())?;
+    Ok((20 - nice) as usize)
 }
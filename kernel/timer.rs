@@ -1,4 +1,92 @@
+use crate::arch::cpu::wfi;
 use crate::arch::timer;
+use crate::arch::vm::{Pagetable, PtLevel};
+use crate::board;
+use crate::cpu::cpu_noguard;
+use crate::err;
+use crate::kalloc::{pgref, zallocpage};
+use crate::proc::Proc;
+use crate::schedule::{self, Queue, QueueType};
+use crate::sync::spinlock::SpinLock;
+use crate::vm::{ka2pa, perm};
+
+use alloc::boxed::Box;
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicPtr, AtomicU64, Ordering};
+
+// Value of the 'time' function at boot, from which CLOCK_MONOTONIC counts.
+static BOOT: AtomicU64 = AtomicU64::new(0);
+
+// Real time at which the 'time' function was 0, in nanoseconds since the Unix epoch.
+static REALTIME: AtomicU64 = AtomicU64::new(0);
+
+/// Clock data that is mapped read-only at Proc::TIME_VA into every process, so that programs can
+/// read the clocks without a system call: they read the 'time' CSR, and convert it using these
+/// values in the same way as the kernel.
+#[repr(C)]
+pub struct TimePage {
+    /// Frequency of the 'time' CSR, in ticks per second.
+    pub freq: AtomicU64,
+    /// Value of 'time' at boot, from which CLOCK_MONOTONIC counts.
+    pub boot: AtomicU64,
+    /// Real time at which 'time' was 0, in nanoseconds since the Unix epoch.
+    pub realtime: AtomicU64,
+}
+
+static TIME_PAGE: AtomicPtr<TimePage> = AtomicPtr::new(null_mut());
+
+// Sleeping processes, ordered by the time at which they are woken up, earliest first.
+static SLEEPERS: SpinLock<Queue> = SpinLock::new(Queue::new(QueueType::Sleep));
+
+/// The timer of a core, which raises an interrupt on that core once it reaches the deadline it was
+/// last programmed for. Only accessed by its own core, with interrupts disabled.
+pub struct ClockEvent {
+    // Deadline the timer is programmed for, u64::MAX if it is stopped, or None if it has fired.
+    next: Option<u64>,
+}
+
+impl ClockEvent {
+    pub const fn new() -> Self {
+        Self { next: None }
+    }
+
+    /// Programs the timer for 'deadline' (see 'time'), unless it already is.
+    pub fn set(&mut self, deadline: u64) {
+        if self.next != Some(deadline) {
+            self.next = Some(deadline);
+            timer::intr_at(deadline);
+        }
+    }
+
+    /// Stops the timer, so that it raises no interrupt until it is programmed again.
+    pub fn stop(&mut self) {
+        self.set(u64::MAX);
+    }
+
+    // Records that the timer fired. It must be programmed again to acknowledge the interrupt.
+    fn fired(&mut self) {
+        self.next = None;
+    }
+}
+
+impl Default for ClockEvent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Time each core spent idle (see 'time'), and the number of times it went idle.
+struct Idle {
+    time: AtomicU64,
+    count: AtomicU64,
+}
+
+static IDLE: [Idle; board::machine::NCORES] = [const {
+    Idle {
+        time: AtomicU64::new(0),
+        count: AtomicU64::new(0),
+    }
+}; board::machine::NCORES];
 
 /// Spin for 'amt' where the 'time' function returns the current time.
 fn delay(amt: u64, time: fn() -> u64) {
@@ -31,3 +119,147 @@ pub fn time() -> u64 {
 pub fn us_since(prev: u64) -> u64 {
     (timer::time() - prev) * 1_000_000 / timer::freq()
 }
+
+/// Converts microseconds into the platform specific frequency of the 'time' function, saturating
+/// at u64::MAX.
+pub fn from_us(us: u64) -> u64 {
+    let t = us as u128 * timer::freq() as u128 / 1_000_000;
+    t.min(u64::MAX as u128) as u64
+}
+
+/// Converts nanoseconds into the platform specific frequency of the 'time' function, saturating
+/// at u64::MAX.
+pub fn from_ns(ns: u64) -> u64 {
+    let t = ns as u128 * timer::freq() as u128 / 1_000_000_000;
+    t.min(u64::MAX as u128) as u64
+}
+
+/// Converts a time of the 'time' function into nanoseconds.
+pub fn to_ns(time: u64) -> u64 {
+    (time as u128 * 1_000_000_000 / timer::freq() as u128) as u64
+}
+
+/// Returns the real time, in nanoseconds since the Unix epoch. It starts at the epoch, unless it
+/// has been set by 'init' or 'set_realtime'.
+pub fn realtime() -> u64 {
+    REALTIME.load(Ordering::Relaxed) + to_ns(time())
+}
+
+/// Sets the real time to 'ns' nanoseconds since the Unix epoch.
+pub fn set_realtime(ns: u64) {
+    REALTIME.store(ns.saturating_sub(to_ns(time())), Ordering::Relaxed);
+    publish();
+}
+
+/// Returns the time since boot in nanoseconds.
+pub fn monotonic() -> u64 {
+    to_ns(time() - BOOT.load(Ordering::Relaxed))
+}
+
+/// Initializes the clocks at boot. The real time is set from the board's real-time clock, if it
+/// has one.
+pub fn init() {
+    BOOT.store(time(), Ordering::Relaxed);
+    let page = Box::into_raw(zallocpage().expect("could not allocate the time page"));
+    TIME_PAGE.store(page as *mut TimePage, Ordering::Release);
+    match board::RTC {
+        Some(rtc) => set_realtime(rtc.read()),
+        None => publish(),
+    }
+}
+
+// Copies the clock data into the time page.
+fn publish() {
+    let Some(page) = (unsafe { TIME_PAGE.load(Ordering::Acquire).as_ref() }) else {
+        return;
+    };
+    page.freq.store(timer::freq(), Ordering::Relaxed);
+    page.boot
+        .store(BOOT.load(Ordering::Relaxed), Ordering::Relaxed);
+    page.realtime
+        .store(REALTIME.load(Ordering::Relaxed), Ordering::Relaxed);
+}
+
+/// Maps the time page read-only into the user address space 'pt', at Proc::TIME_VA.
+pub fn map_page(pt: &mut Pagetable) -> Result<(), isize> {
+    let pa = ka2pa(TIME_PAGE.load(Ordering::Acquire) as usize);
+    // The page is shared by all processes, and the reference taken at boot is never dropped.
+    pgref::dup(pa);
+    if !pt.map(Proc::TIME_VA, pa, PtLevel::Normal, perm::USER | perm::READ) {
+        pgref::put(pa);
+        return Err(err::NOMEM);
+    }
+    Ok(())
+}
+
+/// Blocks 'p' until the 'time' function reaches 'deadline'. Fails with INTR if a signal arrives
+/// first.
+pub fn sleep(p: &mut Proc, deadline: u64) -> Result<(), isize> {
+    // The timer of this core is programmed for the new deadline once the process has yielded and
+    // the scheduler runs the next process (or goes idle).
+    while time() < deadline {
+        p.sleep_until(&SLEEPERS, deadline)?;
+    }
+    Ok(())
+}
+
+/// Handles a timer interrupt on this core: wakes up the processes whose deadline has passed, and
+/// programs the next interrupt.
+pub fn intr() {
+    unsafe { cpu_noguard().clock.fired() };
+    let now = time();
+    let mut sleepers = SLEEPERS.lock();
+    while let Some(p) = sleepers.front() {
+        if unsafe { (*p).data.wakeup } > now {
+            break;
+        }
+        unsafe { sleepers.wake(p) };
+    }
+    drop(sleepers);
+    program();
+}
+
+/// Programs the timer of this core for the earliest deadline of a sleeping process, or the end of
+/// the running process's time slice if that is earlier. The timer of an idle core is stopped if no
+/// process is sleeping. Interrupts must be disabled.
+pub fn program() {
+    let cpu = unsafe { cpu_noguard() };
+    let sleeper = SLEEPERS.lock().front().map(|p| unsafe { (*p).data.wakeup });
+    let slice = unsafe { cpu.proc.as_ref() }.map(schedule::slice_end);
+    match sleeper.into_iter().chain(slice).min() {
+        Some(next) => cpu.clock.set(next),
+        None => cpu.clock.stop(),
+    }
+}
+
+/// Waits for an interrupt on this core, which has nothing to run. There are no periodic ticks
+/// while the core is idle: its timer is only programmed for the next sleeping process, and other
+/// cores interrupt it when they make a process runnable (see schedule::enqueue). Interrupts must
+/// be disabled, and the interrupt that ends the wait is handled once they are enabled.
+pub fn idle() {
+    program();
+    let start = time();
+    wfi();
+    let idle = &IDLE[unsafe { cpu_noguard().coreid }];
+    idle.time.fetch_add(time() - start, Ordering::Relaxed);
+    idle.count.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Prints the time each core has spent idle since boot.
+pub fn dump() {
+    let now = time();
+    println!(
+        "{:>4} {:>10} {:>5} {:>8}",
+        "CORE", "IDLE(ms)", "IDLE%", "WAKEUPS"
+    );
+    for (coreid, idle) in IDLE.iter().enumerate() {
+        let t = idle.time.load(Ordering::Relaxed);
+        println!(
+            "{:>4} {:>10} {:>5} {:>8}",
+            coreid,
+            t * 1000 / timer::freq(),
+            t * 100 / now,
+            idle.count.load(Ordering::Relaxed)
+        );
+    }
+}
@@ -1,25 +1,74 @@
-use crate::arch::timer;
+use crate::dev::irq;
 use crate::proc::Proc;
-use crate::schedule::TICKS_QUEUE;
+use crate::schedule;
+use crate::signal;
+use crate::timer;
 
 #[derive(PartialEq, Copy, Clone)]
 pub enum Irq {
     Timer,
+    External,
+    // Sent by another core that made a process runnable, to end the wait of an idle core.
+    Ipi,
+}
+
+/// Unrecoverable exceptions caused by a user process.
+#[derive(Copy, Clone)]
+pub enum Fault {
+    Page,
+    Illegal,
+    Misaligned,
+}
+
+impl Fault {
+    fn name(self) -> &'static str {
+        match self {
+            Fault::Page => "page fault",
+            Fault::Illegal => "illegal instruction",
+            Fault::Misaligned => "misaligned access",
+        }
+    }
+
+    // Signal number reported in the wait status of a process killed by this fault.
+    fn signal(self) -> i32 {
+        let sig = match self {
+            Fault::Page => signal::SIGSEGV,
+            Fault::Illegal => signal::SIGILL,
+            Fault::Misaligned => signal::SIGBUS,
+        };
+        sig as i32
+    }
 }
 
 pub fn irq_handler_kern(irq: Irq) {
     match irq {
         Irq::Timer => {
-            unsafe { TICKS_QUEUE.wake_all() };
-            timer::intr(timer::TIME_SLICE_US);
+            timer::intr();
         }
+        Irq::External => irq::dispatch(),
+        // The scheduler looks for a runnable process once the interrupt is handled.
+        Irq::Ipi => {}
     }
 }
 
 pub fn irq_handler_user(p: &mut Proc, irq: Irq) {
     irq_handler_kern(irq);
 
-    if irq == Irq::Timer {
+    // Preempt the process once its time slice has run out.
+    if irq == Irq::Timer && schedule::expired(p) {
         p.yield_();
     }
 }
+
+/// Kills the process after it caused an unrecoverable fault at 'epc'. The 'tval' is the faulting
+/// address (or instruction) reported by the hardware.
+pub fn fault_handler_user(p: &mut Proc, fault: Fault, epc: usize, tval: usize) -> ! {
+    println!(
+        "{}: killed: {} at epc: {:#x}, tval: {:#x}",
+        p.data.pid,
+        fault.name(),
+        epc,
+        tval
+    );
+    p.exit(fault.signal());
+}
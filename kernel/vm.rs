@@ -61,16 +61,31 @@ pub const fn pa2hka(pa: usize) -> usize {
     pa + sys::HIGHMEM_BASE
 }
 
-use crate::arch::vm::{Pagetable, PtLevel, Pte};
+#[inline]
+/// Rounds 'va' up to the nearest page boundary.
+pub const fn pgroundup(va: usize) -> usize {
+    (va + sys::PAGESIZE - 1) & !(sys::PAGESIZE - 1)
+}
+
+use crate::arch::vm::{vm_fence, Pagetable, PtLevel, Pte};
+use crate::err;
+use crate::kalloc::pgref::{self, kfree_pg};
+use crate::kalloc::{kallocpage, kfree};
 use crate::proc::Proc;
 use alloc::boxed::Box;
-use core::ptr::drop_in_place;
+use alloc::string::String;
+use alloc::vec::Vec;
 
 pub trait PageMap {
     #[must_use]
     /// Maps the given page at 'va' with permissions 'perm'. The pagetable takes ownership of the
     /// page (the page will be freed when the pagetable is freed).
     fn mappg(&mut self, va: usize, pg: Box<[u8; sys::PAGESIZE]>, perm: u8) -> Option<()>;
+
+    /// Unmaps the page at 'va' and drops the pagetable's reference to it, freeing the page if
+    /// no other pagetable maps it. Returns None if 'va' was not mapped. The caller is responsible
+    /// for flushing the TLB.
+    fn unmappg(&mut self, va: usize) -> Option<()>;
 }
 
 impl PageMap for Pagetable {
@@ -78,11 +93,164 @@ impl PageMap for Pagetable {
         let raw: *mut [u8; sys::PAGESIZE] = Box::into_raw(pg);
         if !self.map(va, ka2pa(raw as usize), PtLevel::Normal, perm) {
             // Free the box since we now own it and mapping failed.
-            unsafe { drop_in_place(raw) };
+            unsafe { drop(Box::from_raw(raw)) };
             return None;
         }
         Some(())
     }
+
+    fn unmappg(&mut self, va: usize) -> Option<()> {
+        let pa = self.unmap(va, PtLevel::Normal)?;
+        unsafe { kfree_pg(pa2ka(pa) as *mut u8) };
+        Some(())
+    }
+}
+
+/// Releases every user page mapped by 'pt'. Pages that are still shared with another pagetable
+/// are only freed once the last mapping goes away. The mappings are left in place, so 'pt' must
+/// not be used afterwards except to drop it.
+pub fn free_upages(pt: &mut Pagetable) {
+    for mut map in PtIter::new(pt) {
+        unsafe { kfree_pg(map.pg_raw()) };
+    }
+}
+
+/// Resolves a write fault on the copy-on-write page mapped at 'va'. If the page is still shared
+/// with another pagetable, it is copied into a freshly allocated page; otherwise the existing page
+/// is reclaimed by making it writable again. Returns false if 'va' is not a copy-on-write mapping
+/// or if the copy could not be allocated.
+pub fn cow_fault(pt: &mut Pagetable, va: usize) -> bool {
+    let pte = match pt.walk::<false>(va, PtLevel::Normal) {
+        Some((pte, PtLevel::Normal)) if pte.is_valid() => pte,
+        _ => {
+            return false;
+        }
+    };
+    let perm = pte.perm();
+    if perm & perm::USER == 0 || perm & perm::COW == 0 {
+        return false;
+    }
+
+    let pa = pte.pa();
+    if !pgref::unique(pa) {
+        let mut pg = match kallocpage() {
+            Err(_) => {
+                return false;
+            }
+            Ok(pg) => pg,
+        };
+        pg.copy_from_slice(unsafe {
+            core::slice::from_raw_parts(pa2ka(pa) as *const u8, sys::PAGESIZE)
+        });
+        pte.set_pa(ka2pa(Box::into_raw(pg) as usize));
+        // Another mapping may have dropped its reference since we checked, in which case this
+        // was the last reference and the old page must be freed.
+        if pgref::put(pa) {
+            unsafe { kfree(pa2ka(pa) as *mut [u8; sys::PAGESIZE]) };
+        }
+    }
+    pte.set_perm((perm & !perm::COW) | perm::WRITE);
+    vm_fence();
+    true
+}
+
+// Returns a kernel pointer to the start of the user page that contains 'va', if the page is mapped
+// with at least the permissions in 'perm'. Copy-on-write pages are made writable if write access
+// is requested.
+fn upage(pt: &mut Pagetable, va: usize, perm: u8) -> Option<*mut u8> {
+    if va >= Proc::MAX_VA {
+        return None;
+    }
+    let mut pte = match pt.walk::<false>(va, PtLevel::Normal) {
+        Some((pte, PtLevel::Normal)) if pte.is_valid() => *pte,
+        _ => {
+            return None;
+        }
+    };
+    let mut have = pte.perm();
+    if perm & perm::WRITE != 0 && have & perm::COW != 0 {
+        if !cow_fault(pt, va) {
+            return None;
+        }
+        pte = *pt.walk::<false>(va, PtLevel::Normal)?.0;
+        have = pte.perm();
+    }
+    if have & (perm | perm::USER) != perm | perm::USER {
+        return None;
+    }
+    Some(pa2ka(pte.pa()) as *mut u8)
+}
+
+// Calls 'f' for each page-sized chunk of the user range [va, va + len), passing a kernel pointer
+// to the chunk, the offset of the chunk within the range, and its length.
+fn urange(
+    pt: &mut Pagetable,
+    va: usize,
+    len: usize,
+    perm: u8,
+    mut f: impl FnMut(*mut u8, usize, usize),
+) -> Result<(), isize> {
+    if va.checked_add(len).is_none_or(|end| end > Proc::MAX_VA) {
+        return Err(err::FAULT);
+    }
+    let mut off = 0;
+    while off < len {
+        let pgoff = (va + off) % sys::PAGESIZE;
+        let n = core::cmp::min(sys::PAGESIZE - pgoff, len - off);
+        let pg = upage(pt, va + off, perm).ok_or(err::FAULT)?;
+        f(unsafe { pg.add(pgoff) }, off, n);
+        off += n;
+    }
+    Ok(())
+}
+
+/// Copies 'dst.len()' bytes from the user address 'src' in 'pt' into 'dst'. Returns err::FAULT if
+/// any page in the range is not mapped as user-readable.
+pub fn copyin(pt: &mut Pagetable, dst: &mut [u8], src: usize) -> Result<(), isize> {
+    urange(pt, src, dst.len(), perm::READ, |ka, off, n| unsafe {
+        core::ptr::copy_nonoverlapping(ka, dst[off..off + n].as_mut_ptr(), n);
+    })
+}
+
+/// Copies 'src' to the user address 'dst' in 'pt'. Returns err::FAULT if any page in the range is
+/// not mapped as user-writable.
+pub fn copyout(pt: &mut Pagetable, dst: usize, src: &[u8]) -> Result<(), isize> {
+    urange(pt, dst, src.len(), perm::WRITE, |ka, off, n| unsafe {
+        core::ptr::copy_nonoverlapping(src[off..off + n].as_ptr(), ka, n);
+    })
+}
+
+/// Checks that the user range [va, va + len) in 'pt' is mapped as user-writable, so that a later
+/// copyout to it succeeds. Returns err::FAULT otherwise.
+pub fn writable(pt: &mut Pagetable, va: usize, len: usize) -> Result<(), isize> {
+    urange(pt, va, len, perm::WRITE, |_, _, _| {})
+}
+
+/// Copies a NUL-terminated string from the user address 'src' in 'pt'. The string (excluding the
+/// terminator) may be at most 'max' bytes long, otherwise err::NAMETOOLONG is returned.
+pub fn copyinstr(pt: &mut Pagetable, src: usize, max: usize) -> Result<String, isize> {
+    let mut bytes = Vec::new();
+    let mut va = src;
+    loop {
+        let pgoff = va % sys::PAGESIZE;
+        let pg = upage(pt, va, perm::READ).ok_or(err::FAULT)?;
+        let chunk = unsafe { core::slice::from_raw_parts(pg.add(pgoff), sys::PAGESIZE - pgoff) };
+        match chunk.iter().position(|&b| b == 0) {
+            Some(end) => {
+                bytes.extend_from_slice(&chunk[..end]);
+                break;
+            }
+            None => bytes.extend_from_slice(chunk),
+        }
+        if bytes.len() > max {
+            return Err(err::NAMETOOLONG);
+        }
+        va += chunk.len();
+    }
+    if bytes.len() > max {
+        return Err(err::NAMETOOLONG);
+    }
+    String::from_utf8(bytes).map_err(|_| err::FAULT)
 }
 
 /// Information about a virtual address mapping, including the virtual address and a reference to
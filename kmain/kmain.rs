@@ -1,11 +1,14 @@
-use kernel::arch::timer;
 use kernel::arch::trap::irq;
+use kernel::console;
 use kernel::cpu::cpu;
+use kernel::dev;
+use kernel::fs::{self, initrd, vfs};
 use kernel::include_bytes_align_as;
 use kernel::kalloc::init_alloc;
 use kernel::println;
 use kernel::proc::Proc;
-use kernel::schedule::{scheduler, RUN_QUEUE};
+use kernel::schedule::{self, cfs::Cfs, scheduler};
+use kernel::timer;
 
 struct Foo {
     i: i64,
@@ -26,11 +29,20 @@ pub extern "C" fn kmain() {
     if cpu().primary {
         // TODO: allocate a full heap
         unsafe { init_alloc(heap_start(), 4096 * 4096) };
+        unsafe { initrd::init(include_bytes_align_as!(u64, "../user/initrd.tar")) };
+        schedule::set_policy(Cfs::new());
+        timer::init();
+        console::init();
+        fs::init();
+        dev::virtio::probe();
+        fs::mount_disks();
         use kernel::arch::fwi::wake_cores;
         wake_cores();
     }
 
     irq::init();
+    kernel::arch::timer::init_hart();
+    dev::irq::init_hart();
 
     println!(
         "core: {}, entered kmain at: {:?}",
@@ -38,17 +50,15 @@ pub extern "C" fn kmain() {
         &kmain as *const _
     );
 
-    if !cpu().primary {
-        return;
+    if cpu().primary {
+        // The first process is init, which starts the other programs.
+        let init = vfs::read_file(None, "/bin/init").expect("could not read /bin/init");
+        let proc1 = Proc::new_from_elf(&init, &[b"/bin/init"]).unwrap();
+        schedule::enqueue(proc1);
     }
 
-    let hello = include_bytes_align_as!(u64, "../user/hello/hello.elf");
-    let proc1 = Proc::new_from_elf(hello).unwrap();
-    RUN_QUEUE.lock().push_front(proc1);
-
+    // Every core runs its own scheduler, sharing the global run queue.
     unsafe { irq::on() };
 
-    timer::intr(timer::TIME_SLICE_US);
-
     scheduler();
 }
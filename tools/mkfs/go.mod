@@ -0,0 +1,3 @@
+module mkfs
+
+go 1.19
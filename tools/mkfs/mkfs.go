@@ -0,0 +1,257 @@
+// Command mkfs creates a disk image containing an xv6-style filesystem (the format used by the
+// kernel's fs::xv6 module), populated with the contents of a host directory.
+package main
+
+import (
+	"encoding/binary"
+	"flag"
+	"log"
+	"os"
+	"path/filepath"
+	"sort"
+)
+
+const (
+	bsize      = 1024
+	fsmagic    = 0x10203040
+	ndirect    = 12
+	nindirect  = bsize / 4
+	maxfile    = ndirect + nindirect
+	dinodeSize = 64
+	ipb        = bsize / dinodeSize
+	bpb        = bsize * 8
+	dirsiz     = 14
+	direntSize = 2 + dirsiz
+	rootino    = 1
+	logsize    = 30
+
+	tDir  = 1
+	tFile = 2
+)
+
+type dinode struct {
+	typ   uint16
+	major uint16
+	minor uint16
+	nlink uint16
+	size  uint32
+	addrs [ndirect + 1]uint32
+}
+
+type superblock struct {
+	size       uint32
+	nblocks    uint32
+	ninodes    uint32
+	nlog       uint32
+	logstart   uint32
+	inodestart uint32
+	bmapstart  uint32
+}
+
+type fs struct {
+	img       []byte
+	sb        superblock
+	freeinode uint32
+	freeblock uint32
+}
+
+var le = binary.LittleEndian
+
+func (f *fs) block(b uint32) []byte {
+	if b >= f.sb.size {
+		log.Fatalf("block %d out of range", b)
+	}
+	return f.img[b*bsize : (b+1)*bsize]
+}
+
+func (f *fs) inode(inum uint32) []byte {
+	b := f.block(f.sb.inodestart + inum/ipb)
+	off := (inum % ipb) * dinodeSize
+	return b[off : off+dinodeSize]
+}
+
+func (f *fs) rinode(inum uint32) dinode {
+	b := f.inode(inum)
+	var din dinode
+	din.typ = le.Uint16(b[0:])
+	din.major = le.Uint16(b[2:])
+	din.minor = le.Uint16(b[4:])
+	din.nlink = le.Uint16(b[6:])
+	din.size = le.Uint32(b[8:])
+	for i := range din.addrs {
+		din.addrs[i] = le.Uint32(b[12+4*i:])
+	}
+	return din
+}
+
+func (f *fs) winode(inum uint32, din *dinode) {
+	b := f.inode(inum)
+	le.PutUint16(b[0:], din.typ)
+	le.PutUint16(b[2:], din.major)
+	le.PutUint16(b[4:], din.minor)
+	le.PutUint16(b[6:], din.nlink)
+	le.PutUint32(b[8:], din.size)
+	for i, a := range din.addrs {
+		le.PutUint32(b[12+4*i:], a)
+	}
+}
+
+func (f *fs) ialloc(typ uint16) uint32 {
+	if f.freeinode >= f.sb.ninodes {
+		log.Fatal("out of inodes")
+	}
+	inum := f.freeinode
+	f.freeinode++
+	din := dinode{typ: typ, nlink: 1}
+	f.winode(inum, &din)
+	return inum
+}
+
+func (f *fs) balloc() uint32 {
+	if f.freeblock >= f.sb.size {
+		log.Fatal("out of blocks")
+	}
+	b := f.freeblock
+	f.freeblock++
+	return b
+}
+
+// Appends data to the end of inode inum.
+func (f *fs) iappend(inum uint32, data []byte) {
+	din := f.rinode(inum)
+	off := din.size
+	for len(data) > 0 {
+		fbn := off / bsize
+		if fbn >= maxfile {
+			log.Fatal("file too large")
+		}
+		var b uint32
+		if fbn < ndirect {
+			if din.addrs[fbn] == 0 {
+				din.addrs[fbn] = f.balloc()
+			}
+			b = din.addrs[fbn]
+		} else {
+			if din.addrs[ndirect] == 0 {
+				din.addrs[ndirect] = f.balloc()
+			}
+			ind := f.block(din.addrs[ndirect])
+			slot := ind[4*(fbn-ndirect):]
+			if le.Uint32(slot) == 0 {
+				le.PutUint32(slot, f.balloc())
+			}
+			b = le.Uint32(slot)
+		}
+		n := copy(f.block(b)[off%bsize:], data)
+		data = data[n:]
+		off += uint32(n)
+	}
+	din.size = off
+	f.winode(inum, &din)
+}
+
+func (f *fs) dirlink(dir uint32, name string, inum uint32) {
+	if len(name) > dirsiz {
+		log.Fatalf("name too long: %s", name)
+	}
+	de := make([]byte, direntSize)
+	le.PutUint16(de, uint16(inum))
+	copy(de[2:], name)
+	f.iappend(dir, de)
+}
+
+// Creates a directory inside parent (which may be itself, for the root).
+func (f *fs) mkdir(parent uint32) uint32 {
+	inum := f.ialloc(tDir)
+	f.dirlink(inum, ".", inum)
+	f.dirlink(inum, "..", parent)
+	if parent != inum {
+		din := f.rinode(parent)
+		din.nlink++
+		f.winode(parent, &din)
+	}
+	return inum
+}
+
+// Copies the contents of the host directory path into dir.
+func (f *fs) add(dir uint32, path string) {
+	entries, err := os.ReadDir(path)
+	if err != nil {
+		log.Fatal(err)
+	}
+	sort.Slice(entries, func(i, j int) bool {
+		return entries[i].Name() < entries[j].Name()
+	})
+	for _, e := range entries {
+		p := filepath.Join(path, e.Name())
+		switch {
+		case e.IsDir():
+			sub := f.mkdir(dir)
+			f.dirlink(dir, e.Name(), sub)
+			f.add(sub, p)
+		case e.Type().IsRegular():
+			data, err := os.ReadFile(p)
+			if err != nil {
+				log.Fatal(err)
+			}
+			inum := f.ialloc(tFile)
+			f.dirlink(dir, e.Name(), inum)
+			f.iappend(inum, data)
+		default:
+			log.Printf("skipping %s: not a regular file or directory", p)
+		}
+	}
+}
+
+func main() {
+	out := flag.String("o", "fs.img", "output file")
+	size := flag.Uint("size", 32768, "size of the filesystem in blocks")
+	ninodes := flag.Uint("ninodes", 1024, "number of inodes")
+	flag.Parse()
+
+	nbitmap := uint32(*size)/bpb + 1
+	ninodeblocks := uint32(*ninodes)/ipb + 1
+	nmeta := 2 + logsize + ninodeblocks + nbitmap
+	if uint32(*size) <= nmeta {
+		log.Fatal("filesystem too small")
+	}
+
+	f := &fs{
+		img: make([]byte, *size*bsize),
+		sb: superblock{
+			size:       uint32(*size),
+			nblocks:    uint32(*size) - nmeta,
+			ninodes:    uint32(*ninodes),
+			nlog:       logsize,
+			logstart:   2,
+			inodestart: 2 + logsize,
+			bmapstart:  2 + logsize + ninodeblocks,
+		},
+		// Inode 0 is never used.
+		freeinode: rootino,
+		freeblock: nmeta,
+	}
+
+	sb := f.block(1)
+	for i, v := range []uint32{fsmagic, f.sb.size, f.sb.nblocks, f.sb.ninodes, f.sb.nlog, f.sb.logstart, f.sb.inodestart, f.sb.bmapstart} {
+		le.PutUint32(sb[4*i:], v)
+	}
+
+	root := f.mkdir(rootino)
+	if root != rootino {
+		log.Fatal("unexpected root inode")
+	}
+	for _, dir := range flag.Args() {
+		f.add(root, dir)
+	}
+
+	// Mark all blocks in use so far as allocated.
+	for b := uint32(0); b < f.freeblock; b++ {
+		bm := f.block(f.sb.bmapstart + b/bpb)
+		bm[(b%bpb)/8] |= 1 << (b % 8)
+	}
+
+	if err := os.WriteFile(*out, f.img, 0o644); err != nil {
+		log.Fatal(err)
+	}
+}
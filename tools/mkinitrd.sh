@@ -0,0 +1,25 @@
+#!/bin/sh
+
+# USAGE: mkinitrd.sh <out.tar> <rootdir> <prog.elf>...
+#
+# Creates a ustar archive for the initial RAM filesystem. The archive contains
+# the files in <rootdir>, and each program is installed into /bin with its .elf
+# extension removed.
+
+set -e
+
+out=$1
+root=$2
+shift 2
+
+tmp=$(mktemp -d)
+chmod 755 "$tmp"
+trap 'rm -rf "$tmp"' EXIT
+
+cp -R "$root"/. "$tmp"
+mkdir -p "$tmp/bin"
+for prog in "$@"; do
+    cp "$prog" "$tmp/bin/$(basename "$prog" .elf)"
+done
+
+tar --format=ustar --owner=0 --group=0 -cf "$out" -C "$tmp" .
@@ -0,0 +1,42 @@
+#include <errno.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+#include <sys/wait.h>
+
+#include "syslib.h"
+
+// The first process. It starts the program below, and then waits for its children, which include
+// the processes whose parent exited before them.
+
+static char* const argv[] = { "/bin/hello", NULL };
+
+int main() {
+    int pid = fork();
+    if (pid < 0) {
+        perror("init: fork");
+    } else if (pid == 0) {
+        execve(argv[0], argv, NULL);
+        perror("init: exec");
+        exit(1);
+    }
+
+    while (1) {
+        int status;
+        int child = wait(&status);
+        if (child < 0) {
+            if (errno == ECHILD)
+                break;
+            continue;
+        }
+        if (WIFEXITED(status) && WEXITSTATUS(status) != 0)
+            printf("init: %d exited with status %d\n", child, WEXITSTATUS(status));
+        else if (WIFSIGNALED(status))
+            printf("init: %d killed by signal %d\n", child, WTERMSIG(status));
+    }
+
+    // Init must not exit.
+    printf("init: no processes left\n");
+    while (1)
+        usleep(1000 * 1000);
+}
@@ -70,4 +70,27 @@ enum {
     SYS_SBRK   = 5,
     SYS_USLEEP = 6,
     SYS_READ   = 7,
+    SYS_EXEC   = 8,
+    SYS_OPEN   = 9,
+    SYS_CLOSE  = 10,
+    SYS_DUP    = 11,
+    SYS_DUP2   = 12,
+    SYS_STAT   = 13,
+    SYS_MKDIR  = 14,
+    SYS_UNLINK = 15,
+    SYS_RENAME = 16,
+    SYS_GETDENTS = 17,
+    SYS_CHDIR  = 18,
+    SYS_PIPE   = 19,
+    SYS_KILL   = 20,
+    SYS_SIGACTION = 21,
+    SYS_SIGRETURN = 22,
+    SYS_SIGPROCMASK = 23,
+    SYS_SETPRIORITY = 24,
+    SYS_GETPRIORITY = 25,
+    SYS_CLOCK_GETTIME = 26,
+    SYS_NANOSLEEP = 27,
 };
+
+// Flag for SYS_UNLINK to remove a directory.
+#define AT_REMOVEDIR 0x200
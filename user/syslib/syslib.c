@@ -1,9 +1,15 @@
+#include <errno.h>
+#include <signal.h>
 #include <stddef.h>
 #include <stdint.h>
+#include <string.h>
 #include <sys/stat.h>
+#include <sys/time.h>
 #include <sys/times.h>
+#include <time.h>
 
 #include "syscall.h"
+#include "syslib.h"
 
 char *__env[1] = { 0 };
 char **_environ = __env;
@@ -11,21 +17,173 @@ char **_environ = __env;
 extern void exit(int code);
 extern int main ();
 
-void _start() {
-    int ex = main();
+// Converts a system call return value into the C convention of returning -1 and setting errno.
+static int sysret(intptr_t r) {
+    if (r < 0) {
+        errno = -r;
+        return -1;
+    }
+    return r;
+}
+
+// The kernel enters with a0 = argc, a1 = argv and a2 = envp.
+void _start(int argc, char **argv, char **envp) {
+    if (envp)
+        _environ = envp;
+    int ex = main(argc, argv, _environ);
     exit(ex);
 }
 
 int _execve(char *name, char **argv, char **env) {
-    return -1;
+    return sysret(syscall_2(SYS_EXEC, (uintptr_t) name, (uintptr_t) argv));
 }
 
 void* _sbrk(int incr) {
-    return (void*) syscall_1(SYS_SBRK, incr);
+    intptr_t brk = syscall_1(SYS_SBRK, incr);
+    if (brk < 0) {
+        errno = -brk;
+        return (void*) -1;
+    }
+    return (void*) brk;
+}
+
+int _open(const char* name, int flags, int mode) {
+    return sysret(syscall_2(SYS_OPEN, (uintptr_t) name, flags));
 }
 
 int _close(int file) {
-    return -1;
+    return sysret(syscall_1(SYS_CLOSE, file));
+}
+
+int dup(int file) {
+    return sysret(syscall_1(SYS_DUP, file));
+}
+
+int dup2(int file, int newfile) {
+    return sysret(syscall_2(SYS_DUP2, file, newfile));
+}
+
+// Metadata written by SYS_STAT.
+struct kstat {
+    uint64_t ino;
+    uint64_t size;
+    uint32_t mode;
+    uint32_t nlink;
+};
+
+int _stat(const char* path, struct stat* st) {
+    struct kstat kst;
+    int r = sysret(syscall_2(SYS_STAT, (uintptr_t) path, (uintptr_t) &kst));
+    if (r < 0)
+        return r;
+    memset(st, 0, sizeof(*st));
+    st->st_ino = kst.ino;
+    st->st_size = kst.size;
+    st->st_mode = kst.mode;
+    st->st_nlink = kst.nlink;
+    return 0;
+}
+
+int mkdir(const char* path, mode_t mode) {
+    return sysret(syscall_1(SYS_MKDIR, (uintptr_t) path));
+}
+
+int rmdir(const char* path) {
+    return sysret(syscall_2(SYS_UNLINK, (uintptr_t) path, AT_REMOVEDIR));
+}
+
+int rename(const char* old, const char* new) {
+    return sysret(syscall_2(SYS_RENAME, (uintptr_t) old, (uintptr_t) new));
+}
+
+int chdir(const char* path) {
+    return sysret(syscall_1(SYS_CHDIR, (uintptr_t) path));
+}
+
+int getdents(int fd, void* buf, size_t len) {
+    return sysret(syscall_3(SYS_GETDENTS, fd, (uintptr_t) buf, len));
+}
+
+int pipe(int fds[2]) {
+    return sysret(syscall_1(SYS_PIPE, (uintptr_t) fds));
+}
+
+// Signal action passed to SYS_SIGACTION.
+struct ksigaction {
+    uintptr_t handler;
+    uint64_t mask;
+    uintptr_t restorer;
+};
+
+// Signal handlers return into __sigreturn, which restores the state they interrupted.
+void __sigreturn(void);
+_Static_assert(SYS_SIGRETURN == 22, "__sigreturn hardcodes SYS_SIGRETURN");
+#ifdef RISCV64
+asm(".text\n"
+    ".global __sigreturn\n"
+    "__sigreturn:\n"
+    "    li a7, 22\n"
+    "    ecall\n");
+#else
+asm(".text\n"
+    ".global __sigreturn\n"
+    "__sigreturn:\n"
+    "    mov x7, #22\n"
+    "    svc #0\n");
+#endif
+
+int sigaction(int sig, const struct sigaction* act, struct sigaction* oldact) {
+    struct ksigaction kact, kold;
+    if (act) {
+        kact.handler = (uintptr_t) act->sa_handler;
+        kact.mask = act->sa_mask;
+        kact.restorer = (uintptr_t) __sigreturn;
+    }
+    int r = sysret(syscall_3(SYS_SIGACTION, sig, act ? (uintptr_t) &kact : 0,
+                oldact ? (uintptr_t) &kold : 0));
+    if (r < 0)
+        return r;
+    if (oldact) {
+        memset(oldact, 0, sizeof(*oldact));
+        oldact->sa_handler = (void (*)(int)) kold.handler;
+        oldact->sa_mask = kold.mask;
+    }
+    return 0;
+}
+
+int sigprocmask(int how, const sigset_t* set, sigset_t* oldset) {
+    uint64_t kset, kold;
+    if (set)
+        kset = *set;
+    int r = sysret(syscall_3(SYS_SIGPROCMASK, how, set ? (uintptr_t) &kset : 0,
+                oldset ? (uintptr_t) &kold : 0));
+    if (r < 0)
+        return r;
+    if (oldset)
+        *oldset = kold;
+    return 0;
+}
+
+int getpriority(int which, int who) {
+    // The kernel returns 20 minus the nice value, so that it is never negative.
+    int r = sysret(syscall_2(SYS_GETPRIORITY, which, who));
+    if (r < 0)
+        return r;
+    return 20 - r;
+}
+
+int setpriority(int which, int who, int prio) {
+    return sysret(syscall_3(SYS_SETPRIORITY, which, who, prio));
+}
+
+int nice(int incr) {
+    errno = 0;
+    int prio = getpriority(PRIO_PROCESS, 0);
+    if (prio == -1 && errno != 0)
+        return -1;
+    if (setpriority(PRIO_PROCESS, 0, prio + incr) < 0)
+        return -1;
+    return getpriority(PRIO_PROCESS, 0);
 }
 
 int _fstat(int file, struct stat* st) {
@@ -42,12 +200,12 @@ int _lseek(int file, int ptr, int dir) {
 }
 
 void _exit(int status) {
-    syscall_0(SYS_EXIT);
+    syscall_1(SYS_EXIT, status);
     while (1) {}
 }
 
-void _kill(int pid, int sig) {
-    return;
+int _kill(int pid, int sig) {
+    return sysret(syscall_2(SYS_KILL, pid, sig));
 }
 
 int _getpid(void) {
@@ -55,29 +213,87 @@ int _getpid(void) {
 }
 
 int _write(int file, char* ptr, int len) {
-    return syscall_3(SYS_WRITE, file, (uintptr_t) ptr, len);
+    return sysret(syscall_3(SYS_WRITE, file, (uintptr_t) ptr, len));
 }
 
 int _read(int file, char* ptr, int len) {
-    return syscall_3(SYS_READ, file, (uintptr_t) ptr, len);
+    return sysret(syscall_3(SYS_READ, file, (uintptr_t) ptr, len));
 }
 
 int _fork(void) {
     return syscall_0(SYS_FORK);
 }
 
-int _wait() {
-    return syscall_0(SYS_WAIT);
+int _wait(int* status) {
+    return sysret(syscall_3(SYS_WAIT, -1, (uintptr_t) status, 0));
+}
+
+pid_t waitpid(pid_t pid, int* status, int options) {
+    return sysret(syscall_3(SYS_WAIT, pid, (uintptr_t) status, options));
 }
 
 int _unlink(char* name) {
-    return -1;
+    return sysret(syscall_2(SYS_UNLINK, (uintptr_t) name, 0));
+}
+
+// Converts a counter value read by rdtime into nanoseconds.
+static uint64_t time_ns(uint64_t t, uint64_t freq) {
+    // Split the conversion to avoid overflowing 64 bits.
+    return t / freq * 1000000000 + t % freq * 1000000000 / freq;
+}
+
+int clock_gettime(clockid_t clock, struct timespec* tp) {
+    // The real-time and monotonic clocks are computed from the time page like in the kernel, and
+    // only the CPU time needs a system call.
+    const volatile struct timepage* tpg = timepage();
+    uint64_t ns;
+    switch (clock) {
+    case CLOCK_REALTIME:
+        ns = tpg->realtime + time_ns(rdtime(), tpg->freq);
+        break;
+    case CLOCK_MONOTONIC:
+        ns = time_ns(rdtime() - tpg->boot, tpg->freq);
+        break;
+    default:
+        return sysret(syscall_2(SYS_CLOCK_GETTIME, clock, (uintptr_t) tp));
+    }
+    tp->tv_sec = ns / 1000000000;
+    tp->tv_nsec = ns % 1000000000;
+    return 0;
+}
+
+int nanosleep(const struct timespec* req, struct timespec* rem) {
+    return sysret(syscall_2(SYS_NANOSLEEP, (uintptr_t) req, (uintptr_t) rem));
+}
+
+int _gettimeofday(struct timeval* tv, void* tz) {
+    struct timespec ts;
+    if (clock_gettime(CLOCK_REALTIME, &ts) < 0)
+        return -1;
+    tv->tv_sec = ts.tv_sec;
+    tv->tv_usec = ts.tv_nsec / 1000;
+    return 0;
+}
+
+// Converts a time into clock ticks for times.
+static clock_t ticks(const struct timespec* ts) {
+    return ts->tv_sec * CLOCKS_PER_SEC + ts->tv_nsec / (1000000000 / CLOCKS_PER_SEC);
 }
 
-int _times(struct tms* buf) {
-    return -1;
+clock_t _times(struct tms* buf) {
+    struct timespec cpu, now;
+    if (clock_gettime(CLOCK_PROCESS_CPUTIME_ID, &cpu) < 0)
+        return -1;
+    if (clock_gettime(CLOCK_MONOTONIC, &now) < 0)
+        return -1;
+    // The kernel does not distinguish system time, nor account for children.
+    buf->tms_utime = ticks(&cpu);
+    buf->tms_stime = 0;
+    buf->tms_cutime = 0;
+    buf->tms_cstime = 0;
+    return ticks(&now);
 }
 
 int usleep(uint64_t us) {
-    return syscall_1(SYS_USLEEP, us);
+    return sysret(syscall_1(SYS_USLEEP, us));
 }
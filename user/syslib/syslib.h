@@ -1,5 +1,73 @@
 #pragma once
 
+#include <stddef.h>
 #include <stdint.h>
+#include <time.h>
 
 int usleep(uint64_t us);
+
+// Clocks for clock_gettime, which newlib only defines for some targets.
+#ifndef CLOCK_REALTIME
+#define CLOCK_REALTIME 1
+#endif
+#ifndef CLOCK_PROCESS_CPUTIME_ID
+#define CLOCK_PROCESS_CPUTIME_ID 2
+#endif
+#ifndef CLOCK_MONOTONIC
+#define CLOCK_MONOTONIC 4
+#endif
+
+// Clock data that the kernel maps read-only into every process at TIME_VA, just above the stack.
+struct timepage {
+    // Frequency of the counter read by rdtime, in ticks per second.
+    uint64_t freq;
+    // Value of the counter at boot, from which CLOCK_MONOTONIC counts.
+    uint64_t boot;
+    // Real time at which the counter was 0, in nanoseconds since the Unix epoch.
+    uint64_t realtime;
+};
+
+#define TIME_VA 0x7fff1000
+
+static inline const volatile struct timepage* timepage(void) {
+    return (const volatile struct timepage*) TIME_VA;
+}
+
+// Reads the counter behind the kernel's clocks, without a system call (on cores that do not
+// implement the 'time' CSR, the kernel emulates the instruction).
+static inline uint64_t rdtime(void) {
+    uint64_t t;
+#ifdef RISCV64
+    asm volatile("rdtime %0" : "=r"(t));
+#else
+    asm volatile("mrs %0, cntvct_el0" : "=r"(t));
+#endif
+    return t;
+}
+
+int clock_gettime(clockid_t clock, struct timespec* tp);
+int nanosleep(const struct timespec* req, struct timespec* rem);
+
+// Directory entry returned by getdents, in the layout of Linux's getdents64.
+struct dirent64 {
+    uint64_t d_ino;
+    int64_t d_off;
+    uint16_t d_reclen;
+    uint8_t d_type;
+    char d_name[];
+};
+
+#define DT_CHR 2
+#define DT_DIR 4
+#define DT_REG 8
+
+#ifndef PRIO_PROCESS
+#define PRIO_PROCESS 0
+#endif
+
+int getpriority(int which, int who);
+int setpriority(int which, int who, int prio);
+
+int getdents(int fd, void* buf, size_t len);
+int chdir(const char* path);
+int rmdir(const char* path);